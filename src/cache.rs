@@ -0,0 +1,277 @@
+//! On-disk index reused across runs (`search --index-dir`).
+//!
+//! The tantivy files live in a subdirectory of their own, so rebuilding
+//! never touches anything else in the directory given; a subdirectory
+//! holding anything but tantivy files is never cleared. Next to it, a
+//! manifest records the settings the index was built with and, for every
+//! source file, its modification time and a hash of its records. A file is
+//! re-indexed when either differs from the last run; files no longer in the
+//! input are dropped from the index.
+
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::time::UNIX_EPOCH;
+
+use crate::engine::{Engine, Extract, IndexOptions, Record};
+
+/// Manifest file name inside the index directory.
+const MANIFEST: &str = "jsonai-files.json";
+
+/// Subdirectory holding the tantivy index, which we may delete and rebuild.
+const INDEX_SUBDIR: &str = "index";
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Manifest {
+    /// Index and extraction settings; any change rebuilds the index
+    settings: String,
+    files: BTreeMap<String, FileState>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct FileState {
+    /// Modification time in nanoseconds since the epoch, when known
+    mtime: Option<u64>,
+    /// SHA-256 of the file's records, pointers included
+    hash: String,
+}
+
+/// Open the index in `dir` and bring it up to date with `records`.
+pub fn open(
+    dir: &Path,
+    options: &IndexOptions,
+    extract: Extract,
+    records: &[Record],
+) -> Result<Engine> {
+    let settings = format!("{:?} {:?}", options, extract);
+    let manifest_path = dir.join(MANIFEST);
+    let index_dir = dir.join(INDEX_SUBDIR);
+    let previous = read_manifest(&manifest_path).filter(|m| m.settings == settings);
+
+    // Without a manifest for these settings, whatever is indexed has an
+    // incompatible schema or unknown records, so start over -- but only
+    // when the subdirectory is ours to delete.
+    if previous.is_none() && index_dir.exists() {
+        if !holds_only_index_files(&index_dir)? {
+            bail!(
+                "{} holds files jsonai didn't create; pick another --index-dir",
+                index_dir.display()
+            );
+        }
+        std::fs::remove_dir_all(&index_dir)
+            .with_context(|| format!("Failed to clear index directory {}", index_dir.display()))?;
+    }
+    let mut previous = previous.map(|m| m.files).unwrap_or_default();
+
+    let engine = Engine::open_in_dir(&index_dir, options)?;
+
+    let mut by_file: BTreeMap<&str, Vec<&Record>> = BTreeMap::new();
+    for record in records {
+        by_file.entry(record.file.as_str()).or_default().push(record);
+    }
+
+    let mut files = BTreeMap::new();
+    let mut stale: Vec<String> = Vec::new();
+    let mut changed: Vec<Record> = Vec::new();
+    for (file, file_records) in &by_file {
+        // The records are loaded anyway, so the hash is always compared:
+        // a rewrite within the mtime granularity is still caught.
+        let state = FileState {
+            mtime: modified_nanos(file),
+            hash: records_hash(file_records),
+        };
+        if previous.remove(*file).as_ref() != Some(&state) {
+            stale.push(file.to_string());
+            changed.extend(file_records.iter().map(|r| (*r).clone()));
+        }
+        files.insert(file.to_string(), state);
+    }
+    stale.extend(previous.into_keys());
+
+    if !stale.is_empty() {
+        engine.replace_files(&changed, &stale)?;
+    }
+
+    let manifest = Manifest { settings, files };
+    std::fs::write(&manifest_path, serde_json::to_string(&manifest)?)
+        .with_context(|| format!("Failed to write {}", manifest_path.display()))?;
+
+    Ok(engine)
+}
+
+/// Whether `path` belongs to an index directory: its tantivy subdirectory or
+/// its manifest. Input discovery skips these, so an index kept among the
+/// data isn't searched as data.
+pub fn is_cache_path(path: &Path) -> bool {
+    let Some(parent) = path.parent() else {
+        return false;
+    };
+    match path.file_name().and_then(|name| name.to_str()) {
+        Some(INDEX_SUBDIR) => path.is_dir() && parent.join(MANIFEST).is_file(),
+        Some(MANIFEST) => parent.join(INDEX_SUBDIR).is_dir(),
+        _ => false,
+    }
+}
+
+/// Whether every entry of `dir` is a file tantivy writes: its meta and lock
+/// files, or a segment file named by a hex segment id.
+fn holds_only_index_files(dir: &Path) -> Result<bool> {
+    let entries = std::fs::read_dir(dir).with_context(|| format!("Failed to read {}", dir.display()))?;
+    for entry in entries {
+        let entry = entry.with_context(|| format!("Failed to read {}", dir.display()))?;
+        let name = entry.file_name();
+        let name = name.to_string_lossy();
+        let segment_file = name
+            .split_once('.')
+            .is_some_and(|(id, _)| id.len() == 32 && id.chars().all(|c| c.is_ascii_hexdigit()));
+        let index_file = name == "meta.json" || name == ".managed.json" || name.starts_with(".tantivy-");
+        if !entry.file_type()?.is_file() || !(segment_file || index_file) {
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
+
+fn read_manifest(path: &Path) -> Option<Manifest> {
+    let content = std::fs::read_to_string(path).ok()?;
+    serde_json::from_str(&content).ok()
+}
+
+fn modified_nanos(file: &str) -> Option<u64> {
+    let modified = std::fs::metadata(file).ok()?.modified().ok()?;
+    let nanos = modified.duration_since(UNIX_EPOCH).ok()?.as_nanos();
+    u64::try_from(nanos).ok()
+}
+
+fn records_hash(records: &[&Record]) -> String {
+    let mut hasher = Sha256::new();
+    for record in records {
+        hasher.update(record.pointer.as_bytes());
+        hasher.update([0]);
+        hasher.update(serde_json::to_string(&record.value).unwrap_or_default());
+        hasher.update([b'\n']);
+    }
+    hasher.finalize().iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::{extract_records, SearchOptions};
+    use serde_json::json;
+
+    fn records(dir: &Path, name: &str, value: serde_json::Value) -> Vec<Record> {
+        let path = dir.join(name);
+        std::fs::write(&path, value.to_string()).unwrap();
+        extract_records(&value, path.to_str().unwrap())
+    }
+
+    fn hits(engine: &Engine, query: &str) -> usize {
+        engine
+            .search(query, &[], &SearchOptions::default(), 100, 0)
+            .unwrap()
+            .len()
+    }
+
+    #[test]
+    fn reuses_unchanged_files_and_drops_deleted_ones() {
+        let data = tempfile::tempdir().unwrap();
+        let index = data.path().join("index");
+        let a = records(data.path(), "a.json", json!([{ "name": "alice" }]));
+        let b = records(data.path(), "b.json", json!([{ "name": "bob" }]));
+        let all: Vec<Record> = a.iter().chain(&b).cloned().collect();
+        let options = IndexOptions::default();
+
+        let engine = open(&index, &options, Extract::Tree, &all).unwrap();
+        assert_eq!(hits(&engine, "alice"), 1);
+        drop(engine);
+
+        // Same mtime but different records: the hash still catches it.
+        let mut renamed = all.clone();
+        renamed[0].value = json!({ "name": "zed" });
+        let engine = open(&index, &options, Extract::Tree, &renamed).unwrap();
+        assert_eq!(hits(&engine, "alice"), 0);
+        assert_eq!(hits(&engine, "zed"), 1);
+        assert_eq!(hits(&engine, "bob"), 1);
+        drop(engine);
+
+        let engine = open(&index, &options, Extract::Tree, &a).unwrap();
+        assert_eq!(hits(&engine, "alice"), 1);
+        assert_eq!(hits(&engine, "bob"), 0);
+    }
+
+    #[test]
+    fn rebuilds_only_its_own_subdirectory() {
+        let data = tempfile::tempdir().unwrap();
+        let index = data.path().join("index");
+        let a = records(data.path(), "a.json", json!([{ "name": "alice" }]));
+        drop(open(&index, &IndexOptions::default(), Extract::Tree, &a).unwrap());
+
+        // Without the manifest the indexed records are unknown; reusing
+        // them would index everything twice.
+        std::fs::remove_file(index.join(MANIFEST)).unwrap();
+        std::fs::write(index.join("notes.txt"), "keep me").unwrap();
+        let engine = open(&index, &IndexOptions::default(), Extract::Tree, &a).unwrap();
+        assert_eq!(hits(&engine, "alice"), 1);
+        assert!(index.join("notes.txt").exists());
+    }
+
+    #[test]
+    fn refuses_to_clear_a_subdirectory_it_did_not_create() {
+        let data = tempfile::tempdir().unwrap();
+        std::fs::create_dir(data.path().join(INDEX_SUBDIR)).unwrap();
+        let user_file = data.path().join(INDEX_SUBDIR).join("data.json");
+        std::fs::write(&user_file, "[]").unwrap();
+        let a = records(data.path(), "a.json", json!([{ "name": "alice" }]));
+
+        let err = open(data.path(), &IndexOptions::default(), Extract::Tree, &a).err().unwrap();
+        assert!(err.to_string().contains("didn't create"), "{}", err);
+        assert!(user_file.exists());
+        assert!(!is_cache_path(&data.path().join(INDEX_SUBDIR)));
+
+        // Once an index of ours is there, it is told apart from the data.
+        let index = data.path().join("cache");
+        drop(open(&index, &IndexOptions::default(), Extract::Tree, &a).unwrap());
+        assert!(is_cache_path(&index.join(INDEX_SUBDIR)));
+        assert!(is_cache_path(&index.join(MANIFEST)));
+    }
+
+    #[test]
+    fn reindexes_changed_file_without_duplicates() {
+        let data = tempfile::tempdir().unwrap();
+        let index = data.path().join("index");
+        let options = IndexOptions::default();
+
+        let before = records(data.path(), "a.json", json!([{ "name": "alice" }]));
+        drop(open(&index, &options, Extract::Tree, &before).unwrap());
+
+        let after = records(data.path(), "a.json", json!([{ "name": "alice" }, { "name": "carol" }]));
+        let later = std::time::SystemTime::now() + std::time::Duration::from_secs(60);
+        std::fs::File::options()
+            .write(true)
+            .open(data.path().join("a.json"))
+            .unwrap()
+            .set_modified(later)
+            .unwrap();
+        let engine = open(&index, &options, Extract::Tree, &after).unwrap();
+        assert_eq!(hits(&engine, "alice"), 1);
+        assert_eq!(hits(&engine, "carol"), 1);
+    }
+
+    #[test]
+    fn rebuilds_when_settings_change() {
+        let data = tempfile::tempdir().unwrap();
+        let index = data.path().join("index");
+        let a = records(data.path(), "a.json", json!([{ "name": "alice" }]));
+        drop(open(&index, &IndexOptions::default(), Extract::Tree, &a).unwrap());
+
+        let ngram = IndexOptions {
+            ngram: Some((3, 3)),
+            ..Default::default()
+        };
+        let engine = open(&index, &ngram, Extract::Tree, &a).unwrap();
+        assert_eq!(hits(&engine, "alice"), 1);
+    }
+}
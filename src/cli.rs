@@ -1,4 +1,4 @@
-use clap::{Parser, Subcommand, ValueEnum};
+use clap::{Args, Parser, Subcommand, ValueEnum};
 
 #[derive(Parser)]
 #[command(name = "jsonai", about = "Agent-first JSON full-text search CLI")]
@@ -11,13 +11,30 @@ pub struct Cli {
     #[arg(long, global = true, conflicts_with = "pretty")]
     pub compact: bool,
 
+    /// In pretty file writes, keep arrays/objects of at most N scalars on one line
+    #[arg(long, global = true, value_name = "N")]
+    pub pretty_arrays_inline: Option<usize>,
+
+    /// Accept only canonical RFC 6901 pointers: no `{field=value}` locators, every `~` escaped as ~0 or ~1
+    #[arg(long, global = true)]
+    pub strict_pointer: bool,
+
+    /// Show the changes and ask for `y` on stdin before an edit overwrites its input file
+    #[arg(long, global = true)]
+    pub require_confirm: bool,
+
+    /// Answer yes to --require-confirm prompts
+    #[arg(long, global = true)]
+    pub yes: bool,
+
     #[command(subcommand)]
     pub command: Commands,
 }
 
 #[derive(Subcommand)]
+#[allow(clippy::large_enum_variant)]
 pub enum Commands {
-    /// Output JSON file as compact JSON (no search)
+    /// Output a JSON file, or the value at a pointer in it, as compact JSON (no search)
     Cat(CatArgs),
     /// Search JSON files by value
     Search(SearchArgs),
@@ -29,10 +46,28 @@ pub enum Commands {
     Add(AddArgs),
     /// Delete a value at a JSON Pointer path
     Delete(DeleteArgs),
+    /// Rename an object key at a JSON Pointer path
+    Rename(RenameArgs),
     /// Apply a JSON Patch (RFC 6902) document
     Patch(PatchArgs),
+    /// Deep-merge a JSON Merge Patch (RFC 7386) document into a file
+    Merge(MergeArgs),
+    /// Print the JSON Patch (RFC 6902) that turns one file into another
+    Diff(DiffArgs),
     /// Run a jq filter on JSON input
     Query(QueryArgs),
+    /// Index input once and serve searches over local HTTP
+    Serve(ServeArgs),
+    /// Print the tokens the search index holds for a record's text
+    DebugTokens(DebugTokensArgs),
+    /// List every distinct value of a field across all records, with counts
+    Distinct(DistinctArgs),
+    /// Summarize a JSON file: records, keys with their types, array lengths, depth
+    Stats(StatsArgs),
+    /// Print an indented outline of a JSON file's keys and types, without values
+    Tree(TreeArgs),
+    /// Check a JSON file against a JSON Schema, listing every violation
+    Validate(ValidateArgs),
 }
 
 #[derive(Parser)]
@@ -41,9 +76,29 @@ pub struct CatArgs {
     #[arg(required = true)]
     pub input: String,
 
-    /// JSON Pointer path to extract a subtree (e.g., /users/0)
+    /// JSON Pointer path to extract a subtree (e.g., /users/0), or a dotted path (users.0)
     #[arg(short, long)]
     pub pointer: Option<String>,
+
+    /// Return only the first N elements when the value is an array
+    #[arg(long, conflicts_with = "tail")]
+    pub head: Option<usize>,
+
+    /// Return only the last N elements when the value is an array
+    #[arg(long)]
+    pub tail: Option<usize>,
+
+    /// Error instead of ignoring --head/--tail on non-array values
+    #[arg(long)]
+    pub strict: bool,
+
+    /// Sort object keys in the output
+    #[arg(long)]
+    pub sort_keys: bool,
+
+    /// Output null instead of failing when --pointer doesn't resolve
+    #[arg(long)]
+    pub null_on_missing: bool,
 }
 
 #[derive(Parser)]
@@ -64,6 +119,98 @@ pub struct SearchArgs {
     #[arg(short, long, value_enum, default_value_t = MatchMode::Text)]
     pub r#match: MatchMode,
 
+    /// Shortest gram indexed for --match ngram
+    #[arg(long, default_value_t = 3)]
+    pub ngram_min: usize,
+
+    /// Longest gram indexed for --match ngram
+    #[arg(long, default_value_t = 4)]
+    pub ngram_max: usize,
+
+    /// Stem words and drop common stopwords of this language, in indexed text and queries alike
+    #[arg(long, value_enum, value_name = "LANG")]
+    pub lang: Option<Lang>,
+
+    /// Relevance scoring
+    #[arg(long, value_enum, default_value_t = Scoring::Bm25)]
+    pub scoring: Scoring,
+
+    /// Abort fuzzy/regex queries that expand to more than N index terms
+    #[arg(long)]
+    pub max_terms: Option<usize>,
+
+    /// Edit distance allowed by -m fuzzy (0-2)
+    #[arg(long, value_name = "0-2", default_value_t = 2, value_parser = clap::value_parser!(u8).range(0..=2))]
+    pub fuzzy_distance: u8,
+
+    /// With -m fuzzy, the first LEN characters must match exactly
+    #[arg(long, value_name = "LEN")]
+    pub fuzzy_prefix: Option<usize>,
+
+    /// With -m fuzzy, every query word must match instead of any of them
+    #[arg(long)]
+    pub fuzzy_and: bool,
+
+    /// Add a snippet of the matched words in context to each hit (--output hit; text and exact modes)
+    #[arg(long)]
+    pub highlight: bool,
+
+    /// Read the query as a boolean expression over all values: AND, OR, NOT, +/- and parentheses, with FIELD:term for a term in one field (text mode)
+    #[arg(long = "bool", conflicts_with = "field")]
+    pub boolean: bool,
+
+    /// Index a top-level field on its own, unstored: name:basic, name:freqs (no phrase queries), or name:positions (repeatable)
+    #[arg(long, value_name = "FIELD:LEVEL")]
+    pub field_option: Vec<String>,
+
+    /// Keep only records that have this field (dotted path, repeatable)
+    #[arg(long, value_name = "FIELD")]
+    pub has: Vec<String>,
+
+    /// Keep only records that lack this field (dotted path, repeatable)
+    #[arg(long, value_name = "FIELD")]
+    pub missing: Vec<String>,
+
+    /// Keep only records whose field equals VALUE (JSON, else a string): FIELD=VALUE (dotted path, repeatable)
+    #[arg(long, value_name = "FIELD=VALUE")]
+    pub eq: Vec<String>,
+
+    /// How --eq compares numbers: as written (3 != 3.0) or by value
+    #[arg(long, value_enum, default_value_t = NumberMatch::Text)]
+    pub numbers: NumberMatch,
+
+    /// Keep only records with a number within MIN..MAX (inclusive, either side optional) at a dotted path (repeatable)
+    #[arg(long, value_name = "FIELD:MIN..MAX")]
+    pub range: Vec<String>,
+
+    /// Rank matches in a dotted field higher, scaling their score by WEIGHT (repeatable)
+    #[arg(long, value_name = "FIELD:WEIGHT")]
+    pub boost: Vec<String>,
+
+    /// Return the ancestor N levels above each match instead (deduplicated)
+    #[arg(long, value_name = "N", conflicts_with = "top_level")]
+    pub ascend: Option<usize>,
+
+    /// Return the top-level record enclosing each match instead (deduplicated)
+    #[arg(long)]
+    pub top_level: bool,
+
+    /// Deduplication policy for matched records
+    #[arg(long, value_enum, default_value_t = Dedup::Ancestors)]
+    pub dedup: Dedup,
+
+    /// Keep every matched record, parents of other matches included (same as --dedup none)
+    #[arg(long, conflicts_with = "dedup")]
+    pub no_dedup: bool,
+
+    /// Order results by file, then pointer (array indices numerically), instead of by score
+    #[arg(long, conflicts_with = "sort")]
+    pub order_by_location: bool,
+
+    /// Order results by a field's value instead of by score: FIELD[:asc|:desc] (nulls last)
+    #[arg(long, value_name = "FIELD[:DIR]")]
+    pub sort: Option<String>,
+
     /// Output mode
     #[arg(short, long, value_enum, default_value_t = OutputMode::Match)]
     pub output: OutputMode,
@@ -76,18 +223,78 @@ pub struct SearchArgs {
     #[arg(long, default_value_t = 0)]
     pub offset: usize,
 
+    /// Resume after the last result of a previous page, given its meta.next_cursor
+    #[arg(long, conflicts_with = "offset")]
+    pub cursor: Option<String>,
+
+    /// Add meta.counts_by_file: matches per file, counted before --offset/--limit
+    #[arg(long)]
+    pub count_by_file: bool,
+
     /// Only return count, no results
     #[arg(long)]
     pub count_only: bool,
 
-    /// Project specific fields in output (comma-separated)
+    /// Print how many matches have each value of this field (dotted path) instead of results; repeatable
+    #[arg(long, value_name = "FIELD", conflicts_with_all = ["count_only", "stream", "plan"])]
+    pub group_by: Vec<String>,
+
+    /// Project specific fields in output (comma-separated); `**.id` collects every `id` at any depth
     #[arg(long)]
     pub select: Option<String>,
 
+    /// Drop specific fields from output (comma-separated, dotted paths)
+    #[arg(long, conflicts_with = "select")]
+    pub select_exclude: Option<String>,
+
+    /// Project the top-level fields whose name matches this regex (adds to --select)
+    #[arg(long, value_name = "PATTERN", conflicts_with = "select_exclude")]
+    pub select_regex: Option<String>,
+
+    /// Apply --select-regex at every depth, keeping the path to each matching key
+    #[arg(long, requires = "select_regex")]
+    pub select_regex_deep: bool,
+
+    /// Replace each returned record with the output of this jq filter; records it yields nothing for are dropped
+    #[arg(long, value_name = "FILTER")]
+    pub transform: Option<String>,
+
+    /// Replace the value at a field (dotted path) with "***". Repeatable.
+    #[arg(long)]
+    pub redact: Vec<String>,
+
+    /// Redact with a SHA-256 digest of the value instead of "***"
+    #[arg(long, requires = "redact")]
+    pub redact_hash: bool,
+
+    /// List the files with at least one match (sorted, no records); ignores limits and overflow
+    #[arg(long)]
+    pub only_files: bool,
+
+    /// Print one compact result per line, then a {"meta":...} trailer line (NDJSON)
+    #[arg(long, conflicts_with = "bare")]
+    pub stream: bool,
+
     /// Output bare JSON array instead of envelope
     #[arg(long)]
     pub bare: bool,
 
+    /// With --output value, collect only values at this field (dotted path, repeatable)
+    #[arg(long)]
+    pub value_field: Vec<String>,
+
+    /// Also report hits with a key-based locator that survives reordering, using this field to identify array elements (e.g. /items/{id=abc})
+    #[arg(long, value_name = "FIELD")]
+    pub id_pointer: Option<String>,
+
+    /// Sort object keys in the output (does not affect stored documents)
+    #[arg(long)]
+    pub sort_keys: bool,
+
+    /// Shorten the longest strings of any single result rendering to more than N bytes (marked with "…")
+    #[arg(long, value_name = "N")]
+    pub clip_record_bytes: Option<usize>,
+
     /// Max output bytes (results truncated to fit, JSON stays valid)
     #[arg(long)]
     pub max_bytes: Option<usize>,
@@ -96,6 +303,14 @@ pub struct SearchArgs {
     #[arg(long, default_value_t = 50)]
     pub threshold: usize,
 
+    /// Never fetch more than N matches from the index, even for plan facets (counts and facets then cover a sample)
+    #[arg(long, value_name = "N")]
+    pub max_fetch: Option<usize>,
+
+    /// Also overflow when the returned results would render to more than N bytes
+    #[arg(long, value_name = "N")]
+    pub overflow_bytes: Option<usize>,
+
     /// Force plan mode: return only metadata/facets/suggestions, no results
     #[arg(long)]
     pub plan: bool,
@@ -104,13 +319,72 @@ pub struct SearchArgs {
     #[arg(long)]
     pub no_overflow: bool,
 
-    /// JSON Schema file for structure awareness
+    /// What to do when results exceed the threshold
+    #[arg(long, value_enum, default_value_t = OnOverflow::Plan)]
+    pub on_overflow: OnOverflow,
+
+    /// In plan mode, also break facet counts down per file
+    #[arg(long)]
+    pub facet_by_file: bool,
+
+    /// Case handling for plan facets: sensitive, fold
+    #[arg(long, value_enum, default_value_t = FacetCase::Sensitive)]
+    pub facet_case: FacetCase,
+
+    /// Only return records that are valid against this JSON Schema file
     #[arg(long)]
     pub schema: Option<String>,
 
+    /// Read newline-delimited file paths to search from this file ("-" for stdin)
+    #[arg(long, conflicts_with = "input")]
+    pub files_from: Option<String>,
+
+    /// Only search files modified since a duration ago (e.g. 1h, 30m) or an RFC 3339 timestamp
+    #[arg(long)]
+    pub since: Option<String>,
+
+    /// Add each hit's score breakdown (`explanation`, in hit output); when nothing matches, add meta.hint explaining casing and tokenization
+    #[arg(long)]
+    pub explain: bool,
+
+    /// Print the resolved search settings and input files as JSON without searching
+    #[arg(long)]
+    pub dump_config: bool,
+
+    /// Fail instead of warning when an input file can't be loaded
+    #[arg(long)]
+    pub strict: bool,
+
+    /// Index each document (or NDJSON line) as one record instead of walking its tree
+    #[arg(long)]
+    pub whole_document: bool,
+
+    /// Follow an NDJSON file: print matches for appended lines as they arrive
+    #[arg(long, conflicts_with_all = ["files_from", "since"])]
+    pub follow: bool,
+
+    /// Read a single file (or stdin) holding one top-level JSON array element by element instead of loading it whole
+    #[arg(long, conflicts_with_all = ["files_from", "follow", "index_dir", "dump_config"])]
+    pub stream_input: bool,
+
+    /// Keep the index on disk in this directory and only re-index files that changed since the last run
+    #[arg(long, value_name = "PATH", conflicts_with = "follow")]
+    pub index_dir: Option<String>,
+
     /// Input: file path, directory, glob, or "-" for stdin
-    #[arg(required = true)]
-    pub input: String,
+    #[arg(required_unless_present = "files_from")]
+    pub input: Option<String>,
+}
+
+impl SearchArgs {
+    /// The deduplication policy in effect, `--no-dedup` included.
+    pub fn dedup_policy(&self) -> &Dedup {
+        if self.no_dedup {
+            &Dedup::None
+        } else {
+            &self.dedup
+        }
+    }
 }
 
 #[derive(Parser)]
@@ -118,9 +392,21 @@ pub struct FieldsArgs {
     /// JSON file or schema file to inspect
     pub input: String,
 
-    /// Use this file as JSON Schema
+    /// Read the input as a JSON Schema and list the fields it declares
     #[arg(long)]
     pub schema: bool,
+
+    /// List only paths of at most N keys
+    #[arg(long, value_name = "N", conflicts_with = "schema")]
+    pub max_depth: Option<usize>,
+
+    /// List only paths to scalar values, not the objects above them
+    #[arg(long, conflicts_with = "schema")]
+    pub leaves_only: bool,
+
+    /// Look only at the first N elements of each array (default: all of them)
+    #[arg(long, value_name = "N", conflicts_with = "schema")]
+    pub sample: Option<usize>,
 }
 
 #[derive(Parser)]
@@ -129,7 +415,7 @@ pub struct SetArgs {
     #[arg(short, long)]
     pub pointer: String,
 
-    /// Value to set (JSON string, number, object, etc.)
+    /// Value to set (JSON string, number, object, etc.), or @file / @- to read it from a file or stdin
     pub value: String,
 
     /// Target JSON file
@@ -142,6 +428,50 @@ pub struct SetArgs {
     /// Dry run: print result without writing
     #[arg(long)]
     pub dry_run: bool,
+
+    /// Print the result to stdout; the input file is not modified (--output is still written)
+    #[arg(long)]
+    pub stdout: bool,
+
+    /// Substitute ${VAR} in the value from the environment before parsing it as JSON
+    #[arg(long)]
+    pub expand_env: bool,
+
+    /// Fallback for a variable missing from the environment: NAME=VALUE (repeatable, implies --expand-env)
+    #[arg(long, value_name = "NAME=VALUE")]
+    pub expand_env_default: Vec<String>,
+
+    /// Only set if the value at this JSON Pointer equals --test-value (otherwise exit 2, nothing written)
+    #[arg(long, value_name = "POINTER", requires = "test_value")]
+    pub test: Option<String>,
+
+    /// JSON value --test must find
+    #[arg(long, value_name = "JSON", requires = "test")]
+    pub test_value: Option<String>,
+
+    /// Only set if the current value at --pointer equals this JSON (otherwise exit 2, nothing written)
+    #[arg(long, value_name = "JSON", conflicts_with = "test")]
+    pub if_equals: Option<String>,
+
+    #[command(flatten)]
+    pub backup: BackupArgs,
+}
+
+/// `--backup`/`--backup-suffix`/`--force`, shared by the commands that
+/// write a file in place.
+#[derive(Args)]
+pub struct BackupArgs {
+    /// Before writing in place, copy the original file to <file>.bak
+    #[arg(long = "backup")]
+    pub enabled: bool,
+
+    /// Suffix for the --backup copy (implies --backup)
+    #[arg(long = "backup-suffix", value_name = "SUFFIX")]
+    pub suffix: Option<String>,
+
+    /// Overwrite a backup left by an earlier run
+    #[arg(long)]
+    pub force: bool,
 }
 
 #[derive(Parser)]
@@ -150,7 +480,7 @@ pub struct AddArgs {
     #[arg(short, long)]
     pub pointer: String,
 
-    /// Value to add (JSON)
+    /// Value to add (JSON), or @file / @- to read it from a file or stdin
     pub value: String,
 
     /// Target JSON file
@@ -161,6 +491,20 @@ pub struct AddArgs {
 
     #[arg(long)]
     pub dry_run: bool,
+
+    #[arg(long)]
+    pub stdout: bool,
+
+    /// Substitute ${VAR} in the value from the environment before parsing it as JSON
+    #[arg(long)]
+    pub expand_env: bool,
+
+    /// Fallback for a variable missing from the environment: NAME=VALUE (repeatable, implies --expand-env)
+    #[arg(long, value_name = "NAME=VALUE")]
+    pub expand_env_default: Vec<String>,
+
+    #[command(flatten)]
+    pub backup: BackupArgs,
 }
 
 #[derive(Parser)]
@@ -177,6 +521,34 @@ pub struct DeleteArgs {
 
     #[arg(long)]
     pub dry_run: bool,
+
+    #[arg(long)]
+    pub stdout: bool,
+
+    #[command(flatten)]
+    pub backup: BackupArgs,
+}
+
+#[derive(Parser)]
+pub struct RenameArgs {
+    /// JSON Pointer path of the key to rename (e.g., /users/0/mail)
+    #[arg(short, long)]
+    pub pointer: String,
+
+    /// New key name
+    pub new_name: String,
+
+    /// Target JSON file
+    pub file: String,
+
+    #[arg(short, long)]
+    pub output: Option<String>,
+
+    #[arg(long)]
+    pub dry_run: bool,
+
+    #[arg(long)]
+    pub stdout: bool,
 }
 
 #[derive(Parser)]
@@ -185,6 +557,14 @@ pub struct PatchArgs {
     #[arg(short, long)]
     pub patch: Option<String>,
 
+    /// Apply every operation that succeeds instead of none on the first failure; a summary goes to stderr
+    #[arg(long)]
+    pub keep_going: bool,
+
+    /// Treat the patch as a JSON Merge Patch (RFC 7386) object instead of an operations array
+    #[arg(long, conflicts_with = "keep_going")]
+    pub merge: bool,
+
     /// Target JSON file
     pub file: String,
 
@@ -193,6 +573,39 @@ pub struct PatchArgs {
 
     #[arg(long)]
     pub dry_run: bool,
+
+    #[arg(long)]
+    pub stdout: bool,
+
+    #[command(flatten)]
+    pub backup: BackupArgs,
+}
+
+#[derive(Parser)]
+pub struct MergeArgs {
+    /// Base JSON file
+    pub base: String,
+
+    /// Merge patch document: objects merge recursively, null deletes a key, anything else replaces
+    pub patch: String,
+
+    #[arg(short, long)]
+    pub output: Option<String>,
+
+    #[arg(long)]
+    pub dry_run: bool,
+
+    #[arg(long)]
+    pub stdout: bool,
+}
+
+#[derive(Parser)]
+pub struct DiffArgs {
+    /// Original JSON file, or "-" for stdin
+    pub left: String,
+
+    /// Changed JSON file, or "-" for stdin
+    pub right: String,
 }
 
 #[derive(Parser)]
@@ -201,29 +614,222 @@ pub struct QueryArgs {
     #[arg(short, long)]
     pub filter: String,
 
-    /// Input: file path or "-" for stdin
+    /// Exit 0 if the filter produces any truthy output (not null/false), 1 otherwise; print nothing
+    #[arg(long, conflicts_with = "none")]
+    pub any: bool,
+
+    /// Exit 0 if the filter produces no truthy output, 1 otherwise; print nothing
+    #[arg(long)]
+    pub none: bool,
+
+    /// Bind $NAME to the string VALUE in the filter; repeatable
+    #[arg(long = "arg", num_args = 2, value_names = ["NAME", "VALUE"])]
+    pub args: Vec<String>,
+
+    /// Bind $NAME to the parsed JSON VALUE in the filter; repeatable
+    #[arg(long = "argjson", num_args = 2, value_names = ["NAME", "VALUE"])]
+    pub json_args: Vec<String>,
+
+    /// Run the filter once over an array of every input instead of on each input
+    #[arg(long)]
+    pub slurp: bool,
+
+    /// Print each result on its own line, strings without quotes (like jq -r)
+    #[arg(short, long, conflicts_with_all = ["any", "none"])]
+    pub raw_output: bool,
+
+    /// Exit 1 if the last output is null or false, or there is none (like jq -e)
+    #[arg(short, long, conflicts_with_all = ["any", "none"])]
+    pub exit_status: bool,
+
+    /// Input: file paths, directories, globs, or "-" for stdin
+    #[arg(required = true)]
+    pub input: Vec<String>,
+}
+
+#[derive(Parser)]
+pub struct ServeArgs {
+    /// Input: file path, directory, glob, or "-" for stdin
     #[arg(required = true)]
     pub input: String,
+
+    /// Index a top-level field on its own, unstored: name:basic, name:freqs (no phrase queries), or name:positions (repeatable)
+    #[arg(long, value_name = "FIELD:LEVEL")]
+    pub field_option: Vec<String>,
+
+    /// Address to bind (localhost only by default)
+    #[arg(long, default_value = "127.0.0.1")]
+    pub bind: String,
+
+    /// Port to listen on (0 picks a free port)
+    #[arg(long, default_value_t = 7878)]
+    pub port: u16,
 }
 
-#[derive(Clone, ValueEnum)]
+#[derive(Parser)]
+pub struct DebugTokensArgs {
+    /// JSON file, or "-" for stdin
+    pub input: String,
+
+    /// JSON Pointer of the record to tokenize (default: the whole document)
+    #[arg(short, long)]
+    pub pointer: Option<String>,
+
+    /// Match mode whose analyzer to run (ngram splits into grams; others use the default tokenizer)
+    #[arg(short, long, value_enum, default_value_t = MatchMode::Text)]
+    pub r#match: MatchMode,
+
+    /// Shortest gram for --match ngram
+    #[arg(long, default_value_t = 3)]
+    pub ngram_min: usize,
+
+    /// Longest gram for --match ngram
+    #[arg(long, default_value_t = 4)]
+    pub ngram_max: usize,
+
+    /// Show the tokens as `search --lang` indexes them: stemmed, stopwords dropped
+    #[arg(long, value_enum, value_name = "LANG")]
+    pub lang: Option<Lang>,
+}
+
+#[derive(Parser)]
+pub struct DistinctArgs {
+    /// Field whose values to list (dotted path)
+    #[arg(short, long)]
+    pub field: String,
+
+    /// Input: file path, directory, glob pattern, or "-" for stdin
+    pub input: String,
+}
+
+#[derive(Parser)]
+pub struct ValidateArgs {
+    /// JSON Schema file (draft 2020-12 unless it declares another $schema)
+    #[arg(short, long)]
+    pub schema: String,
+
+    /// Input JSON file, or "-" for stdin
+    pub input: String,
+}
+
+#[derive(Parser)]
+pub struct StatsArgs {
+    /// Input JSON file, or "-" for stdin
+    pub input: String,
+}
+
+#[derive(Parser)]
+pub struct TreeArgs {
+    /// Input JSON file, or "-" for stdin
+    pub input: String,
+
+    /// Show at most N levels below the root
+    #[arg(long, value_name = "N")]
+    pub depth: Option<usize>,
+}
+
+#[derive(Clone, Default, ValueEnum)]
 pub enum MatchMode {
     /// Tokenized full-text search (default)
+    #[default]
     Text,
-    /// Exact value match
+    /// Whole-value match, case-sensitive
     Exact,
     /// Fuzzy match (edit distance)
     Fuzzy,
     /// Regular expression
     Regex,
+    /// Substring match through an n-gram index (see --ngram-min/--ngram-max)
+    Ngram,
+    /// Every query word must start a word of the value
+    Prefix,
+}
+
+#[derive(Clone, Default, ValueEnum)]
+pub enum Scoring {
+    /// BM25 with length normalization (default)
+    #[default]
+    Bm25,
+    /// Raw term frequency of the query terms, no length normalization
+    Tf,
+    /// Constant score for every match
+    None,
+}
+
+#[derive(Clone, PartialEq, ValueEnum)]
+pub enum OnOverflow {
+    /// Return a plan with facets and suggested commands instead of results (default)
+    Plan,
+    /// Return the top --limit results and flag meta.overflow
+    Truncate,
+    /// Return results regardless of the threshold (same as --no-overflow)
+    All,
+}
+
+#[derive(Clone, Default, PartialEq, ValueEnum)]
+pub enum FacetCase {
+    /// Count values exactly as written (default)
+    #[default]
+    Sensitive,
+    /// Lowercase values before counting; buckets show the first spelling seen
+    Fold,
+}
+
+/// Languages `--lang` can stem and remove stopwords for.
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+pub enum Lang {
+    /// English
+    En,
+    /// German
+    De,
+    /// Spanish
+    Es,
+    /// French
+    Fr,
+    /// Italian
+    It,
+    /// Dutch
+    Nl,
+    /// Portuguese
+    Pt,
+    /// Russian
+    Ru,
+    /// Swedish
+    Sv,
+}
+
+#[derive(Clone, Copy, Default, PartialEq, ValueEnum)]
+pub enum NumberMatch {
+    /// Numbers equal only as written: `3` does not equal `3.0` (default)
+    #[default]
+    Text,
+    /// Numbers equal by value: `3` equals `3.0`
+    Numeric,
 }
 
 #[derive(Clone, ValueEnum)]
+pub enum Dedup {
+    /// Drop a record when one of its descendants also matched (default)
+    Ancestors,
+    /// Drop records whose value is identical to a higher-scoring record
+    Value,
+    /// Keep every matched record
+    None,
+}
+
+#[derive(Clone, Default, ValueEnum)]
 pub enum OutputMode {
     /// Matched JSON objects (default)
+    #[default]
     Match,
     /// Matched objects with file path and JSON pointer
     Hit,
     /// Just matched values
     Value,
+    /// RFC 6902 `add` operations recreating each match at its pointer
+    Patch,
+    /// One object mapping each match's pointer (`file#pointer` across files) to its record
+    Map,
+    /// CSV with a header row: one column per top-level key (or --select field), nested values as JSON
+    Csv,
 }
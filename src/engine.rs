@@ -1,12 +1,64 @@
-use anyhow::{Context, Result};
+use anyhow::{bail, Context, Result};
+use levenshtein_automata::{Distance, LevenshteinAutomatonBuilder, DFA, SINK_STATE};
 use serde_json::Value;
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, HashMap};
+use std::ops::Bound;
+use std::path::Path;
 use tantivy::collector::TopDocs;
-use tantivy::query::{FuzzyTermQuery, QueryParser, RegexQuery};
+use tantivy::directory::MmapDirectory;
+use tantivy::json_utils::JsonTermWriter;
+use tantivy::postings::{Postings, SegmentPostings};
+use tantivy::query::{
+    AutomatonWeight, BooleanQuery, BoostQuery, ConstScoreQuery, Explanation, FuzzyTermQuery, Occur, Query, QueryParser, RangeQuery, RegexQuery,
+    TermQuery,
+};
+use tantivy::snippet::SnippetGenerator;
+use tantivy::tokenizer::{
+    Language, LowerCaser, NgramTokenizer, RemoveLongFilter, SimpleTokenizer, Stemmer, StopWordFilter, TextAnalyzer,
+};
 use tantivy::schema::{self, *};
-use tantivy::{Index, ReloadPolicy, TantivyDocument, Term};
+use tantivy::{DocAddress, DocId, DocSet, Index, IndexWriter, ReloadPolicy, Score, Searcher, SegmentReader, TantivyDocument, Term};
 
-use crate::cli::MatchMode;
+use crate::cli::{Dedup, Lang, MatchMode, NumberMatch, OutputMode, Scoring, SearchArgs};
+use crate::manipulate::key_segment;
+
+/// Explain the casing and tokenization assumptions behind a query that
+/// matched nothing, for `--explain`.
+pub fn zero_result_hint(match_mode: &MatchMode, lang: Option<Lang>) -> String {
+    let base = format!(
+        "Values are indexed with the {:?} tokenizer: split into words on non-alphanumeric characters and lowercased. Queries are case-insensitive.",
+        TOKENIZER
+    );
+    let specific = match match_mode {
+        MatchMode::Text => {
+            "All query words must appear in the same record; with --field, in that field."
+        }
+        MatchMode::Exact => {
+            "Exact ignores the tokenizer: the whole query must equal one value, including case; with --field, a value of that field."
+        }
+        MatchMode::Fuzzy => {
+            "Fuzzy matches each query word within --fuzzy-distance edits (2 by default) across all fields; any one word is enough unless --fuzzy-and is given."
+        }
+        MatchMode::Regex => {
+            "Regex must match one whole word across all fields (with --field, a word of that field); patterns spanning spaces or punctuation never match."
+        }
+        MatchMode::Ngram => {
+            "N-gram matches substrings across all fields; queries shorter than --ngram-min match nothing."
+        }
+        MatchMode::Prefix => {
+            "Prefix needs every query word to start an indexed word, so `auth` matches `authorize` but not `oauth`; with --field, a word of that field."
+        }
+    };
+    let stemming = match (lang, match_mode) {
+        (Some(_), MatchMode::Regex) => {
+            " With --lang, words are indexed stemmed but the pattern is not, so it must match the stem: `running` misses a record saying \"running\", `run` finds it."
+        }
+        (Some(_), MatchMode::Exact) => "",
+        (Some(_), _) => " With --lang, indexed words and query words are both stemmed and stopwords are dropped.",
+        (None, _) => "",
+    };
+    format!("{} {}{}", base, specific, stemming)
+}
 
 /// A record extracted from a JSON file
 #[derive(Debug, Clone)]
@@ -21,8 +73,328 @@ pub struct Record {
 pub struct SearchResult {
     pub record: Record,
     pub score: f32,
+    /// Key-based pointer that survives array reordering (`--id-pointer`)
+    pub locator: Option<String>,
+    /// The matched words in context (`--highlight`)
+    pub snippet: Option<String>,
+    /// How the score was computed (`--explain`)
+    pub explanation: Option<Value>,
+    /// Where the match sits in the index, for `Engine::explain`
+    pub doc: Option<DocAddress>,
+}
+
+/// Where a `--cursor` page starts: just after the record at `file` +
+/// `pointer`, which scored `score`. Matches rank by score, then by index
+/// order, so the place holds even when scores tie.
+#[derive(Debug, Clone, PartialEq)]
+pub struct After {
+    pub score: Score,
+    pub file: String,
+    pub pointer: String,
+}
+
+/// How a query is matched and ranked.
+#[derive(Clone)]
+pub struct SearchOptions {
+    pub match_mode: MatchMode,
+    pub scoring: Scoring,
+    /// Reject fuzzy/regex queries expanding to more index terms than this
+    pub max_terms: Option<usize>,
+    /// Numeric ranges every match must also satisfy (`--range`)
+    pub ranges: Vec<NumericRange>,
+    /// Fields where a match weighs more in the score (`--boost`)
+    pub boosts: Vec<FieldBoost>,
+    /// Text mode: `FIELD:term` in the query searches a record field (`--bool`)
+    pub boolean: bool,
+    /// Edit distance allowed in fuzzy mode
+    pub fuzzy_distance: u8,
+    /// Fuzzy mode: this many leading characters must match exactly
+    pub fuzzy_prefix: Option<usize>,
+    /// Fuzzy mode: every query word must match, not just one
+    pub fuzzy_and: bool,
+    /// Fill in `SearchResult::explanation` with `Engine::explain` (only
+    /// `-o hit` prints it)
+    pub explain: bool,
+    /// Only return matches ranked after this one (`--cursor`)
+    pub after: Option<After>,
+}
+
+impl Default for SearchOptions {
+    fn default() -> Self {
+        SearchOptions {
+            match_mode: MatchMode::default(),
+            scoring: Scoring::default(),
+            max_terms: None,
+            ranges: Vec::new(),
+            boosts: Vec::new(),
+            boolean: false,
+            fuzzy_distance: FUZZY_DISTANCE,
+            fuzzy_prefix: None,
+            fuzzy_and: false,
+            explain: false,
+            after: None,
+        }
+    }
+}
+
+impl SearchOptions {
+    pub fn from_args(args: &SearchArgs) -> Result<Self> {
+        Ok(SearchOptions {
+            match_mode: args.r#match.clone(),
+            scoring: args.scoring.clone(),
+            max_terms: args.max_terms,
+            ranges: NumericRange::parse_all(&args.range)?,
+            boosts: args.boost.iter().map(|spec| FieldBoost::parse(spec)).collect::<Result<_>>()?,
+            boolean: args.boolean,
+            fuzzy_distance: args.fuzzy_distance,
+            fuzzy_prefix: args.fuzzy_prefix,
+            fuzzy_and: args.fuzzy_and,
+            // Only hit output prints the breakdown, so don't compute it
+            // for any other mode.
+            explain: args.explain && matches!(args.output, OutputMode::Hit),
+            after: None,
+        })
+    }
+}
+
+/// A `--boost FIELD:WEIGHT` request: matches in the dotted field add to the
+/// score, multiplied by the weight.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FieldBoost {
+    pub field: String,
+    pub weight: f32,
+}
+
+impl FieldBoost {
+    /// Parse `field:weight`.
+    pub fn parse(spec: &str) -> Result<Self> {
+        let (field, weight) = spec
+            .rsplit_once(':')
+            .with_context(|| format!("Invalid --boost {:?}: expected field:weight", spec))?;
+        if field.is_empty() {
+            bail!("Invalid --boost {:?}: missing field name", spec);
+        }
+        let weight = weight
+            .parse::<f32>()
+            .ok()
+            .filter(|w| w.is_finite() && *w > 0.0)
+            .with_context(|| format!("Invalid --boost weight {:?} in {:?}: expected a positive number", weight, spec))?;
+        Ok(FieldBoost {
+            field: field.to_string(),
+            weight,
+        })
+    }
+}
+
+/// A `--range FIELD:MIN..MAX` condition: a number at the dotted path must
+/// lie within the inclusive bounds. Either bound may be left out.
+#[derive(Debug, Clone, PartialEq)]
+pub struct NumericRange {
+    pub field: String,
+    pub min: Option<f64>,
+    pub max: Option<f64>,
+}
+
+impl NumericRange {
+    /// Parse every `--range` given.
+    pub fn parse_all(specs: &[String]) -> Result<Vec<Self>> {
+        specs.iter().map(|spec| Self::parse(spec)).collect()
+    }
+
+    /// Parse `field:min..max`, `field:min..` or `field:..max`.
+    pub fn parse(spec: &str) -> Result<Self> {
+        let (field, bounds) = spec
+            .rsplit_once(':')
+            .with_context(|| format!("Invalid --range {:?}: expected field:min..max", spec))?;
+        if field.is_empty() {
+            bail!("Invalid --range {:?}: missing field name", spec);
+        }
+        let (min, max) = bounds
+            .split_once("..")
+            .with_context(|| format!("Invalid --range {:?}: expected field:min..max", spec))?;
+        let bound = |text: &str| -> Result<Option<f64>> {
+            if text.is_empty() {
+                return Ok(None);
+            }
+            let number = text
+                .parse::<f64>()
+                .ok()
+                .filter(|n| n.is_finite())
+                .with_context(|| format!("Invalid --range bound {:?} in {:?}: expected a number", text, spec))?;
+            Ok(Some(number))
+        };
+        let (min, max) = (bound(min)?, bound(max)?);
+        if let (Some(lo), Some(hi)) = (min, max) {
+            if lo > hi {
+                bail!("Invalid --range {:?}: min is greater than max", spec);
+            }
+        }
+        Ok(NumericRange {
+            field: field.to_string(),
+            min,
+            max,
+        })
+    }
+}
+
+/// How much of a top-level field is kept in the index, set per field with
+/// `--field-option name:level`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FieldIndexing {
+    /// Document ids only: the field can be searched but not ranked by frequency
+    Basic,
+    /// Term frequencies without positions: no phrase queries
+    Freqs,
+    /// Frequencies and positions, the same as any other field
+    Positions,
+}
+
+/// Per-field indexing override. Fields given one are indexed on their own
+/// and never stored in the index; their value is still returned from the
+/// record source.
+#[derive(Debug, Clone)]
+pub struct FieldOption {
+    pub field: String,
+    pub indexing: FieldIndexing,
+}
+
+impl FieldOption {
+    /// Parse every `--field-option` given. A field may only get one.
+    pub fn parse_all(specs: &[String]) -> Result<Vec<Self>> {
+        let options: Vec<Self> = specs.iter().map(|spec| Self::parse(spec)).collect::<Result<_>>()?;
+        check_unique_fields(&options)?;
+        Ok(options)
+    }
+
+    /// Parse `name:level`, e.g. `content:freqs`.
+    pub fn parse(spec: &str) -> Result<Self> {
+        let (field, level) = spec
+            .rsplit_once(':')
+            .with_context(|| format!("Invalid --field-option {:?}: expected name:level", spec))?;
+        if field.is_empty() {
+            bail!("Invalid --field-option {:?}: missing field name", spec);
+        }
+        let indexing = match level {
+            "basic" => FieldIndexing::Basic,
+            "freqs" => FieldIndexing::Freqs,
+            "positions" => FieldIndexing::Positions,
+            other => bail!(
+                "Invalid --field-option level {:?}: expected basic, freqs, or positions",
+                other
+            ),
+        };
+        Ok(FieldOption {
+            field: field.to_string(),
+            indexing,
+        })
+    }
+}
+
+fn check_unique_fields(options: &[FieldOption]) -> Result<()> {
+    for (i, option) in options.iter().enumerate() {
+        if options[..i].iter().any(|earlier| earlier.field == option.field) {
+            bail!("--field-option given twice for field {:?}", option.field);
+        }
+    }
+    Ok(())
+}
+
+impl FieldIndexing {
+    fn record_option(self) -> IndexRecordOption {
+        match self {
+            FieldIndexing::Basic => IndexRecordOption::Basic,
+            FieldIndexing::Freqs => IndexRecordOption::WithFreqs,
+            FieldIndexing::Positions => IndexRecordOption::WithFreqsAndPositions,
+        }
+    }
 }
 
+/// Which ancestor of a match `--ascend`/`--top-level` return.
+#[derive(Debug, Clone, Copy)]
+pub enum Ascend {
+    /// Walk this many levels up the pointer
+    Levels(usize),
+    /// The record directly under the document root (`/3`, `/users`)
+    TopLevel,
+}
+
+/// Settings that must be fixed before anything is indexed.
+#[derive(Debug, Clone, Default)]
+pub struct IndexOptions {
+    /// Per-field indexing overrides (`--field-option`)
+    pub field_options: Vec<FieldOption>,
+    /// Min and max gram length of the n-gram field searched by
+    /// `--match ngram`. The field is only built when this is set, since it
+    /// holds several terms per character of text.
+    pub ngram: Option<(usize, usize)>,
+    /// Dotted paths whose numbers are indexed for `--range`
+    pub range_fields: Vec<String>,
+    /// Build the untokenized fields searched by `--match exact`. They hold
+    /// every scalar once per record containing it, so only when needed.
+    pub exact: bool,
+    /// Stem and drop stopwords in tokenized text (`--lang`)
+    pub lang: Option<Lang>,
+}
+
+impl IndexOptions {
+    pub fn from_args(args: &SearchArgs) -> Result<Self> {
+        let mut range_fields: Vec<String> = Vec::new();
+        for range in NumericRange::parse_all(&args.range)? {
+            if !range_fields.contains(&range.field) {
+                range_fields.push(range.field);
+            }
+        }
+        Ok(IndexOptions {
+            field_options: FieldOption::parse_all(&args.field_option)?,
+            ngram: Self::ngram_range(&args.r#match, args.ngram_min, args.ngram_max)?,
+            range_fields,
+            exact: matches!(args.r#match, MatchMode::Exact),
+            lang: args.lang,
+        })
+    }
+
+    /// The fields a search with `options` needs: the exact or n-gram fields
+    /// for those modes (grams of the CLI's default 3 to 4 characters) and a
+    /// range field per `ranges` entry.
+    pub fn for_search(options: &SearchOptions) -> Result<Self> {
+        let mut range_fields: Vec<String> = Vec::new();
+        for range in &options.ranges {
+            if !range_fields.contains(&range.field) {
+                range_fields.push(range.field.clone());
+            }
+        }
+        Ok(IndexOptions {
+            ngram: Self::ngram_range(&options.match_mode, 3, 4)?,
+            range_fields,
+            exact: matches!(options.match_mode, MatchMode::Exact),
+            ..Default::default()
+        })
+    }
+
+    /// The n-gram range to build, which only `--match ngram` needs.
+    pub fn ngram_range(match_mode: &MatchMode, min: usize, max: usize) -> Result<Option<(usize, usize)>> {
+        match match_mode {
+            MatchMode::Ngram => {
+                if min == 0 || min > max {
+                    bail!(
+                        "Invalid n-gram range {}..{}: need 1 <= --ngram-min <= --ngram-max",
+                        min,
+                        max
+                    );
+                }
+                Ok(Some((min, max)))
+            }
+            _ => Ok(None),
+        }
+    }
+}
+
+/// Name the n-gram analyzer is registered under.
+const NGRAM_TOKENIZER: &str = "ngram";
+
+/// Default edit distance of fuzzy matching.
+const FUZZY_DISTANCE: u8 = 2;
+
 /// The search engine
 pub struct Engine {
     index: Index,
@@ -33,16 +405,83 @@ pub struct Engine {
     pointer_field: Field,
     file_field: Field,
     source_field: Field,
+    /// Fields indexed outside `content` because of a `--field-option`
+    dedicated_fields: HashMap<String, (Field, FieldIndexing)>,
+    /// All text split into n-grams, when `IndexOptions::ngram` is set
+    ngram_field: Option<Field>,
+    /// Every scalar value untokenized, for `--match exact`, when
+    /// `IndexOptions::exact` is set
+    exact_field: Option<Field>,
+    /// Numbers at each `IndexOptions::range_fields` path, for `--range`
+    range_fields: HashMap<String, Field>,
+    /// Every scalar value untokenized and prefixed with its dotted path,
+    /// for `--match exact --field`, when `IndexOptions::exact` is set
+    exact_path_field: Option<Field>,
+}
+
+/// Adds records to an [`Engine`]'s index; none are searchable until
+/// [`RecordWriter::commit`].
+pub struct RecordWriter<'a> {
+    engine: &'a Engine,
+    writer: IndexWriter,
+}
+
+impl RecordWriter<'_> {
+    pub fn add(&mut self, record: &Record) -> Result<()> {
+        self.writer.add_document(self.engine.document(record)?)?;
+        Ok(())
+    }
+
+    pub fn commit(mut self) -> Result<()> {
+        self.writer.commit().context("Failed to commit index")?;
+        Ok(())
+    }
+}
+
+/// Tokenizer applied to indexed text and queries. It splits on anything
+/// that isn't alphanumeric and lowercases every token, so the term
+/// dictionary only ever holds lowercase single words.
+pub const TOKENIZER: &str = "default";
+
+/// Tokenizer of the exact-match fields: the whole value is one term, with
+/// its case kept.
+const EXACT_TOKENIZER: &str = "raw";
+
+/// Separates the path from the value in `_exact_path` terms.
+const EXACT_PATH_SEPARATOR: char = '\u{0}';
+
+/// Regex queries are matched against lowercased index terms; matching
+/// case-insensitively keeps uppercase letters in a pattern from silently
+/// matching nothing.
+fn regex_pattern(query_str: &str) -> String {
+    format!("(?i){}", query_str)
 }
 
 impl Engine {
+    #[cfg(test)]
     pub fn new() -> Result<Self> {
+        Self::with_options(&IndexOptions::default())
+    }
+
+    /// Build an engine with per-field index settings and optional extra
+    /// fields. With default options this is the same as [`Engine::new`].
+    pub fn with_options(options: &IndexOptions) -> Result<Self> {
+        Self::build(options, None)
+    }
+
+    /// Open the index stored in `dir`, creating it if it doesn't exist yet.
+    /// An existing index must have been built with the same `options`.
+    pub fn open_in_dir(dir: &Path, options: &IndexOptions) -> Result<Self> {
+        Self::build(options, Some(dir))
+    }
+
+    fn build(options: &IndexOptions, dir: Option<&Path>) -> Result<Self> {
         let mut builder = Schema::builder();
 
         let json_options = JsonObjectOptions::default()
             .set_indexing_options(
                 TextFieldIndexing::default()
-                    .set_tokenizer("default")
+                    .set_tokenizer(TOKENIZER)
                     .set_index_option(IndexRecordOption::WithFreqsAndPositions),
             )
             .set_stored();
@@ -53,8 +492,82 @@ impl Engine {
         let file_field = builder.add_text_field("_file", STRING | STORED);
         let source_field = builder.add_text_field("_source", STORED);
 
+        check_unique_fields(&options.field_options)?;
+        let mut dedicated_fields = HashMap::new();
+        for option in &options.field_options {
+            let indexing = TextOptions::default().set_indexing_options(
+                TextFieldIndexing::default()
+                    .set_tokenizer(TOKENIZER)
+                    .set_index_option(option.indexing.record_option()),
+            );
+            let field = builder.add_text_field(&dedicated_field_name(&option.field), indexing);
+            dedicated_fields.insert(option.field.clone(), (field, option.indexing));
+        }
+
+        let ngram_field = options.ngram.map(|_| {
+            let indexing = TextOptions::default().set_indexing_options(
+                TextFieldIndexing::default()
+                    .set_tokenizer(NGRAM_TOKENIZER)
+                    .set_index_option(IndexRecordOption::WithFreqs),
+            );
+            builder.add_text_field("_ngram", indexing)
+        });
+
+        let exact_indexing = TextOptions::default().set_indexing_options(
+            TextFieldIndexing::default()
+                .set_tokenizer(EXACT_TOKENIZER)
+                .set_index_option(IndexRecordOption::Basic),
+        );
+        let (exact_field, exact_path_field) = if options.exact {
+            (
+                Some(builder.add_text_field("_exact", exact_indexing.clone())),
+                Some(builder.add_text_field("_exact_path", exact_indexing)),
+            )
+        } else {
+            (None, None)
+        };
+
+        let range_fields: HashMap<String, Field> = options
+            .range_fields
+            .iter()
+            .map(|path| (path.clone(), builder.add_f64_field(&range_field_name(path), INDEXED)))
+            .collect();
+
         let schema = builder.build();
-        let index = Index::create_in_ram(schema.clone());
+        let index = match dir {
+            None => Index::create_in_ram(schema.clone()),
+            Some(dir) => {
+                std::fs::create_dir_all(dir)
+                    .with_context(|| format!("Failed to create index directory {}", dir.display()))?;
+                let directory = MmapDirectory::open(dir)
+                    .with_context(|| format!("Failed to open index directory {}", dir.display()))?;
+                Index::open_or_create(directory, schema.clone())
+                    .with_context(|| format!("Failed to open index in {}", dir.display()))?
+            }
+        };
+
+        // Same pipeline as tantivy's default tokenizer, plus the language's
+        // stopwords and stemmer. Replacing it under its own name covers every
+        // tokenized field, and queries are analyzed the same way.
+        if let Some(lang) = options.lang {
+            let language = language(lang);
+            let stopwords = StopWordFilter::new(language)
+                .with_context(|| format!("No stopword list for {:?}", language))?;
+            let analyzer = TextAnalyzer::builder(SimpleTokenizer::default())
+                .filter(RemoveLongFilter::limit(40))
+                .filter(LowerCaser)
+                .filter(stopwords)
+                .filter(Stemmer::new(language))
+                .build();
+            index.tokenizers().register(TOKENIZER, analyzer);
+        }
+
+        if let Some((min_gram, max_gram)) = options.ngram {
+            let analyzer = TextAnalyzer::builder(NgramTokenizer::all_ngrams(min_gram, max_gram)?)
+                .filter(LowerCaser)
+                .build();
+            index.tokenizers().register(NGRAM_TOKENIZER, analyzer);
+        }
 
         Ok(Engine {
             index,
@@ -64,50 +577,118 @@ impl Engine {
             pointer_field,
             file_field,
             source_field,
+            dedicated_fields,
+            ngram_field,
+            exact_field,
+            exact_path_field,
+            range_fields,
         })
     }
 
     pub fn index_records(&self, records: &[Record]) -> Result<()> {
-        let mut writer = self
+        self.replace_files(records, &[])
+    }
+
+    /// Drop every record indexed from `stale_files`, then index `records`,
+    /// in a single commit.
+    pub fn replace_files(&self, records: &[Record], stale_files: &[String]) -> Result<()> {
+        let mut writer = self.writer()?;
+        for file in stale_files {
+            writer.writer.delete_term(Term::from_field_text(self.file_field, file));
+        }
+        for record in records {
+            writer.add(record)?;
+        }
+        writer.commit()
+    }
+
+    /// A writer adding records one at a time, for input too large to
+    /// collect first.
+    pub fn writer(&self) -> Result<RecordWriter<'_>> {
+        let writer = self
             .index
             .writer(50_000_000)
             .context("Failed to create index writer")?;
+        Ok(RecordWriter {
+            engine: self,
+            writer,
+        })
+    }
 
-        for record in records {
-            let all_text = collect_all_text(&record.value);
-            let source_json = serde_json::to_string(&record.value)?;
-
-            let json_object: BTreeMap<String, schema::OwnedValue> = match &record.value {
-                Value::Object(map) => map
-                    .iter()
-                    .map(|(k, v)| (k.clone(), schema::OwnedValue::from(v.clone())))
-                    .collect(),
-                _ => {
-                    let mut m = BTreeMap::new();
-                    m.insert("_value".to_string(), schema::OwnedValue::from(record.value.clone()));
-                    m
+    fn document(&self, record: &Record) -> Result<TantivyDocument> {
+        // Dedicated fields are indexed only once, in their own field.
+        let all_text = match &record.value {
+            Value::Object(map) if !self.dedicated_fields.is_empty() => {
+                let mut texts = Vec::new();
+                for (key, value) in map {
+                    if !self.dedicated_fields.contains_key(key) {
+                        collect_text_recursive(value, &mut texts);
+                    }
                 }
-            };
+                texts.join(" ")
+            }
+            value => collect_all_text(value),
+        };
+        let source_json = serde_json::to_string(&record.value)?;
 
-            let mut doc = TantivyDocument::default();
-            doc.add_object(self.content_field, json_object);
-            doc.add_text(self.all_text_field, &all_text);
-            doc.add_text(self.pointer_field, &record.pointer);
-            doc.add_text(self.file_field, &record.file);
-            doc.add_text(self.source_field, &source_json);
+        let mut doc = TantivyDocument::default();
 
-            writer.add_document(doc)?;
-        }
+        let json_object: BTreeMap<String, schema::OwnedValue> = match &record.value {
+            Value::Object(map) => map
+                .iter()
+                .filter(|(k, v)| match self.dedicated_fields.get(*k) {
+                    Some((field, _)) => {
+                        doc.add_text(*field, collect_all_text(v));
+                        false
+                    }
+                    None => true,
+                })
+                .map(|(k, v)| (k.clone(), schema::OwnedValue::from(v.clone())))
+                .collect(),
+            _ => {
+                let mut m = BTreeMap::new();
+                m.insert("_value".to_string(), schema::OwnedValue::from(record.value.clone()));
+                m
+            }
+        };
 
-        writer.commit().context("Failed to commit index")?;
-        Ok(())
+        doc.add_object(self.content_field, json_object);
+        if let Some(ngram_field) = self.ngram_field {
+            // Dedicated fields have no grams of their own, so theirs go here.
+            if self.dedicated_fields.is_empty() {
+                doc.add_text(ngram_field, &all_text);
+            } else {
+                doc.add_text(ngram_field, collect_all_text(&record.value));
+            }
+        }
+        doc.add_text(self.all_text_field, &all_text);
+        if let (Some(exact_field), Some(exact_path_field)) = (self.exact_field, self.exact_path_field) {
+            let mut leaves = Vec::new();
+            collect_exact_values(&record.value, "", &mut leaves);
+            for (path, text) in leaves {
+                doc.add_text(exact_path_field, exact_path_term(&path, &text));
+                doc.add_text(exact_field, text);
+            }
+        }
+        for (path, field) in &self.range_fields {
+            let segments: Vec<&str> = path.split('.').collect();
+            let mut numbers = Vec::new();
+            collect_numbers_at(&record.value, &segments, &mut numbers);
+            for number in numbers {
+                doc.add_f64(*field, number);
+            }
+        }
+        doc.add_text(self.pointer_field, &record.pointer);
+        doc.add_text(self.file_field, &record.file);
+        doc.add_text(self.source_field, &source_json);
+        Ok(doc)
     }
 
     pub fn search(
         &self,
         query_str: &str,
         fields: &[String],
-        match_mode: &MatchMode,
+        options: &SearchOptions,
         limit: usize,
         offset: usize,
     ) -> Result<Vec<SearchResult>> {
@@ -120,57 +701,34 @@ impl Engine {
 
         let searcher = reader.searcher();
 
-        let query: Box<dyn tantivy::query::Query> = match match_mode {
-            MatchMode::Text | MatchMode::Exact => {
-                let search_fields = if fields.is_empty() {
-                    vec![self.all_text_field]
-                } else {
-                    vec![self.content_field]
-                };
-
-                let mut parser = QueryParser::for_index(&self.index, search_fields);
-                parser.set_conjunction_by_default();
-
-                let effective_query = if !fields.is_empty() {
-                    fields
-                        .iter()
-                        .map(|f| format!("content.{}:{}", f, query_str))
-                        .collect::<Vec<_>>()
-                        .join(" OR ")
-                } else {
-                    query_str.to_string()
-                };
+        if let Some(max_terms) = options.max_terms {
+            self.check_term_expansion(&searcher, query_str, options, max_terms)?;
+        }
 
-                parser
-                    .parse_query(&effective_query)
-                    .context("Failed to parse query")?
-            }
-            MatchMode::Fuzzy => {
-                let term = if fields.is_empty() {
-                    Term::from_field_text(self.all_text_field, &query_str.to_lowercase())
-                } else {
-                    Term::from_field_text(self.all_text_field, &query_str.to_lowercase())
-                };
+        let query = self.scored_query(query_str, fields, options)?;
 
-                Box::new(FuzzyTermQuery::new(term, 2, true))
-            }
-            MatchMode::Regex => {
-                let field = if fields.is_empty() {
-                    self.all_text_field
-                } else {
-                    self.all_text_field
+        let collector = TopDocs::with_limit(limit + offset);
+        let top_docs = match (&options.after, &options.scoring) {
+            (Some(after), scoring) => {
+                let start = self.doc_address(&searcher, &after.file, &after.pointer)?.with_context(|| {
+                    format!(
+                        "Invalid --cursor: {} {} no longer exists; start over without --cursor",
+                        after.file, after.pointer
+                    )
+                })?;
+                let scorer: SegmentScorer = match scoring {
+                    Scoring::Tf => Box::new(term_frequency_scorer(&query)),
+                    _ => Box::new(|_: &SegmentReader| -> Box<dyn FnMut(DocId, Score) -> Score> {
+                        Box::new(|_doc, score| score)
+                    }),
                 };
-
-                Box::new(
-                    RegexQuery::from_pattern(query_str, field)
-                        .context("Failed to parse regex")?,
-                )
+                let ranked = searcher.search(&query, &collector.tweak_score(ranked_after(&searcher, scorer, after.score, start)));
+                ranked.map(|docs| docs.into_iter().filter(|(score, _)| *score != Score::NEG_INFINITY).collect())
             }
-        };
-
-        let top_docs = searcher
-            .search(&query, &TopDocs::with_limit(limit + offset))
-            .context("Search failed")?;
+            (None, Scoring::Tf) => searcher.search(&query, &collector.tweak_score(term_frequency_scorer(&query))),
+            (None, _) => searcher.search(&query, &collector),
+        }
+        .context("Search failed")?;
 
         let mut results = Vec::new();
         for (i, (score, doc_address)) in top_docs.into_iter().enumerate() {
@@ -193,93 +751,2449 @@ impl Engine {
                     value,
                 },
                 score,
+                locator: None,
+                snippet: None,
+                explanation: None,
+                doc: Some(doc_address),
             });
         }
 
         Ok(results)
     }
-}
-
-fn get_stored_text(doc: &TantivyDocument, field: Field) -> String {
-    use tantivy::schema::Value as TValue;
-    doc.get_first(field)
-        .and_then(|v| v.as_str().map(|s| s.to_string()))
-        .unwrap_or_default()
-}
-
-/// Recursively collect all string values from a JSON value
-fn collect_all_text(value: &Value) -> String {
-    let mut texts = Vec::new();
-    collect_text_recursive(value, &mut texts);
-    texts.join(" ")
-}
 
-fn collect_text_recursive(value: &Value, texts: &mut Vec<String>) {
-    match value {
-        Value::String(s) => texts.push(s.clone()),
-        Value::Number(n) => texts.push(n.to_string()),
-        Value::Bool(b) => texts.push(b.to_string()),
-        Value::Array(arr) => {
-            for item in arr {
-                collect_text_recursive(item, texts);
-            }
-        }
-        Value::Object(map) => {
-            for val in map.values() {
-                collect_text_recursive(val, texts);
+    /// Fill in `SearchResult::explanation` (`--explain`) for `results`, as
+    /// returned by `search` with the same query and options. Kept apart from
+    /// `search` so only the page that is printed pays for it.
+    pub fn explain(
+        &self,
+        query_str: &str,
+        fields: &[String],
+        options: &SearchOptions,
+        results: &mut [SearchResult],
+    ) -> Result<()> {
+        let reader = self
+            .index
+            .reader_builder()
+            .reload_policy(ReloadPolicy::Manual)
+            .try_into()
+            .context("Failed to create reader")?;
+        let searcher = reader.searcher();
+        let query = self.scored_query(query_str, fields, options)?;
+        for result in results {
+            if let Some(doc) = result.doc {
+                result.explanation = Some(explain_score(&searcher, query.as_ref(), &options.scoring, result.score, doc)?);
             }
         }
-        Value::Null => {}
+        Ok(())
     }
-}
-
-/// Extract records from a JSON value, walking the tree
-pub fn extract_records(value: &Value, file: &str) -> Vec<Record> {
-    let mut records = Vec::new();
-    extract_recursive(value, "", file, &mut records);
-    records
-}
-
-fn extract_recursive(value: &Value, pointer: &str, file: &str, records: &mut Vec<Record>) {
-    match value {
-        Value::Object(map) => {
-            records.push(Record {
-                pointer: pointer.to_string(),
-                file: file.to_string(),
-                value: value.clone(),
-            });
 
-            for (key, val) in map {
-                let escaped_key = key.replace('~', "~0").replace('/', "~1");
-                let child_pointer = format!("{}/{}", pointer, escaped_key);
-                extract_recursive(val, &child_pointer, file, records);
-            }
-        }
-        Value::Array(arr) => {
-            for (i, item) in arr.iter().enumerate() {
-                let child_pointer = format!("{}/{}", pointer, i);
-                extract_recursive(item, &child_pointer, file, records);
-            }
+    /// The query `search` runs: the match mode's, with boosts and ranges
+    /// applied and, under `--scoring none`, every score flattened to 1.
+    fn scored_query(&self, query_str: &str, fields: &[String], options: &SearchOptions) -> Result<Box<dyn Query>> {
+        if options.boolean && (!matches!(options.match_mode, MatchMode::Text) || !fields.is_empty()) {
+            bail!("--bool needs text mode (-m text) and no --field; scope terms with FIELD:term instead");
         }
-        _ => {}
-    }
-}
 
-/// Deduplicate results: if a child matches, remove its ancestor
-pub fn dedup_results(results: &mut Vec<SearchResult>) {
-    let pointers: Vec<(String, String)> = results
-        .iter()
-        .map(|r| (r.record.pointer.clone(), r.record.file.clone()))
-        .collect();
+        let query = self.mode_query(query_str, fields, options)?;
+        let query = self.with_boosts(query, query_str, options)?;
+        let query = self.with_ranges(query, &options.ranges)?;
 
-    results.retain(|r| {
-        let my_pointer = &r.record.pointer;
-        let my_file = &r.record.file;
-        !pointers.iter().any(|(other_ptr, other_file)| {
-            other_ptr != my_pointer
-                && other_file == my_file
-                && other_ptr.starts_with(my_pointer.as_str())
-                && other_ptr.len() > my_pointer.len()
+        Ok(match options.scoring {
+            Scoring::None => Box::new(ConstScoreQuery::new(query, 1.0)),
+            _ => query,
+        })
+    }
+
+    /// Fill in `SearchResult::snippet` (`--highlight`): the query's words in
+    /// context, cut from the text of `shown(record)`, the record as it will
+    /// be printed, so fields projected or redacted away never appear in a
+    /// snippet. Fuzzy and regex queries match index terms rather than words
+    /// of the query, so they get none.
+    pub fn highlight(
+        &self,
+        query_str: &str,
+        match_mode: &MatchMode,
+        results: &mut [SearchResult],
+        shown: impl Fn(&Value) -> Value,
+    ) -> Result<()> {
+        if !matches!(match_mode, MatchMode::Text | MatchMode::Exact) {
+            return Ok(());
+        }
+        let reader = self
+            .index
+            .reader_builder()
+            .reload_policy(ReloadPolicy::Manual)
+            .try_into()
+            .context("Failed to create reader")?;
+        let parser = QueryParser::for_index(&self.index, vec![self.all_text_field]);
+        let (words, _) = parser.parse_query_lenient(query_str);
+        let generator = SnippetGenerator::create(&reader.searcher(), &*words, self.all_text_field)
+            .context("Failed to create snippet generator")?;
+        for result in results {
+            result.snippet = snippet(&generator, &collect_all_text(&shown(&result.record.value)));
+        }
+        Ok(())
+    }
+
+    /// Rewrite `pointer`'s array indices into `{field=value}` segments
+    /// wherever the indexed element is an object with a scalar `field`, e.g.
+    /// `/items/3/name` -> `/items/{id=abc}/name`. Other segments are kept.
+    pub fn key_locator(&self, file: &str, pointer: &str, field: &str) -> Result<String> {
+        let reader = self
+            .index
+            .reader_builder()
+            .reload_policy(ReloadPolicy::Manual)
+            .try_into()
+            .context("Failed to create reader")?;
+        let searcher = reader.searcher();
+
+        let segments: Vec<&str> = pointer.split('/').skip(1).collect();
+        let mut locator = String::new();
+        for (i, seg) in segments.iter().enumerate() {
+            let prefix = |end: usize| -> String {
+                segments[..end].iter().map(|s| format!("/{}", s)).collect()
+            };
+
+            let mut out = seg.to_string();
+            if seg.parse::<usize>().is_ok() {
+                let parent_has_key = matches!(
+                    self.stored_value(&searcher, file, &prefix(i))?,
+                    Some(Value::Object(map)) if map.contains_key(*seg)
+                );
+                if !parent_has_key {
+                    if let Some(element) = self.stored_value(&searcher, file, &prefix(i + 1))? {
+                        if let Some(key) = element.get(field).and_then(|v| key_segment(field, v)) {
+                            out = key.replace('~', "~0").replace('/', "~1");
+                        }
+                    }
+                }
+            }
+            locator.push('/');
+            locator.push_str(&out);
+        }
+        Ok(locator)
+    }
+
+    /// Tokens the index would hold for `value`'s text: the n-gram analyzer's
+    /// when the engine was built with `IndexOptions::ngram`, each scalar
+    /// whole (as `--match exact` compares them) with `IndexOptions::exact`,
+    /// otherwise the default tokenizer's. Duplicates are kept, in stream
+    /// order.
+    pub fn analyze(&self, value: &Value) -> Result<Vec<String>> {
+        if self.ngram_field.is_none() && self.exact_field.is_some() {
+            let mut leaves = Vec::new();
+            collect_exact_values(value, "", &mut leaves);
+            return Ok(leaves.into_iter().map(|(_, text)| text).collect());
+        }
+        let name = if self.ngram_field.is_some() { NGRAM_TOKENIZER } else { TOKENIZER };
+        let mut analyzer = self
+            .index
+            .tokenizers()
+            .get(name)
+            .with_context(|| format!("Tokenizer {:?} is not registered", name))?;
+
+        let text = collect_all_text(value);
+        let mut tokens = Vec::new();
+        let mut stream = analyzer.token_stream(&text);
+        while stream.advance() {
+            tokens.push(stream.token().text.clone());
+        }
+        Ok(tokens)
+    }
+
+    /// Number of indexed records.
+    pub fn doc_count(&self) -> Result<usize> {
+        let reader = self
+            .index
+            .reader_builder()
+            .reload_policy(ReloadPolicy::Manual)
+            .try_into()
+            .context("Failed to create reader")?;
+        Ok(reader.searcher().num_docs() as usize)
+    }
+
+    /// The query for `query_str` in the selected match mode, limited to
+    /// `fields` when any are given.
+    fn mode_query(&self, query_str: &str, fields: &[String], options: &SearchOptions) -> Result<Box<dyn Query>> {
+        Ok(match options.match_mode {
+            MatchMode::Exact => self.exact_query(query_str, fields)?,
+            MatchMode::Text => {
+                // Dedicated fields are not part of `_all`, so unscoped
+                // queries search them too; phrases only where they can.
+                let search_fields = if fields.is_empty() {
+                    let phrase = query_str.contains('"');
+                    std::iter::once(self.all_text_field)
+                        .chain(
+                            self.dedicated_fields
+                                .values()
+                                .filter(|(_, indexing)| !phrase || *indexing == FieldIndexing::Positions)
+                                .map(|(field, _)| *field),
+                        )
+                        .collect()
+                } else {
+                    vec![self.content_field]
+                };
+
+                let mut parser = QueryParser::for_index(&self.index, search_fields);
+                parser.set_conjunction_by_default();
+
+                let effective_query = if !fields.is_empty() {
+                    fields
+                        .iter()
+                        .map(|f| match self.dedicated_fields.get(f) {
+                            Some((_, indexing)) => {
+                                check_phrase_support(f, *indexing, query_str)?;
+                                field_scoped_query(&dedicated_field_name(f), query_str)
+                            }
+                            None => field_scoped_query(&format!("content.{}", f), query_str),
+                        })
+                        .map(|q| q.map(|q| format!("({})", q)))
+                        .collect::<Result<Vec<_>>>()?
+                        .join(" OR ")
+                } else if options.boolean {
+                    qualify_fields(query_str, |f| match self.dedicated_fields.get(f) {
+                        Some(_) => escape_field_name(&dedicated_field_name(f)),
+                        None => format!("content.{}", escape_field_name(f)),
+                    })
+                } else {
+                    query_str.to_string()
+                };
+
+                parser
+                    .parse_query(&fold_and_not(&effective_query))
+                    .context("Failed to parse query")?
+            }
+            MatchMode::Fuzzy => self.fuzzy_query(query_str, options)?,
+            MatchMode::Regex => self.regex_query(query_str, fields)?,
+            MatchMode::Ngram if !fields.is_empty() => {
+                bail!("-m ngram searches the text of whole records and can't be limited with --field; drop --field")
+            }
+            MatchMode::Ngram => self.ngram_query(query_str)?,
+            MatchMode::Prefix => self.prefix_query(query_str, fields)?,
+        })
+    }
+
+    /// Add a `--boost` clause per field: the same query limited to that
+    /// field, weighted, so records matching there score higher. The boosts
+    /// only add to the score; the base query alone decides what matches.
+    fn with_boosts(&self, query: Box<dyn Query>, query_str: &str, options: &SearchOptions) -> Result<Box<dyn Query>> {
+        if options.boosts.is_empty() {
+            return Ok(query);
+        }
+        if matches!(options.match_mode, MatchMode::Fuzzy | MatchMode::Ngram) || options.boolean {
+            bail!("--boost needs text, exact, regex or prefix mode, without --bool");
+        }
+        let mut clauses: Vec<(Occur, Box<dyn Query>)> = vec![(Occur::Must, query)];
+        for boost in &options.boosts {
+            let scoped = self.mode_query(query_str, std::slice::from_ref(&boost.field), options)?;
+            clauses.push((Occur::Should, Box::new(BoostQuery::new(scoped, boost.weight))));
+        }
+        Ok(Box::new(BooleanQuery::new(clauses)))
+    }
+
+    /// Intersect `query` with a numeric range query per `--range`.
+    fn with_ranges(&self, query: Box<dyn Query>, ranges: &[NumericRange]) -> Result<Box<dyn Query>> {
+        if ranges.is_empty() {
+            return Ok(query);
+        }
+        let mut clauses: Vec<(Occur, Box<dyn Query>)> = vec![(Occur::Must, query)];
+        for range in ranges {
+            if !self.range_fields.contains_key(&range.field) {
+                bail!(
+                    "Range field {:?} was not indexed; create the engine with IndexOptions::range_fields",
+                    range.field
+                );
+            }
+            let bound = |value: Option<f64>| value.map_or(Bound::Unbounded, Bound::Included);
+            let query = RangeQuery::new_f64_bounds(
+                range_field_name(&range.field),
+                bound(range.min),
+                bound(range.max),
+            );
+            clauses.push((Occur::Must, Box::new(query)));
+        }
+        Ok(Box::new(BooleanQuery::new(clauses)))
+    }
+
+    /// The query must equal a whole scalar value, case included: any value
+    /// in the record, or with `fields`, a value at one of those paths.
+    fn exact_query(&self, query_str: &str, fields: &[String]) -> Result<Box<dyn Query>> {
+        let (exact_field, exact_path_field) = self
+            .exact_field
+            .zip(self.exact_path_field)
+            .context("The exact-match fields were not built; create the engine with IndexOptions::exact")?;
+        let term_query = |field: Field, text: &str| -> Box<dyn Query> {
+            Box::new(TermQuery::new(
+                Term::from_field_text(field, text),
+                IndexRecordOption::Basic,
+            ))
+        };
+        Ok(match fields {
+            [] => term_query(exact_field, query_str),
+            [field] => term_query(exact_path_field, &exact_path_term(field, query_str)),
+            _ => Box::new(BooleanQuery::new(
+                fields
+                    .iter()
+                    .map(|f| {
+                        (
+                            Occur::Should,
+                            term_query(exact_path_field, &exact_path_term(f, query_str)),
+                        )
+                    })
+                    .collect(),
+            )),
+        })
+    }
+
+    /// One fuzzy clause per query word against `_all`, any of which may match
+    /// (all of them with `fuzzy_and`).
+    fn fuzzy_query(&self, query_str: &str, options: &SearchOptions) -> Result<Box<dyn Query>> {
+        let occur = if options.fuzzy_and { Occur::Must } else { Occur::Should };
+        let fields = self.unscoped_fields();
+        let queries = self
+            .fuzzy_words(query_str)?
+            .into_iter()
+            .map(|text| {
+                let per_field = fields
+                    .iter()
+                    .map(|&field| -> Box<dyn Query> {
+                        match options.fuzzy_prefix {
+                            Some(prefix_len) => Box::new(FuzzyPrefixQuery {
+                                field,
+                                text: text.clone(),
+                                distance: options.fuzzy_distance,
+                                prefix_len,
+                            }),
+                            None => {
+                                let term = Term::from_field_text(field, &text);
+                                Box::new(FuzzyTermQuery::new(term, options.fuzzy_distance, true))
+                            }
+                        }
+                    })
+                    .collect();
+                combine(Occur::Should, per_field)
+            })
+            .collect();
+        Ok(combine(occur, queries))
+    }
+
+    /// The fields a query without `--field` searches: `_all`, plus every
+    /// dedicated field, whose values `_all` leaves out.
+    fn unscoped_fields(&self) -> Vec<Field> {
+        std::iter::once(self.all_text_field)
+            .chain(self.dedicated_fields.values().map(|(field, _)| *field))
+            .collect()
+    }
+
+    /// Every query word must be the start of an indexed word: of any value,
+    /// or with `fields`, of a value in one of those fields (all the words in
+    /// the same field, as in text mode).
+    fn prefix_query(&self, query_str: &str, fields: &[String]) -> Result<Box<dyn Query>> {
+        let words = self.fuzzy_words(query_str)?;
+        let all_words = |term_for: &dyn Fn(&str) -> Term| -> Box<dyn Query> {
+            let mut clauses: Vec<(Occur, Box<dyn Query>)> = words
+                .iter()
+                .map(|word| {
+                    let query: Box<dyn Query> = Box::new(FuzzyTermQuery::new_prefix(term_for(word), 0, false));
+                    (Occur::Must, query)
+                })
+                .collect();
+            if clauses.len() == 1 {
+                return clauses.remove(0).1;
+            }
+            Box::new(BooleanQuery::new(clauses))
+        };
+
+        if fields.is_empty() {
+            // Each word may start a word of any field, dedicated ones included.
+            let fields = self.unscoped_fields();
+            let queries = words
+                .iter()
+                .map(|word| {
+                    let per_field = fields
+                        .iter()
+                        .map(|&field| -> Box<dyn Query> {
+                            Box::new(FuzzyTermQuery::new_prefix(Term::from_field_text(field, word), 0, false))
+                        })
+                        .collect();
+                    combine(Occur::Should, per_field)
+                })
+                .collect();
+            return Ok(combine(Occur::Must, queries));
+        }
+        let mut per_field: Vec<(Occur, Box<dyn Query>)> = fields
+            .iter()
+            .map(|field| (Occur::Should, all_words(&|word| self.field_term(field, word))))
+            .collect();
+        if per_field.len() == 1 {
+            return Ok(per_field.remove(0).1);
+        }
+        Ok(Box::new(BooleanQuery::new(per_field)))
+    }
+
+    /// The pattern must match a whole indexed word: of any value, or with
+    /// `fields`, of a value in one of those fields.
+    fn regex_query(&self, query_str: &str, fields: &[String]) -> Result<Box<dyn Query>> {
+        let pattern = regex_pattern(query_str);
+        let regex_on = |field: Field, pattern: &str| -> Result<Box<dyn Query>> {
+            Ok(Box::new(
+                RegexQuery::from_pattern(pattern, field).context("Failed to parse regex")?,
+            ))
+        };
+        if fields.is_empty() {
+            let queries = self
+                .unscoped_fields()
+                .into_iter()
+                .map(|field| regex_on(field, &pattern))
+                .collect::<Result<_>>()?;
+            return Ok(combine(Occur::Should, queries));
+        }
+
+        let mut clauses = fields
+            .iter()
+            .map(|field| {
+                let query = match self.dedicated_fields.get(field) {
+                    Some((dedicated, _)) => regex_on(*dedicated, &pattern)?,
+                    None => {
+                        // `content` terms start with the path and value
+                        // type; take them from a term of that path, minus
+                        // its text, so the pattern only sees the word.
+                        const PROBE: &str = "x";
+                        let term = self.field_term(field, PROBE);
+                        let bytes = term.serialized_value_bytes();
+                        let path = std::str::from_utf8(&bytes[..bytes.len() - PROBE.len()])
+                            .with_context(|| format!("Invalid field {:?}", field))?;
+                        // Grouped, so a top-level `|` can't escape the path.
+                        regex_on(self.content_field, &format!("{}(?:{})", regex::escape(path), pattern))?
+                    }
+                };
+                Ok((Occur::Should, query))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        if clauses.len() == 1 {
+            return Ok(clauses.remove(0).1);
+        }
+        Ok(Box::new(BooleanQuery::new(clauses)))
+    }
+
+    /// The index term for the single indexed `word` at the dotted path
+    /// `field`: in its dedicated field when it has one, otherwise under that
+    /// path in `content`.
+    /// The term is always a string term, even for a word that looks like a
+    /// number, since prefix and regex queries only walk string terms.
+    fn field_term(&self, field: &str, word: &str) -> Term {
+        if let Some((dedicated, _)) = self.dedicated_fields.get(field) {
+            return Term::from_field_text(*dedicated, word);
+        }
+        let mut term = Term::with_capacity(field.len() + word.len() + 8);
+        let mut writer = JsonTermWriter::from_field_and_json_path(self.content_field, field, false, &mut term);
+        writer.set_str(word);
+        term
+    }
+
+    /// The query split into words the way values are indexed: on
+    /// non-alphanumeric characters, lowercased, without repeats.
+    fn fuzzy_words(&self, query_str: &str) -> Result<Vec<String>> {
+        let mut analyzer = self
+            .index
+            .tokenizers()
+            .get(TOKENIZER)
+            .context("Default tokenizer is not registered")?;
+        let mut words: Vec<String> = Vec::new();
+        let mut stream = analyzer.token_stream(query_str);
+        while stream.advance() {
+            let text = &stream.token().text;
+            if !words.contains(text) {
+                words.push(text.clone());
+            }
+        }
+        if words.is_empty() {
+            bail!("Query {:?} has no words to match", query_str);
+        }
+        Ok(words)
+    }
+
+    /// Every gram of the query must occur in the record's text, which
+    /// approximates a substring match.
+    fn ngram_query(&self, query_str: &str) -> Result<Box<dyn Query>> {
+        let field = self
+            .ngram_field
+            .context("The n-gram field was not built; create the engine with IndexOptions::ngram")?;
+        let mut analyzer = self
+            .index
+            .tokenizers()
+            .get(NGRAM_TOKENIZER)
+            .context("N-gram tokenizer is not registered")?;
+
+        let mut grams: Vec<String> = Vec::new();
+        let mut stream = analyzer.token_stream(query_str);
+        while stream.advance() {
+            let text = &stream.token().text;
+            if !grams.contains(text) {
+                grams.push(text.clone());
+            }
+        }
+        if grams.is_empty() {
+            bail!(
+                "Query {:?} is shorter than --ngram-min; use a longer query or lower --ngram-min",
+                query_str
+            );
+        }
+
+        let clauses: Vec<(Occur, Box<dyn Query>)> = grams
+            .iter()
+            .map(|gram| {
+                let term = Term::from_field_text(field, gram);
+                let query: Box<dyn Query> = Box::new(TermQuery::new(term, IndexRecordOption::WithFreqs));
+                (Occur::Must, query)
+            })
+            .collect();
+        Ok(Box::new(BooleanQuery::new(clauses)))
+    }
+
+    /// Replace each result with the ancestor `ascend` selects, then drop
+    /// repeats of the same ancestor (the first, highest ranked, is kept).
+    /// The ancestor value is read from the nearest indexed record at or
+    /// above it, so ancestors that are arrays resolve too. An ancestor with
+    /// no record at or above it (past the root of a document whose root
+    /// isn't a record) is clamped to the highest one that resolves.
+    pub fn ascend_results(&self, results: &mut Vec<SearchResult>, ascend: Ascend) -> Result<()> {
+        let reader = self
+            .index
+            .reader_builder()
+            .reload_policy(ReloadPolicy::Manual)
+            .try_into()
+            .context("Failed to create reader")?;
+        let searcher = reader.searcher();
+
+        let mut seen = std::collections::HashSet::new();
+        let mut ascended = Vec::with_capacity(results.len());
+        for mut result in results.drain(..) {
+            let segments: Vec<&str> = result.record.pointer.split('/').skip(1).collect();
+            let mut keep = match ascend {
+                Ascend::Levels(n) => segments.len().saturating_sub(n),
+                Ascend::TopLevel => segments.len().min(1),
+            };
+            let (target, value) = loop {
+                let target: String = segments[..keep].iter().map(|s| format!("/{}", s)).collect();
+                if keep >= segments.len() {
+                    break (target, None);
+                }
+                if let Some(value) = self.value_at(&searcher, &result.record.file, &target)? {
+                    break (target, Some(value));
+                }
+                keep += 1;
+            };
+
+            if !seen.insert((result.record.file.clone(), target.clone())) {
+                continue;
+            }
+            if let Some(value) = value {
+                result.record.value = value;
+                result.record.pointer = target;
+            }
+            ascended.push(result);
+        }
+
+        *results = ascended;
+        Ok(())
+    }
+
+    /// Value at `pointer`, taken from the nearest indexed record at or above it.
+    fn value_at(
+        &self,
+        searcher: &tantivy::Searcher,
+        file: &str,
+        pointer: &str,
+    ) -> Result<Option<Value>> {
+        let mut base = pointer.to_string();
+        loop {
+            if let Some(value) = self.stored_value(searcher, file, &base)? {
+                return Ok(value.pointer(&pointer[base.len()..]).cloned());
+            }
+            match base.rfind('/') {
+                Some(idx) => base.truncate(idx),
+                None => return Ok(None),
+            }
+        }
+    }
+
+    /// Source of the record indexed at `file` + `pointer`, if any.
+    fn stored_value(
+        &self,
+        searcher: &tantivy::Searcher,
+        file: &str,
+        pointer: &str,
+    ) -> Result<Option<Value>> {
+        match self.doc_address(searcher, file, pointer)? {
+            Some(address) => {
+                let doc: TantivyDocument = searcher.doc(address)?;
+                let source = get_stored_text(&doc, self.source_field);
+                Ok(serde_json::from_str(&source).ok())
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Address of the record indexed at `file` + `pointer`, if any.
+    fn doc_address(&self, searcher: &tantivy::Searcher, file: &str, pointer: &str) -> Result<Option<DocAddress>> {
+        let query = BooleanQuery::intersection(vec![
+            Box::new(TermQuery::new(
+                Term::from_field_text(self.file_field, file),
+                IndexRecordOption::Basic,
+            )),
+            Box::new(TermQuery::new(
+                Term::from_field_text(self.pointer_field, pointer),
+                IndexRecordOption::Basic,
+            )),
+        ]);
+        let top = searcher.search(&query, &TopDocs::with_limit(1))?;
+        Ok(top.first().map(|(_, address)| *address))
+    }
+}
+
+/// Rewrite `query_str` so every term and phrase in it is searched in
+/// `field`, not just the first one: `Ada OR "Grace Hopper"` becomes
+/// `f:Ada OR f:"Grace Hopper"`. Operators, `+`/`-` prefixes and parentheses
+/// are kept. Characters of the field name that the query syntax treats
+/// specially are escaped; dots still separate the segments of a nested path.
+/// An unquoted term with a colon (`status:active`) would name a second
+/// field, so it is rejected; quoted (`"http://x"`) it is searched as text.
+fn field_scoped_query(field: &str, query_str: &str) -> Result<String> {
+    let prefix = format!("{}:", escape_field_name(field));
+
+    let mut scoped = String::with_capacity(query_str.len() * 2);
+    let mut chars = query_str.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        if c.is_whitespace() || matches!(c, '(' | ')' | '+' | '-') {
+            scoped.push(c);
+            chars.next();
+            continue;
+        }
+        let mut term = String::new();
+        if c == '"' {
+            term.push(c);
+            chars.next();
+            for c in chars.by_ref() {
+                term.push(c);
+                if c == '"' {
+                    break;
+                }
+            }
+        } else {
+            while let Some(&c) = chars.peek() {
+                if c.is_whitespace() || matches!(c, '(' | ')') {
+                    break;
+                }
+                term.push(c);
+                chars.next();
+            }
+        }
+        if !term.starts_with('"') && term.contains(':') {
+            bail!(
+                "Query term `{}` names a field, but --field already scopes every term; drop the prefix, or quote the term to search for it as text",
+                term
+            );
+        }
+        if !matches!(term.as_str(), "AND" | "OR" | "NOT") {
+            scoped.push_str(&prefix);
+        }
+        scoped.push_str(&term);
+    }
+    Ok(scoped)
+}
+
+/// Escape the characters of a field name that the query syntax treats
+/// specially; dots are left alone to separate nested path segments.
+fn escape_field_name(field: &str) -> String {
+    const SPECIAL: &[char] = &['+', '^', '`', ':', '{', '}', '"', '[', ']', '(', ')', '!', '\\', '*', ' '];
+    let mut escaped = String::with_capacity(field.len());
+    for c in field.chars() {
+        if SPECIAL.contains(&c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// Rewrite the `FIELD:` qualifiers of a `--bool` query to index field names
+/// with `resolve`, which gets the field unescaped: `status:active AND NOT
+/// archived` becomes `content.status:active AND NOT archived`. Unqualified
+/// terms, phrases and operators are kept as written.
+fn qualify_fields(query_str: &str, resolve: impl Fn(&str) -> String) -> String {
+    let mut qualified = String::with_capacity(query_str.len() * 2);
+    let mut chars = query_str.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        if c.is_whitespace() || matches!(c, '(' | ')' | '+' | '-' | '"') {
+            qualified.push(c);
+            chars.next();
+            if c == '"' {
+                for c in chars.by_ref() {
+                    qualified.push(c);
+                    if c == '"' {
+                        break;
+                    }
+                }
+            }
+            continue;
+        }
+        let mut field = String::new();
+        let mut escaped = false;
+        while let Some(&c) = chars.peek() {
+            if !escaped && (c.is_whitespace() || matches!(c, '(' | ')' | '"' | ':')) {
+                break;
+            }
+            escaped = !escaped && c == '\\';
+            field.push(c);
+            chars.next();
+        }
+        if chars.peek() == Some(&':') {
+            chars.next();
+            qualified.push_str(&resolve(&unescape(&field)));
+            qualified.push(':');
+            // The value is copied as is, colons included.
+            let mut in_phrase = false;
+            while let Some(&c) = chars.peek() {
+                if !in_phrase && (c.is_whitespace() || matches!(c, '(' | ')')) {
+                    break;
+                }
+                in_phrase ^= c == '"';
+                qualified.push(c);
+                chars.next();
+            }
+        } else {
+            qualified.push_str(&field);
+        }
+    }
+    qualified
+}
+
+/// Turn `a AND NOT b` into `a AND -b`. tantivy parses the former into a
+/// clause holding only the negation, which matches nothing, so the whole
+/// conjunction would come back empty.
+fn fold_and_not(query_str: &str) -> String {
+    let mut folded = String::with_capacity(query_str.len());
+    let mut in_phrase = false;
+    let mut after_and = false;
+    let mut rest = query_str;
+    while let Some(c) = rest.chars().next() {
+        if !in_phrase && (c.is_whitespace() || c == '(') {
+            folded.push(c);
+            rest = &rest[c.len_utf8()..];
+            after_and &= c.is_whitespace();
+            continue;
+        }
+        let end = rest
+            .char_indices()
+            .find(|&(i, c)| {
+                if c == '"' {
+                    in_phrase = !in_phrase;
+                }
+                !in_phrase && i > 0 && (c.is_whitespace() || c == ')')
+            })
+            .map_or(rest.len(), |(i, _)| i);
+        let word = &rest[..end];
+        rest = &rest[end..];
+        if after_and && word == "NOT" && rest.starts_with(char::is_whitespace) {
+            folded.push('-');
+            rest = rest.trim_start();
+            after_and = false;
+            continue;
+        }
+        after_and = !in_phrase && word == "AND";
+        folded.push_str(word);
+    }
+    folded
+}
+
+fn unescape(text: &str) -> String {
+    let mut unescaped = String::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => unescaped.extend(chars.next()),
+            c => unescaped.push(c),
+        }
+    }
+    unescaped
+}
+
+fn dedicated_field_name(field: &str) -> String {
+    format!("_field_{}", field)
+}
+
+fn language(lang: Lang) -> Language {
+    match lang {
+        Lang::En => Language::English,
+        Lang::De => Language::German,
+        Lang::Es => Language::Spanish,
+        Lang::Fr => Language::French,
+        Lang::It => Language::Italian,
+        Lang::Nl => Language::Dutch,
+        Lang::Pt => Language::Portuguese,
+        Lang::Ru => Language::Russian,
+        Lang::Sv => Language::Swedish,
+    }
+}
+
+fn range_field_name(path: &str) -> String {
+    format!("_range_{}", path)
+}
+
+/// Collect the numbers at a dotted path, looking through arrays on the way.
+/// Values of any other type are skipped.
+fn collect_numbers_at(value: &Value, segments: &[&str], out: &mut Vec<f64>) {
+    match (segments.split_first(), value) {
+        (None, Value::Number(n)) => out.extend(n.as_f64()),
+        (_, Value::Array(items)) => {
+            for item in items {
+                collect_numbers_at(item, segments, out);
+            }
+        }
+        (Some((first, rest)), Value::Object(map)) => {
+            if let Some(child) = map.get(*first) {
+                collect_numbers_at(child, rest, out);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Phrase queries need positions; say so instead of surfacing tantivy's
+/// schema error.
+fn check_phrase_support(field: &str, indexing: FieldIndexing, query_str: &str) -> Result<()> {
+    if indexing != FieldIndexing::Positions && query_str.contains('"') {
+        bail!(
+            "Phrase queries are not supported on field {:?}: it is indexed without positions (--field-option {}:{}); search for the terms without quotes",
+            field,
+            field,
+            if indexing == FieldIndexing::Freqs { "freqs" } else { "basic" }
+        );
+    }
+    Ok(())
+}
+
+/// Tantivy's breakdown of how `doc` got its score. `--scoring tf` replaces
+/// the query's score after the fact, so it gets only the total.
+fn explain_score(
+    searcher: &Searcher,
+    query: &dyn Query,
+    scoring: &Scoring,
+    score: Score,
+    doc: DocAddress,
+) -> Result<Value> {
+    let explanation = match scoring {
+        Scoring::Tf => Explanation::new("sum of matched term frequencies (--scoring tf)", score),
+        _ => query.explain(searcher, doc).context("Failed to explain score")?,
+    };
+    Ok(serde_json::to_value(explanation)?)
+}
+
+/// Per-segment score tweaker, as `TopDocs::tweak_score` takes it.
+type SegmentScorer = Box<dyn Fn(&SegmentReader) -> Box<dyn FnMut(DocId, Score) -> Score> + Send + Sync>;
+
+/// Wrap `scorer` so that matches ranked at or before `start` (which scored
+/// `score`) sink to negative infinity, for the caller to drop. `TopDocs`
+/// ranks by score, then by ascending address, so whatever keeps its score
+/// comes after `start` in that order.
+fn ranked_after(
+    searcher: &Searcher,
+    scorer: SegmentScorer,
+    score: Score,
+    start: DocAddress,
+) -> impl Fn(&SegmentReader) -> Box<dyn FnMut(DocId, Score) -> Score> + Send + Sync {
+    let segments: Vec<_> = searcher.segment_readers().iter().map(|r| r.segment_id()).collect();
+    move |segment_reader: &SegmentReader| {
+        let ord = segments
+            .iter()
+            .position(|id| *id == segment_reader.segment_id())
+            .unwrap_or(usize::MAX) as u32;
+        let mut inner = scorer(segment_reader);
+        Box::new(move |doc, raw| {
+            let tweaked = inner(doc, raw);
+            let before = tweaked > score || (tweaked == score && DocAddress::new(ord, doc) <= start);
+            if before {
+                Score::NEG_INFINITY
+            } else {
+                tweaked
+            }
+        })
+    }
+}
+
+/// Build a score tweaker that replaces BM25 with the summed frequency of the
+/// query's terms in each document. Queries without explicit terms (fuzzy,
+/// regex) keep their original score.
+fn term_frequency_scorer(
+    query: &dyn Query,
+) -> impl Fn(&SegmentReader) -> Box<dyn FnMut(DocId, Score) -> Score> + Send + Sync {
+    let mut terms: Vec<Term> = Vec::new();
+    query.query_terms(&mut |term, _| {
+        if !terms.contains(term) {
+            terms.push(term.clone());
+        }
+    });
+
+    move |segment_reader: &SegmentReader| {
+        let mut postings: Vec<SegmentPostings> = terms
+            .iter()
+            .filter_map(|term| {
+                segment_reader
+                    .inverted_index(term.field())
+                    .ok()?
+                    .read_postings(term, IndexRecordOption::WithFreqs)
+                    .ok()?
+            })
+            .collect();
+
+        if postings.is_empty() {
+            return Box::new(|_doc, score| score);
+        }
+
+        Box::new(move |doc, _score| {
+            let mut tf = 0;
+            for p in postings.iter_mut() {
+                if p.doc() < doc {
+                    p.seek(doc);
+                }
+                if p.doc() == doc {
+                    tf += p.term_freq();
+                }
+            }
+            tf as Score
+        })
+    }
+}
+
+impl Engine {
+    /// Count the index terms a fuzzy or regex query would expand to and bail
+    /// once the count passes `max_terms`. Other match modes don't expand.
+    fn check_term_expansion(
+        &self,
+        searcher: &tantivy::Searcher,
+        query_str: &str,
+        options: &SearchOptions,
+        max_terms: usize,
+    ) -> Result<()> {
+        let expanded = match options.match_mode {
+            MatchMode::Fuzzy => {
+                let mut most = 0;
+                for word in self.fuzzy_words(query_str)? {
+                    let automaton =
+                        DfaAutomaton::new(&word, options.fuzzy_distance, options.fuzzy_prefix.unwrap_or(0));
+                    most = most.max(self.count_terms(searcher, &automaton, max_terms)?);
+                }
+                most
+            }
+            MatchMode::Regex => {
+                let regex = tantivy_fst::Regex::new(&regex_pattern(query_str))
+                    .map_err(|e| anyhow::anyhow!("Failed to parse regex: {}", e))?;
+                self.count_terms(searcher, &regex, max_terms)?
+            }
+            MatchMode::Prefix => {
+                let mut most = 0;
+                for word in self.fuzzy_words(query_str)? {
+                    let starts_with = tantivy_fst::Regex::new(&format!("{}.*", regex::escape(&word)))
+                        .map_err(|e| anyhow::anyhow!("Failed to build prefix automaton: {}", e))?;
+                    most = most.max(self.count_terms(searcher, &starts_with, max_terms)?);
+                }
+                most
+            }
+            MatchMode::Text | MatchMode::Exact | MatchMode::Ngram => return Ok(()),
+        };
+
+        if expanded > max_terms {
+            bail!(
+                "Query {:?} expands to more than {} index terms (--max-terms); use a more specific query",
+                query_str,
+                max_terms
+            );
+        }
+        Ok(())
+    }
+
+    /// Count `_all` terms accepted by `automaton`, stopping just past `max`.
+    fn count_terms<A: tantivy_fst::Automaton>(
+        &self,
+        searcher: &tantivy::Searcher,
+        automaton: &A,
+        max: usize,
+    ) -> Result<usize>
+    where
+        A::State: Clone,
+    {
+        let mut count = 0;
+        for segment_reader in searcher.segment_readers() {
+            let inverted_index = segment_reader.inverted_index(self.all_text_field)?;
+            let mut stream = inverted_index.terms().search(automaton).into_stream()?;
+            while stream.advance() {
+                count += 1;
+                if count > max {
+                    return Ok(count);
+                }
+            }
+        }
+        Ok(count)
+    }
+}
+
+/// Levenshtein DFA adapted to the term dictionary's automaton interface,
+/// mirroring how tantivy runs `FuzzyTermQuery`, that also rejects terms not
+/// starting with `prefix`. The state is the DFA state and the number of bytes
+/// read so far.
+struct DfaAutomaton {
+    dfa: DFA,
+    prefix: Vec<u8>,
+}
+
+impl DfaAutomaton {
+    /// Accept terms within `distance` edits of `text` whose first
+    /// `prefix_len` characters equal those of `text`.
+    fn new(text: &str, distance: u8, prefix_len: usize) -> Self {
+        let prefix = text.chars().take(prefix_len).collect::<String>().into_bytes();
+        DfaAutomaton {
+            dfa: LevenshteinAutomatonBuilder::new(distance, true).build_dfa(text),
+            prefix,
+        }
+    }
+}
+
+impl tantivy_fst::Automaton for DfaAutomaton {
+    type State = (u32, usize);
+
+    fn start(&self) -> (u32, usize) {
+        (self.dfa.initial_state(), 0)
+    }
+
+    fn is_match(&self, state: &(u32, usize)) -> bool {
+        state.1 >= self.prefix.len() && matches!(self.dfa.distance(state.0), Distance::Exact(_))
+    }
+
+    fn can_match(&self, state: &(u32, usize)) -> bool {
+        state.0 != SINK_STATE
+    }
+
+    fn accept(&self, &(state, read): &(u32, usize), byte: u8) -> (u32, usize) {
+        match self.prefix.get(read) {
+            Some(&expected) if expected != byte => (SINK_STATE, read + 1),
+            _ => (self.dfa.transition(state, byte), read + 1),
+        }
+    }
+}
+
+/// Fuzzy match on one term with an exact prefix (`--fuzzy-prefix`); like
+/// `FuzzyTermQuery`, every match scores the same.
+#[derive(Clone, Debug)]
+struct FuzzyPrefixQuery {
+    field: Field,
+    text: String,
+    distance: u8,
+    prefix_len: usize,
+}
+
+impl Query for FuzzyPrefixQuery {
+    fn weight(&self, _: tantivy::query::EnableScoring<'_>) -> tantivy::Result<Box<dyn tantivy::query::Weight>> {
+        let automaton = DfaAutomaton::new(&self.text, self.distance, self.prefix_len);
+        Ok(Box::new(AutomatonWeight::new(self.field, automaton)))
+    }
+}
+
+fn get_stored_text(doc: &TantivyDocument, field: Field) -> String {
+    use tantivy::schema::Value as TValue;
+    doc.get_first(field)
+        .and_then(|v| v.as_str().map(|s| s.to_string()))
+        .unwrap_or_default()
+}
+
+/// Recursively collect all string values from a JSON value
+/// `queries` joined with `occur`, or the only one as is.
+fn combine(occur: Occur, mut queries: Vec<Box<dyn Query>>) -> Box<dyn Query> {
+    if queries.len() == 1 {
+        return queries.remove(0);
+    }
+    Box::new(BooleanQuery::new(queries.into_iter().map(|query| (occur, query)).collect()))
+}
+
+/// The highlighted fragment of `text`, with `...` where text was cut off.
+fn snippet(generator: &SnippetGenerator, text: &str) -> Option<String> {
+    let snippet = generator.snippet(text);
+    let fragment = snippet.fragment().trim();
+    if fragment.is_empty() {
+        return None;
+    }
+    let before = if text.trim_start().starts_with(fragment) { "" } else { "..." };
+    let after = if text.trim_end().ends_with(fragment) { "" } else { "..." };
+    Some(format!("{}{}{}", before, fragment, after))
+}
+
+fn collect_all_text(value: &Value) -> String {
+    let mut texts = Vec::new();
+    collect_text_recursive(value, &mut texts);
+    texts.join(" ")
+}
+
+/// Collect every scalar under `value` as text, with the dotted path of
+/// object keys leading to it. Array indices are not part of the path, so
+/// `tags.name` covers every element of `tags`, as with `--field`.
+fn collect_exact_values(value: &Value, path: &str, out: &mut Vec<(String, String)>) {
+    match value {
+        Value::String(s) => out.push((path.to_string(), s.clone())),
+        Value::Number(n) => out.push((path.to_string(), n.to_string())),
+        Value::Bool(b) => out.push((path.to_string(), b.to_string())),
+        Value::Array(arr) => {
+            for item in arr {
+                collect_exact_values(item, path, out);
+            }
+        }
+        Value::Object(map) => {
+            for (key, val) in map {
+                let child = if path.is_empty() {
+                    key.clone()
+                } else {
+                    format!("{}.{}", path, key)
+                };
+                collect_exact_values(val, &child, out);
+            }
+        }
+        Value::Null => {}
+    }
+}
+
+fn exact_path_term(path: &str, text: &str) -> String {
+    format!("{}{}{}", path, EXACT_PATH_SEPARATOR, text)
+}
+
+fn collect_text_recursive(value: &Value, texts: &mut Vec<String>) {
+    match value {
+        Value::String(s) => texts.push(s.clone()),
+        Value::Number(n) => texts.push(n.to_string()),
+        Value::Bool(b) => texts.push(b.to_string()),
+        Value::Array(arr) => {
+            for item in arr {
+                collect_text_recursive(item, texts);
+            }
+        }
+        Value::Object(map) => {
+            for val in map.values() {
+                collect_text_recursive(val, texts);
+            }
+        }
+        Value::Null => {}
+    }
+}
+
+/// How a parsed document is turned into records.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum Extract {
+    /// Every object in the tree is a record
+    #[default]
+    Tree,
+    /// The document itself is the only record (`--whole-document`)
+    Document,
+}
+
+impl Extract {
+    pub fn from_args(args: &SearchArgs) -> Self {
+        if args.whole_document {
+            Extract::Document
+        } else {
+            Extract::Tree
+        }
+    }
+
+    /// Records of one document.
+    pub fn records(self, value: &Value, file: &str) -> Vec<Record> {
+        match self {
+            Extract::Tree => extract_records(value, file),
+            Extract::Document => vec![document_record(value, "", file)],
+        }
+    }
+
+    /// Records of one NDJSON line. The file is treated as an array of lines,
+    /// so pointers are prefixed with the 0-based line index.
+    pub fn line_records(self, value: &Value, line: usize, file: &str) -> Vec<Record> {
+        let pointer = format!("/{}", line);
+        match self {
+            Extract::Tree => {
+                let mut records = Vec::new();
+                extract_recursive(value, &pointer, file, &mut records);
+                records
+            }
+            Extract::Document => vec![document_record(value, &pointer, file)],
+        }
+    }
+}
+
+fn document_record(value: &Value, pointer: &str, file: &str) -> Record {
+    Record {
+        pointer: pointer.to_string(),
+        file: file.to_string(),
+        value: value.clone(),
+    }
+}
+
+/// Extract records from a JSON value, walking the tree
+pub fn extract_records(value: &Value, file: &str) -> Vec<Record> {
+    let mut records = Vec::new();
+    extract_recursive(value, "", file, &mut records);
+    records
+}
+
+fn extract_recursive(value: &Value, pointer: &str, file: &str, records: &mut Vec<Record>) {
+    match value {
+        Value::Object(map) => {
+            records.push(Record {
+                pointer: pointer.to_string(),
+                file: file.to_string(),
+                value: value.clone(),
+            });
+
+            for (key, val) in map {
+                let escaped_key = key.replace('~', "~0").replace('/', "~1");
+                let child_pointer = format!("{}/{}", pointer, escaped_key);
+                extract_recursive(val, &child_pointer, file, records);
+            }
+        }
+        Value::Array(arr) => {
+            for (i, item) in arr.iter().enumerate() {
+                let child_pointer = format!("{}/{}", pointer, i);
+                extract_recursive(item, &child_pointer, file, records);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Keep results whose record has every `has` path and none of the `missing`
+/// paths (dotted, e.g. `contact.email`). A field set to `null` counts as
+/// present.
+pub fn filter_presence(results: &mut Vec<SearchResult>, has: &[String], missing: &[String]) {
+    if has.is_empty() && missing.is_empty() {
+        return;
+    }
+    results.retain(|r| {
+        has.iter().all(|path| has_path(&r.record.value, path))
+            && !missing.iter().any(|path| has_path(&r.record.value, path))
+    });
+}
+
+/// Whether a dotted path resolves in `value`. Arrays along the way count
+/// as having the path when any element does.
+fn has_path(value: &Value, path: &str) -> bool {
+    fn walk(value: &Value, segments: &[&str]) -> bool {
+        let Some((first, rest)) = segments.split_first() else {
+            return true;
+        };
+        match value {
+            Value::Object(map) => map.get(*first).is_some_and(|child| walk(child, rest)),
+            Value::Array(arr) => arr.iter().any(|item| walk(item, segments)),
+            _ => false,
+        }
+    }
+    let segments: Vec<&str> = path.split('.').collect();
+    walk(value, &segments)
+}
+
+/// An `--eq FIELD=VALUE` condition: the value at a dotted path must equal
+/// `value`.
+#[derive(Debug, Clone)]
+pub struct FieldEq {
+    pub path: String,
+    pub value: Value,
+}
+
+impl FieldEq {
+    /// Parse every `--eq` given.
+    pub fn parse_all(specs: &[String]) -> Result<Vec<Self>> {
+        specs.iter().map(|spec| Self::parse(spec)).collect()
+    }
+
+    /// Parse `path=value`. The value is read as JSON (`3.0`, `true`,
+    /// `"x"`), falling back to a plain string (`status=open`).
+    pub fn parse(spec: &str) -> Result<Self> {
+        let (path, value) = spec
+            .split_once('=')
+            .with_context(|| format!("Invalid --eq {:?}: expected FIELD=VALUE", spec))?;
+        if path.is_empty() {
+            bail!("Invalid --eq {:?}: missing field name", spec);
+        }
+        Ok(FieldEq {
+            path: path.to_string(),
+            value: serde_json::from_str(value).unwrap_or_else(|_| Value::String(value.to_string())),
+        })
+    }
+}
+
+/// Keep results whose record satisfies every `--eq` condition. An array
+/// along the path, or at its end, matches when any element does.
+/// `numbers` decides whether `3` equals `3.0`.
+pub fn filter_equal(results: &mut Vec<SearchResult>, conditions: &[FieldEq], numbers: NumberMatch) {
+    if conditions.is_empty() {
+        return;
+    }
+    results.retain(|r| {
+        conditions.iter().all(|eq| {
+            let segments: Vec<&str> = eq.path.split('.').collect();
+            path_equals(&r.record.value, &segments, &eq.value, numbers)
+        })
+    });
+}
+
+fn path_equals(value: &Value, segments: &[&str], wanted: &Value, numbers: NumberMatch) -> bool {
+    let Some((first, rest)) = segments.split_first() else {
+        return match value {
+            Value::Array(arr) if !wanted.is_array() => {
+                arr.iter().any(|item| values_equal(item, wanted, numbers))
+            }
+            _ => values_equal(value, wanted, numbers),
+        };
+    };
+    match value {
+        Value::Object(map) => map
+            .get(*first)
+            .is_some_and(|child| path_equals(child, rest, wanted, numbers)),
+        Value::Array(arr) => arr.iter().any(|item| path_equals(item, segments, wanted, numbers)),
+        _ => false,
+    }
+}
+
+fn values_equal(stored: &Value, wanted: &Value, numbers: NumberMatch) -> bool {
+    match (stored, wanted, numbers) {
+        // Two integers compare exactly; once a float is involved, by value.
+        (Value::Number(a), Value::Number(b), NumberMatch::Numeric) if a.is_f64() || b.is_f64() => {
+            a.as_f64() == b.as_f64()
+        }
+        _ => stored == wanted,
+    }
+}
+
+/// Order results by file, then pointer, instead of by score. Array index
+/// segments compare numerically, so `/2` comes before `/10`.
+pub fn sort_by_location(results: &mut [SearchResult]) {
+    results.sort_by(|a, b| {
+        a.record
+            .file
+            .cmp(&b.record.file)
+            .then_with(|| compare_pointers(&a.record.pointer, &b.record.pointer))
+    });
+}
+
+/// A `--sort field[:asc|:desc]` key.
+#[derive(Debug, Clone)]
+pub struct SortKey {
+    /// Dotted path of the value to sort by
+    pub field: String,
+    pub descending: bool,
+}
+
+impl SortKey {
+    /// Parse `field`, `field:asc` or `field:desc`.
+    pub fn parse(spec: &str) -> Result<Self> {
+        let (field, descending) = match spec.rsplit_once(':') {
+            Some((field, "asc")) => (field, false),
+            Some((field, "desc")) => (field, true),
+            Some((_, other)) => bail!("Invalid --sort direction {:?}: expected asc or desc", other),
+            None => (spec, false),
+        };
+        if field.is_empty() {
+            bail!("Invalid --sort {:?}: missing field name", spec);
+        }
+        Ok(SortKey {
+            field: field.to_string(),
+            descending,
+        })
+    }
+}
+
+/// Order results by the value at a field. Numbers compare numerically and
+/// strings lexicographically (numbers before strings); records where the
+/// field is null or missing go last in either direction. The sort is
+/// stable, so equal keys keep score order.
+pub fn sort_by_field(results: &mut [SearchResult], key: &SortKey) {
+    fn sort_value<'a>(result: &'a SearchResult, segments: &[&str]) -> Option<&'a Value> {
+        segments
+            .iter()
+            .try_fold(&result.record.value, |value, segment| value.get(*segment))
+            .filter(|value| !value.is_null())
+    }
+    let segments: Vec<&str> = key.field.split('.').collect();
+    results.sort_by(|a, b| match (sort_value(a, &segments), sort_value(b, &segments)) {
+        (Some(x), Some(y)) => {
+            let ordering = compare_sort_values(x, y);
+            if key.descending {
+                ordering.reverse()
+            } else {
+                ordering
+            }
+        }
+        (Some(_), None) => std::cmp::Ordering::Less,
+        (None, Some(_)) => std::cmp::Ordering::Greater,
+        (None, None) => std::cmp::Ordering::Equal,
+    });
+}
+
+fn compare_sort_values(a: &Value, b: &Value) -> std::cmp::Ordering {
+    fn rank(value: &Value) -> u8 {
+        match value {
+            Value::Number(_) => 0,
+            Value::String(_) => 1,
+            Value::Bool(_) => 2,
+            _ => 3,
+        }
+    }
+    match (a, b) {
+        (Value::Number(x), Value::Number(y)) => x
+            .as_f64()
+            .partial_cmp(&y.as_f64())
+            .unwrap_or(std::cmp::Ordering::Equal),
+        (Value::String(x), Value::String(y)) => x.cmp(y),
+        (Value::Bool(x), Value::Bool(y)) => x.cmp(y),
+        _ => rank(a).cmp(&rank(b)),
+    }
+}
+
+fn compare_pointers(a: &str, b: &str) -> std::cmp::Ordering {
+    let segments = |p: &str| p.split('/').skip(1).map(str::to_string).collect::<Vec<_>>();
+    let (a, b) = (segments(a), segments(b));
+    for (x, y) in a.iter().zip(&b) {
+        let ordering = match (x.parse::<u64>(), y.parse::<u64>()) {
+            (Ok(x), Ok(y)) => x.cmp(&y),
+            _ => x.cmp(y),
+        };
+        if ordering.is_ne() {
+            return ordering;
+        }
+    }
+    a.len().cmp(&b.len())
+}
+
+/// Deduplicate results according to the chosen policy.
+pub fn dedup_results(results: &mut Vec<SearchResult>, policy: &Dedup) {
+    match policy {
+        Dedup::Ancestors => dedup_ancestors(results),
+        Dedup::Value => dedup_values(results),
+        Dedup::None => {}
+    }
+}
+
+/// Remove records whose value equals another record's, keeping the
+/// highest-scoring one (the earliest on ties).
+fn dedup_values(results: &mut Vec<SearchResult>) {
+    let mut best: Vec<usize> = Vec::new();
+    for (i, r) in results.iter().enumerate() {
+        match best
+            .iter_mut()
+            .find(|b| results[**b].record.value == r.record.value)
+        {
+            Some(b) if results[*b].score < r.score => *b = i,
+            Some(_) => {}
+            None => best.push(i),
+        }
+    }
+
+    let mut i = 0;
+    results.retain(|_| {
+        let keep = best.contains(&i);
+        i += 1;
+        keep
+    });
+}
+
+/// If a child matches, remove its ancestor
+fn dedup_ancestors(results: &mut Vec<SearchResult>) {
+    let pointers: Vec<(String, String)> = results
+        .iter()
+        .map(|r| (r.record.pointer.clone(), r.record.file.clone()))
+        .collect();
+
+    results.retain(|r| {
+        let my_pointer = &r.record.pointer;
+        let my_file = &r.record.file;
+        !pointers.iter().any(|(other_ptr, other_file)| {
+            other_file == my_file
+                && other_ptr
+                    .strip_prefix(my_pointer.as_str())
+                    .is_some_and(|rest| rest.starts_with('/'))
         })
     });
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        dedup_results, escape_field_name, extract_records, field_scoped_query, filter_equal, filter_presence,
+        fold_and_not, qualify_fields, sort_by_field, After, Ascend, Engine, FieldBoost, FieldEq, FieldIndexing, IndexOptions,
+        FieldOption, NumericRange, Record, SearchOptions, SearchResult, SortKey,
+    };
+    use crate::cli::{Dedup, Lang, MatchMode, NumberMatch, Scoring, SearchArgs};
+    use clap::Parser;
+    use serde_json::json;
+
+    fn engine_for(value: serde_json::Value) -> Engine {
+        let engine = Engine::new().unwrap();
+        engine.index_records(&extract_records(&value, "test.json")).unwrap();
+        engine
+    }
+
+    fn options(match_mode: MatchMode, scoring: Scoring) -> SearchOptions {
+        SearchOptions {
+            match_mode,
+            scoring,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn scoring_none_yields_equal_scores() {
+        let engine = engine_for(json!([
+            { "t": "error" },
+            { "t": "error error error with a much longer description attached" }
+        ]));
+
+        let results = engine
+            .search("error", &[], &options(MatchMode::Text, Scoring::None), 10, 0)
+            .unwrap();
+
+        assert_eq!(results.len(), 2);
+        assert!(results.iter().all(|r| r.score == 1.0));
+    }
+
+    #[test]
+    fn scoring_tf_ignores_length_normalization() {
+        let engine = engine_for(json!([
+            { "t": "error" },
+            { "t": "error error error with a much longer description attached" }
+        ]));
+
+        let results = engine
+            .search("error", &[], &options(MatchMode::Text, Scoring::Tf), 10, 0)
+            .unwrap();
+
+        assert_eq!(results[0].record.pointer, "/1");
+        assert_eq!(results[0].score, 3.0);
+        assert_eq!(results[1].score, 1.0);
+    }
+
+    #[test]
+    fn scoring_none_with_sort_orders_by_the_field_alone() {
+        let engine = engine_for(json!([
+            { "t": "error", "price": 30 },
+            { "t": "error error error", "price": 10 },
+            { "t": "error in a much longer description", "price": 20 }
+        ]));
+
+        let mut results = engine
+            .search("error", &[], &options(MatchMode::Text, Scoring::None), 10, 0)
+            .unwrap();
+        sort_by_field(&mut results, &SortKey::parse("price").unwrap());
+        assert_eq!(pointers(&results), vec!["/1", "/2", "/0"]);
+
+        sort_by_field(&mut results, &SortKey::parse("price:desc").unwrap());
+        assert_eq!(pointers(&results), vec!["/0", "/2", "/1"]);
+    }
+
+    fn result(pointer: &str, value: serde_json::Value, score: f32) -> SearchResult {
+        SearchResult {
+            record: Record {
+                pointer: pointer.to_string(),
+                file: "test.json".to_string(),
+                value,
+            },
+            score,
+            locator: None,
+            snippet: None,
+            explanation: None,
+            doc: None,
+        }
+    }
+
+    fn dedup_corpus() -> Vec<SearchResult> {
+        vec![
+            result("/0", json!({ "a": { "t": "x" } }), 1.0),
+            result("/0/a", json!({ "t": "x" }), 2.0),
+            result("/1", json!({ "t": "x" }), 3.0),
+            result("/2", json!({ "t": "y" }), 0.5),
+        ]
+    }
+
+    fn pointers(results: &[SearchResult]) -> Vec<&str> {
+        results.iter().map(|r| r.record.pointer.as_str()).collect()
+    }
+
+    #[test]
+    fn dedup_ancestors_drops_parents_of_matches() {
+        let mut results = dedup_corpus();
+        dedup_results(&mut results, &Dedup::Ancestors);
+        assert_eq!(pointers(&results), vec!["/0/a", "/1", "/2"]);
+    }
+
+    #[test]
+    fn dedup_ancestors_requires_a_segment_boundary() {
+        let mut results = vec![
+            result("/foo", json!({ "t": "x" }), 1.0),
+            result("/foobar", json!({ "t": "x" }), 1.0),
+        ];
+        dedup_results(&mut results, &Dedup::Ancestors);
+        assert_eq!(pointers(&results), vec!["/foo", "/foobar"]);
+    }
+
+    #[test]
+    fn dedup_ancestors_keeps_sibling_array_indices() {
+        let mut results = vec![
+            result("/items/1", json!({ "t": "x" }), 1.0),
+            result("/items/10", json!({ "t": "x" }), 1.0),
+            result("/items/10/meta", json!({ "t": "x" }), 1.0),
+        ];
+        dedup_results(&mut results, &Dedup::Ancestors);
+        // `/items/10` is dropped for its own child, not `/items/1`'s.
+        assert_eq!(pointers(&results), vec!["/items/1", "/items/10/meta"]);
+    }
+
+    #[test]
+    fn dedup_value_keeps_highest_scoring_duplicate() {
+        let mut results = dedup_corpus();
+        dedup_results(&mut results, &Dedup::Value);
+        assert_eq!(pointers(&results), vec!["/0", "/1", "/2"]);
+    }
+
+    #[test]
+    fn dedup_none_keeps_everything() {
+        let mut results = dedup_corpus();
+        dedup_results(&mut results, &Dedup::None);
+        assert_eq!(pointers(&results), vec!["/0", "/0/a", "/1", "/2"]);
+    }
+
+    fn contacts() -> Vec<SearchResult> {
+        vec![
+            result("/0", json!({ "name": "Ada", "email": "ada@x.io", "phone": "1" }), 1.0),
+            result("/1", json!({ "name": "Bob", "email": null }), 1.0),
+            result("/2", json!({ "name": "Cy", "phones": [{ "home": "2" }] }), 1.0),
+        ]
+    }
+
+    #[test]
+    fn filter_presence_keeps_records_missing_a_field() {
+        let mut results = contacts();
+        filter_presence(&mut results, &[], &["email".to_string()]);
+        assert_eq!(pointers(&results), vec!["/2"]);
+    }
+
+    #[test]
+    fn filter_presence_keeps_records_having_fields() {
+        let mut results = contacts();
+        filter_presence(&mut results, &["email".to_string()], &["phone".to_string()]);
+        assert_eq!(pointers(&results), vec!["/1"]);
+
+        let mut results = contacts();
+        filter_presence(&mut results, &["phones.home".to_string()], &[]);
+        assert_eq!(pointers(&results), vec!["/2"]);
+    }
+
+    #[test]
+    fn filter_equal_numeric_mode_matches_integer_against_float() {
+        let prices = || {
+            vec![
+                result("/0", json!({ "sku": "a", "price": 3.0 }), 1.0),
+                result("/1", json!({ "sku": "b", "price": 3 }), 1.0),
+                result("/2", json!({ "sku": "c", "price": 3.5, "tags": ["sale", 3] }), 1.0),
+            ]
+        };
+
+        let mut results = prices();
+        filter_equal(&mut results, &[FieldEq::parse("price=3").unwrap()], NumberMatch::Numeric);
+        assert_eq!(pointers(&results), vec!["/0", "/1"]);
+
+        let mut results = prices();
+        filter_equal(&mut results, &[FieldEq::parse("price=3.0").unwrap()], NumberMatch::Numeric);
+        assert_eq!(pointers(&results), vec!["/0", "/1"]);
+
+        // As text, numbers only match as written.
+        let mut results = prices();
+        filter_equal(&mut results, &[FieldEq::parse("price=3").unwrap()], NumberMatch::Text);
+        assert_eq!(pointers(&results), vec!["/1"]);
+
+        let mut results = prices();
+        filter_equal(&mut results, &[FieldEq::parse("tags=3.0").unwrap()], NumberMatch::Numeric);
+        assert_eq!(pointers(&results), vec!["/2"]);
+    }
+
+    #[test]
+    fn filter_equal_reads_unquoted_strings() {
+        let mut results = contacts();
+        filter_equal(&mut results, &FieldEq::parse_all(&["name=Bob".to_string()]).unwrap(), NumberMatch::Text);
+        assert_eq!(pointers(&results), vec!["/1"]);
+
+        assert!(FieldEq::parse("price").is_err());
+        assert!(FieldEq::parse("=3").is_err());
+    }
+
+    #[test]
+    fn sort_by_field_orders_numbers_and_puts_nulls_last() {
+        let products = || {
+            vec![
+                result("/0", json!({ "sku": "a", "price": 10 }), 5.0),
+                result("/1", json!({ "sku": "b", "price": null }), 4.0),
+                result("/2", json!({ "sku": "c", "price": 9.5 }), 3.0),
+                result("/3", json!({ "sku": "d" }), 2.0),
+                result("/4", json!({ "sku": "e", "price": 10 }), 1.0),
+                result("/5", json!({ "sku": "f", "price": 100 }), 0.5),
+            ]
+        };
+
+        let mut results = products();
+        sort_by_field(&mut results, &SortKey::parse("price").unwrap());
+        assert_eq!(pointers(&results), vec!["/2", "/0", "/4", "/5", "/1", "/3"]);
+
+        // Equal prices keep score order in both directions.
+        let mut results = products();
+        sort_by_field(&mut results, &SortKey::parse("price:desc").unwrap());
+        assert_eq!(pointers(&results), vec!["/5", "/0", "/4", "/2", "/1", "/3"]);
+
+        let mut results = products();
+        sort_by_field(&mut results, &SortKey::parse("sku:desc").unwrap());
+        assert_eq!(pointers(&results), vec!["/5", "/4", "/3", "/2", "/1", "/0"]);
+
+        assert!(SortKey::parse("price:up").is_err());
+        assert!(SortKey::parse(":desc").is_err());
+    }
+
+    fn vocabulary_engine() -> Engine {
+        let words: Vec<serde_json::Value> = (0..200)
+            .map(|i| json!({ "word": format!("term{}", i) }))
+            .collect();
+        engine_for(serde_json::Value::Array(words))
+    }
+
+    #[test]
+    fn max_terms_rejects_broad_regex() {
+        let engine = vocabulary_engine();
+        let broad = SearchOptions {
+            match_mode: MatchMode::Regex,
+            max_terms: Some(50),
+            ..Default::default()
+        };
+
+        let err = engine.search("term.*", &[], &broad, 10, 0).unwrap_err();
+        assert!(err.to_string().contains("--max-terms"), "{}", err);
+
+        let narrow = engine.search("term1[0-9]", &[], &broad, 10, 0).unwrap();
+        assert_eq!(narrow.len(), 10);
+    }
+
+    #[test]
+    fn max_terms_rejects_broad_fuzzy() {
+        let engine = vocabulary_engine();
+        let fuzzy = SearchOptions {
+            match_mode: MatchMode::Fuzzy,
+            max_terms: Some(5),
+            ..Default::default()
+        };
+
+        assert!(engine.search("term1", &[], &fuzzy, 10, 0).is_err());
+        assert!(engine
+            .search("term1", &[], &SearchOptions { max_terms: None, ..fuzzy }, 10, 0)
+            .is_ok());
+    }
+
+    #[test]
+    fn fuzzy_distance_and_prefix_narrow_matches() {
+        let engine = engine_for(json!([{ "word": "login" }, { "word": "logout" }, { "word": "begin" }]));
+        let search = |query: &str, fuzzy_distance: u8, fuzzy_prefix: Option<usize>| {
+            let options = SearchOptions {
+                match_mode: MatchMode::Fuzzy,
+                fuzzy_distance,
+                fuzzy_prefix,
+                max_terms: Some(10),
+                ..Default::default()
+            };
+            let results = engine.search(query, &[], &options, 10, 0).unwrap();
+            let mut found: Vec<String> = pointers(&results).into_iter().map(String::from).collect();
+            found.sort();
+            found
+        };
+
+        assert_eq!(search("logon", 2, None), ["/0", "/1"]);
+        assert_eq!(search("logon", 1, None), ["/0"]);
+        assert!(search("logon", 0, None).is_empty());
+        assert_eq!(search("login", 0, None), ["/0"]);
+        // "begin" is 2 edits from "login" but differs in the first letters.
+        assert_eq!(search("legin", 2, None), ["/0", "/2"]);
+        assert_eq!(search("legin", 2, Some(2)), Vec::<String>::new());
+        assert_eq!(search("logon", 2, Some(3)), ["/0", "/1"]);
+        assert_eq!(search("legin", 2, Some(1)), ["/0"]);
+    }
+
+    #[test]
+    fn fuzzy_matches_each_query_word() {
+        let engine = engine_for(json!([
+            { "msg": "login failure for admin" },
+            { "msg": "logout complete" },
+            { "msg": "disk failure" }
+        ]));
+        let fuzzy = SearchOptions {
+            match_mode: MatchMode::Fuzzy,
+            ..Default::default()
+        };
+
+        let results = engine.search("login failugre", &[], &fuzzy, 10, 0).unwrap();
+        // Either word is enough; the record matching both ranks first.
+        assert_eq!(pointers(&results), ["/0", "/2"]);
+
+        let all_words = SearchOptions {
+            fuzzy_and: true,
+            ..fuzzy
+        };
+        let results = engine.search("login failugre", &[], &all_words, 10, 0).unwrap();
+        assert_eq!(pointers(&results), ["/0"]);
+    }
+
+    fn mode(match_mode: MatchMode) -> SearchOptions {
+        SearchOptions {
+            match_mode,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn mixed_case_queries_match_lowercased_terms_in_every_mode() {
+        let engine = engine_for(json!([{ "msg": "Connection Refused" }, { "msg": "ok" }]));
+
+        for (query, match_mode) in [
+            ("REFUSED", MatchMode::Text),
+            ("Refusd", MatchMode::Fuzzy),
+            ("REF.*", MatchMode::Regex),
+            ("refused", MatchMode::Regex),
+        ] {
+            let results = engine.search(query, &[], &mode(match_mode), 10, 0).unwrap();
+            assert_eq!(results.len(), 1, "query {:?}", query);
+            assert_eq!(results[0].record.value, json!({ "msg": "Connection Refused" }));
+        }
+    }
+
+    #[test]
+    fn regex_matches_single_tokens_not_whole_values() {
+        let engine = engine_for(json!([{ "msg": "Connection Refused" }]));
+        let results = engine
+            .search("connection refused", &[], &mode(MatchMode::Regex), 10, 0)
+            .unwrap();
+        assert!(results.is_empty());
+    }
+
+    #[test]
+    fn exact_mode_matches_whole_value_with_case() {
+        let engine = Engine::with_options(&IndexOptions {
+            exact: true,
+            ..Default::default()
+        })
+        .unwrap();
+        engine
+            .index_records(&extract_records(
+                &json!([
+                    { "state": "Active State" },
+                    { "state": "active state" },
+                    { "state": "Active State Now" },
+                    { "meta": { "status": "Active State" } }
+                ]),
+                "test.json",
+            ))
+            .unwrap();
+
+        let results = engine
+            .search("Active State", &[], &mode(MatchMode::Exact), 10, 0)
+            .unwrap();
+        let pointers: Vec<_> = results.iter().map(|r| r.record.pointer.as_str()).collect();
+        assert_eq!(pointers.len(), 3);
+        assert!(pointers.contains(&"/0") && pointers.contains(&"/3") && pointers.contains(&"/3/meta"));
+
+        let results = engine
+            .search("Active State", &["state".to_string()], &mode(MatchMode::Exact), 10, 0)
+            .unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].record.value, json!({ "state": "Active State" }));
+
+        let results = engine
+            .search("Active State", &["meta.status".to_string()], &mode(MatchMode::Exact), 10, 0)
+            .unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].record.value, json!({ "meta": { "status": "Active State" } }));
+
+        // Other engines don't build the exact fields at all.
+        let plain = engine_for(json!([{ "state": "Active State" }]));
+        assert!(plain.search("Active State", &[], &mode(MatchMode::Exact), 10, 0).is_err());
+    }
+
+    #[test]
+    fn explain_attaches_score_breakdown() {
+        let engine = engine_for(json!([{ "msg": "user login failed" }, { "msg": "login ok" }]));
+        let explain = |scoring| {
+            let options = SearchOptions {
+                scoring,
+                explain: true,
+                ..Default::default()
+            };
+            let mut results = engine.search("login", &[], &options, 10, 0).unwrap();
+            assert!(results.iter().all(|r| r.explanation.is_none()));
+            engine.explain("login", &[], &options, &mut results).unwrap();
+            results
+        };
+
+        let results = explain(Scoring::Bm25);
+        for result in &results {
+            let explanation = result.explanation.as_ref().unwrap();
+            let value = explanation["value"].as_f64().unwrap();
+            assert!((value - result.score as f64).abs() < 1e-4, "{} vs {}", value, result.score);
+            assert!(explanation["description"].as_str().unwrap().contains("TermQuery"), "{}", explanation);
+        }
+
+        let results = explain(Scoring::Tf);
+        assert_eq!(results[0].explanation.as_ref().unwrap()["value"], json!(1.0));
+    }
+
+    #[test]
+    fn search_after_resumes_past_the_given_match() {
+        let engine = engine_for(json!([
+            { "msg": "error" },
+            { "msg": "error error" },
+            { "msg": "error" },
+            { "msg": "error error error" },
+            { "msg": "error" }
+        ]));
+        for scoring in [Scoring::Bm25, Scoring::Tf, Scoring::None] {
+            let options = options(MatchMode::Text, scoring);
+            let all = engine.search("error", &[], &options, 10, 0).unwrap();
+            let pointers = |results: &[SearchResult]| -> Vec<String> {
+                results.iter().map(|r| r.record.pointer.clone()).collect()
+            };
+            for (i, start) in all.iter().enumerate() {
+                let after = SearchOptions {
+                    after: Some(After {
+                        score: start.score,
+                        file: start.record.file.clone(),
+                        pointer: start.record.pointer.clone(),
+                    }),
+                    ..options.clone()
+                };
+                let rest = engine.search("error", &[], &after, 2, 0).unwrap();
+                let expected: Vec<_> = pointers(&all[i + 1..]).into_iter().take(2).collect();
+                assert_eq!(pointers(&rest), expected);
+            }
+        }
+
+        let gone = SearchOptions {
+            after: Some(After {
+                score: 1.0,
+                file: "test.json".to_string(),
+                pointer: "/99".to_string(),
+            }),
+            ..Default::default()
+        };
+        assert!(engine.search("error", &[], &gone, 2, 0).is_err());
+    }
+
+    #[test]
+    fn highlight_adds_snippets_for_text_but_not_fuzzy() {
+        let long = format!("{} user login failed at noon {}", "padding ".repeat(40), "trailing ".repeat(40));
+        let engine = engine_for(json!([{ "msg": long, "secret": "login hunter2" }]));
+        let highlight = |query: &str, match_mode: MatchMode| {
+            let mut results = engine.search(query, &[], &mode(match_mode.clone()), 10, 0).unwrap();
+            engine
+                .highlight(query, &match_mode, &mut results, |value| json!({ "msg": value["msg"] }))
+                .unwrap();
+            results
+        };
+
+        let results = highlight("login", MatchMode::Text);
+        let snippet = results[0].snippet.as_deref().unwrap();
+        assert!(snippet.contains("user login failed"), "{}", snippet);
+        assert!(snippet.starts_with("...") && snippet.ends_with("..."), "{}", snippet);
+        // Only the record as shown is quoted.
+        assert!(!snippet.contains("hunter2"), "{}", snippet);
+
+        let results = highlight("logn", MatchMode::Fuzzy);
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].snippet, None);
+    }
+
+    #[test]
+    fn field_search_targets_nested_paths_with_every_word() {
+        let engine = engine_for(json!([
+            { "author": { "name": "Ada" } },
+            { "author": { "name": "Ada Lovelace" } },
+            { "author": { "name": "Grace" }, "note": "Ada Lovelace" },
+            { "a-b": { "c d": "Ada" } }
+        ]));
+        let search = |query: &str, field: &str| -> Vec<String> {
+            engine
+                .search(query, &[field.to_string()], &SearchOptions::default(), 10, 0)
+                .unwrap()
+                .iter()
+                .map(|r| r.record.pointer.clone())
+                .collect()
+        };
+
+        let mut ada = search("Ada", "author.name");
+        ada.sort();
+        assert_eq!(ada, ["/0", "/1"]);
+        assert_eq!(search("Ada Lovelace", "author.name"), ["/1"]);
+        assert_eq!(search("\"Ada Lovelace\"", "author.name"), ["/1"]);
+        assert_eq!(search("Lovelace OR Grace", "author.name").len(), 2);
+        assert_eq!(search("Ada", "a-b.c d"), ["/3"]);
+    }
+
+    #[test]
+    fn field_scoped_query_prefixes_terms_but_not_operators() {
+        assert_eq!(
+            field_scoped_query("content.a.b", "x +y -\"p q\" OR (z AND w)").unwrap(),
+            "content.a.b:x +content.a.b:y -content.a.b:\"p q\" OR (content.a.b:z AND content.a.b:w)"
+        );
+        assert_eq!(field_scoped_query("content.c d", "x").unwrap(), "content.c\\ d:x");
+        assert_eq!(field_scoped_query("content.u", "\"http://x\"").unwrap(), "content.u:\"http://x\"");
+        let err = field_scoped_query("content.title", "login status:active").unwrap_err();
+        assert!(err.to_string().contains("`status:active` names a field"), "{}", err);
+    }
+
+    fn ticket_engine() -> Engine {
+        let engine = Engine::new().unwrap();
+        engine
+            .index_records(&extract_records(
+                &json!([
+                    { "title": "login fails", "status": "active" },
+                    { "title": "login slow", "status": "archived" },
+                    { "title": "logout fails", "status": "active" }
+                ]),
+                "tickets.json",
+            ))
+            .unwrap();
+        engine
+    }
+
+    #[test]
+    fn text_query_passes_boolean_operators_through() {
+        let engine = ticket_engine();
+        let search = |query: &str| {
+            let results = engine.search(query, &[], &SearchOptions::default(), 10, 0).unwrap();
+            let mut found: Vec<String> = pointers(&results).into_iter().map(String::from).collect();
+            found.sort();
+            found
+        };
+
+        assert_eq!(search("login AND fails"), ["/0"]);
+        assert_eq!(search("slow OR logout"), ["/1", "/2"]);
+        assert_eq!(search("fails NOT logout"), ["/0"]);
+        assert_eq!(search("(login OR logout) AND NOT archived"), ["/0", "/2"]);
+    }
+
+    #[test]
+    fn bool_query_scopes_qualified_terms_to_fields() {
+        let engine = ticket_engine();
+        let options = SearchOptions {
+            boolean: true,
+            ..Default::default()
+        };
+        let results = engine.search("status:active AND NOT logout", &[], &options, 10, 0).unwrap();
+        assert_eq!(pointers(&results), ["/0"]);
+
+        // "fails" is not a status, so the qualified term finds nothing.
+        assert!(engine.search("status:fails", &[], &options, 10, 0).unwrap().is_empty());
+
+        let fields = vec!["title".to_string()];
+        assert!(engine.search("login", &fields, &options, 10, 0).is_err());
+    }
+
+    #[test]
+    fn qualify_fields_rewrites_only_field_prefixes() {
+        let resolve = |f: &str| format!("content.{}", escape_field_name(f));
+        assert_eq!(
+            qualify_fields("a:x OR -b:\"p q\" NOT (c AND \"d:e\")", resolve),
+            "content.a:x OR -content.b:\"p q\" NOT (c AND \"d:e\")"
+        );
+        assert_eq!(qualify_fields("url:http://x", resolve), "content.url:http://x");
+        assert_eq!(
+            fold_and_not("a AND NOT b AND (NOT c) \"AND NOT d\""),
+            "a AND -b AND (NOT c) \"AND NOT d\""
+        );
+        assert_eq!(qualify_fields("my\\ key:v", resolve), "content.my\\ key:v");
+    }
+
+    fn blob_corpus() -> Vec<Record> {
+        extract_records(
+            &json!([
+                { "title": "intro", "content": "the quick brown fox jumps" },
+                { "title": "outro", "content": "brown bears sleep" }
+            ]),
+            "docs.json",
+        )
+    }
+
+    #[test]
+    fn freqs_only_field_supports_terms_but_rejects_phrases() {
+        let option = FieldOption::parse("content:freqs").unwrap();
+        let engine = Engine::with_options(&IndexOptions {
+            field_options: vec![option],
+            ..Default::default()
+        })
+        .unwrap();
+        engine.index_records(&blob_corpus()).unwrap();
+        let fields = vec!["content".to_string()];
+
+        let results = engine.search("fox", &fields, &SearchOptions::default(), 10, 0).unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].record.value["title"], json!("intro"));
+
+        let err = engine
+            .search("\"quick brown\"", &fields, &SearchOptions::default(), 10, 0)
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("without positions"), "{}", err);
+    }
+
+    #[test]
+    fn dedicated_field_is_left_out_of_all_text_but_still_searched() {
+        let engine = Engine::with_options(&IndexOptions {
+            field_options: vec![FieldOption::parse("content:freqs").unwrap()],
+            ngram: Some((3, 4)),
+            ..Default::default()
+        })
+        .unwrap();
+        engine.index_records(&blob_corpus()).unwrap();
+        let search = |query: &str, mode: MatchMode| {
+            let options = SearchOptions {
+                match_mode: mode,
+                ..Default::default()
+            };
+            engine.search(query, &[], &options, 10, 0).unwrap().len()
+        };
+
+        assert_eq!(search("fox", MatchMode::Text), 1);
+        assert_eq!(search("fox intro", MatchMode::Text), 1);
+        // Phrases skip the positionless field instead of failing.
+        assert_eq!(search("\"quick brown\"", MatchMode::Text), 0);
+        // Every other mode searches the dedicated field too.
+        assert_eq!(search("fo", MatchMode::Prefix), 1);
+        assert_eq!(search("fo intro", MatchMode::Prefix), 1);
+        assert_eq!(search("fxo", MatchMode::Fuzzy), 1);
+        assert_eq!(search("f.x", MatchMode::Regex), 1);
+        assert_eq!(search("jump", MatchMode::Ngram), 1);
+    }
+
+    #[test]
+    fn phrase_queries_work_on_default_fields() {
+        let engine = Engine::new().unwrap();
+        engine.index_records(&blob_corpus()).unwrap();
+        let fields = vec!["content".to_string()];
+
+        let results = engine
+            .search("\"quick brown\"", &fields, &SearchOptions::default(), 10, 0)
+            .unwrap();
+        assert_eq!(results.len(), 1);
+    }
+
+    #[test]
+    fn explanations_are_only_computed_for_hit_output() {
+        let options = |argv: &[&str]| {
+            let args = SearchArgs::parse_from([&["search", "-q", "x", "--explain"], argv, &["a.json"]].concat());
+            SearchOptions::from_args(&args).unwrap().explain
+        };
+        assert!(options(&["-o", "hit"]));
+        assert!(!options(&[]));
+        assert!(!options(&["-o", "value"]));
+    }
+
+    #[test]
+    fn field_option_rejects_unknown_level() {
+        assert!(FieldOption::parse("content:zip").is_err());
+        assert!(FieldOption::parse("content").is_err());
+        assert_eq!(FieldOption::parse("body:basic").unwrap().indexing, FieldIndexing::Basic);
+        let twice = FieldOption::parse_all(&["a:freqs".to_string(), "a:basic".to_string()]).unwrap_err();
+        assert!(twice.to_string().contains("given twice"), "{}", twice);
+    }
+
+    #[test]
+    fn numeric_range_parses_open_ends_and_rejects_bad_bounds() {
+        let range = NumericRange::parse("price:100..").unwrap();
+        assert_eq!((range.field.as_str(), range.min, range.max), ("price", Some(100.0), None));
+        let range = NumericRange::parse("a.b:..5.5").unwrap();
+        assert_eq!((range.field.as_str(), range.min, range.max), ("a.b", None, Some(5.5)));
+        assert!(NumericRange::parse("price:10").is_err());
+        assert!(NumericRange::parse("price:x..5").is_err());
+        assert!(NumericRange::parse("price:5..1").is_err());
+        assert!(NumericRange::parse(":1..5").is_err());
+    }
+
+    #[test]
+    fn range_intersects_query_with_numeric_field() {
+        let engine = Engine::with_options(&IndexOptions {
+            range_fields: vec!["price".to_string()],
+            ..Default::default()
+        })
+        .unwrap();
+        engine
+            .index_records(&extract_records(
+                &json!([
+                    { "name": "item a", "price": 50 },
+                    { "name": "item b", "price": 100 },
+                    { "name": "item c", "price": 250.5 },
+                    { "name": "item d", "price": 500 },
+                    { "name": "item e", "price": 900 },
+                    { "name": "item f", "price": "300" }
+                ]),
+                "test.json",
+            ))
+            .unwrap();
+
+        let names = |spec: &str| -> Vec<String> {
+            let options = SearchOptions {
+                ranges: vec![NumericRange::parse(spec).unwrap()],
+                ..Default::default()
+            };
+            let mut names: Vec<String> = engine
+                .search("item", &[], &options, 10, 0)
+                .unwrap()
+                .iter()
+                .map(|r| r.record.value["name"].as_str().unwrap().to_string())
+                .collect();
+            names.sort();
+            names
+        };
+
+        assert_eq!(names("price:100..500"), ["item b", "item c", "item d"]);
+        assert_eq!(names("price:..100"), ["item a", "item b"]);
+        assert_eq!(names("price:500.."), ["item d", "item e"]);
+    }
+
+    #[test]
+    fn boost_ranks_matches_in_boosted_field_first() {
+        let engine = engine_for(json!([
+            { "title": "status", "body": "error error error" },
+            { "title": "disk error", "body": "the disk filled up overnight while saving" },
+            { "title": "note", "body": "error while saving" }
+        ]));
+        let titles = |boosts: &[&str]| -> Vec<String> {
+            let options = SearchOptions {
+                boosts: boosts.iter().map(|spec| FieldBoost::parse(spec).unwrap()).collect(),
+                ..Default::default()
+            };
+            engine
+                .search("error", &[], &options, 10, 0)
+                .unwrap()
+                .iter()
+                .map(|r| r.record.value["title"].as_str().unwrap().to_string())
+                .collect()
+        };
+
+        assert_eq!(titles(&[]).first().unwrap(), "status");
+        let boosted = titles(&["title:3", "body:1"]);
+        assert_eq!(boosted.len(), 3);
+        assert_eq!(boosted[0], "disk error");
+
+        assert!(FieldBoost::parse("title").is_err());
+        assert!(FieldBoost::parse("title:0").is_err());
+        assert!(FieldBoost::parse(":2").is_err());
+    }
+
+    #[test]
+    fn lang_stems_words_and_drops_stopwords() {
+        let records = extract_records(
+            &json!([{ "note": "running the tests" }, { "note": "ran out of disk" }]),
+            "test.json",
+        );
+        let english = Engine::with_options(&IndexOptions {
+            lang: Some(Lang::En),
+            ..Default::default()
+        })
+        .unwrap();
+        english.index_records(&records).unwrap();
+        let hits = |engine: &Engine, query: &str| {
+            engine.search(query, &[], &SearchOptions::default(), 10, 0).unwrap().len()
+        };
+
+        assert_eq!(hits(&english, "run"), 1);
+        assert_eq!(hits(&english, "test"), 1);
+        // A stopword in the query is dropped rather than required.
+        assert_eq!(hits(&english, "the disk"), 1);
+
+        let plain = Engine::new().unwrap();
+        plain.index_records(&records).unwrap();
+        assert_eq!(hits(&plain, "run"), 0);
+    }
+
+    #[test]
+    fn key_locator_replaces_array_indices_with_id_segments() {
+        let engine = engine_for(json!({
+            "items": [
+                { "id": "x", "tags": [{ "id": 9, "name": "red" }] },
+                { "id": "a/b", "name": "second" }
+            ],
+            "0": { "id": "not-an-element" }
+        }));
+
+        assert_eq!(
+            engine.key_locator("test.json", "/items/0/tags/0", "id").unwrap(),
+            "/items/{id=x}/tags/{id=9}"
+        );
+        assert_eq!(engine.key_locator("test.json", "/items/1", "id").unwrap(), "/items/{id=a~1b}");
+        assert_eq!(engine.key_locator("test.json", "/0", "id").unwrap(), "/0");
+        assert_eq!(engine.key_locator("test.json", "/items/1", "sku").unwrap(), "/items/1");
+    }
+
+    fn nested_corpus() -> Engine {
+        engine_for(json!([
+            { "id": 1, "profile": { "address": { "city": "Paris" } } },
+            { "id": 2, "profile": { "address": { "city": "Paris" }, "work": { "city": "Paris" } } },
+            { "id": 3, "tags": [["paris"]] }
+        ]))
+    }
+
+    #[test]
+    fn top_level_returns_enclosing_record_once() {
+        let engine = nested_corpus();
+        let mut results = engine.search("paris", &[], &SearchOptions::default(), 10, 0).unwrap();
+        dedup_results(&mut results, &Dedup::Ancestors);
+        engine.ascend_results(&mut results, Ascend::TopLevel).unwrap();
+
+        let mut ids: Vec<_> = results.iter().map(|r| r.record.value["id"].clone()).collect();
+        ids.sort_by_key(|v| v.as_i64());
+        assert_eq!(ids, vec![json!(1), json!(2), json!(3)]);
+        assert!(results.iter().all(|r| r.record.pointer.matches('/').count() == 1));
+    }
+
+    #[test]
+    fn ascend_walks_up_n_levels() {
+        let engine = nested_corpus();
+        let mut results = engine
+            .search("paris", &[], &SearchOptions::default(), 10, 0)
+            .unwrap();
+        results.retain(|r| r.record.pointer == "/0/profile/address");
+        engine.ascend_results(&mut results, Ascend::Levels(1)).unwrap();
+
+        assert_eq!(results[0].record.pointer, "/0/profile");
+        assert_eq!(results[0].record.value, json!({ "address": { "city": "Paris" } }));
+    }
+
+    #[test]
+    fn ascend_past_the_root_clamps_to_the_top_level_record() {
+        let engine = engine_for(json!([{ "a": { "b": "x" } }, { "a": { "b": "x y" } }]));
+        let mut results = engine
+            .search("x", &[], &SearchOptions::default(), 10, 0)
+            .unwrap();
+        engine.ascend_results(&mut results, Ascend::Levels(5)).unwrap();
+
+        let mut pointers: Vec<&str> = results.iter().map(|r| r.record.pointer.as_str()).collect();
+        pointers.sort();
+        assert_eq!(pointers, ["/0", "/1"]);
+        assert!(results.iter().all(|r| r.record.value.get("a").is_some()));
+    }
+
+    #[test]
+    fn analyze_lists_default_tokens_lowercased() {
+        let engine = Engine::new().unwrap();
+        let tokens = engine
+            .analyze(&json!({ "msg": "Connection-Refused: port 5432", "ok": false }))
+            .unwrap();
+        assert_eq!(tokens, vec!["connection", "refused", "port", "5432", "false"]);
+    }
+
+    #[test]
+    fn analyze_uses_ngram_analyzer_when_built() {
+        let engine = Engine::with_options(&IndexOptions {
+            ngram: Some((3, 3)),
+            ..Default::default()
+        })
+        .unwrap();
+        assert_eq!(engine.analyze(&json!("SQLite")).unwrap(), vec!["sql", "qli", "lit", "ite"]);
+    }
+
+    #[test]
+    fn analyze_keeps_each_value_whole_for_exact() {
+        let engine = Engine::with_options(&IndexOptions {
+            exact: true,
+            ..Default::default()
+        })
+        .unwrap();
+        let tokens = engine
+            .analyze(&json!({ "msg": "Connection-Refused: port 5432", "tags": ["A b", 7], "none": null }))
+            .unwrap();
+        assert_eq!(tokens, vec!["Connection-Refused: port 5432", "A b", "7"]);
+    }
+
+    #[test]
+    fn regex_mode_honors_field() {
+        let engine = engine_for(json!([
+            { "url": "http://a.com/login", "note": "home" },
+            { "url": "http://b.com/home", "note": "login here" }
+        ]));
+        let search = |fields: &[&str]| {
+            let fields: Vec<String> = fields.iter().map(|f| f.to_string()).collect();
+            let results = engine.search("log.n", &fields, &mode(MatchMode::Regex), 10, 0).unwrap();
+            results.into_iter().map(|r| r.record.value["url"].clone()).collect::<Vec<_>>()
+        };
+
+        assert_eq!(search(&[]).len(), 2);
+        assert_eq!(search(&["url"]), [json!("http://a.com/login")]);
+        assert_eq!(search(&["note"]), [json!("http://b.com/home")]);
+        assert!(search(&["missing"]).is_empty());
+
+        // Each alternative stays within the field.
+        let alternation = |fields: &[&str]| {
+            let fields: Vec<String> = fields.iter().map(|f| f.to_string()).collect();
+            engine.search("login|home", &fields, &mode(MatchMode::Regex), 10, 0).unwrap().len()
+        };
+        assert_eq!(alternation(&[]), 2);
+        assert_eq!(alternation(&["url"]), 2);
+        assert_eq!(alternation(&["note"]), 2);
+    }
+
+    #[test]
+    fn prefix_mode_matches_word_starts_in_any_or_chosen_field() {
+        let engine = engine_for(json!([
+            { "msg": "Authorize user", "url": "http://x/login" },
+            { "msg": "oauth token", "url": "http://x/authenticate" },
+            { "msg": "plain", "meta": { "tag": "authority" } },
+            { "msg": "ticket", "code": "A-1234" }
+        ]));
+        let search = |query: &str, fields: &[&str]| {
+            let fields: Vec<String> = fields.iter().map(|f| f.to_string()).collect();
+            let results = engine.search(query, &fields, &mode(MatchMode::Prefix), 10, 0).unwrap();
+            let mut values: Vec<_> = results.into_iter().map(|r| r.record.value).collect();
+            values.sort_by_key(|v| v.to_string());
+            values
+        };
+
+        // `meta` is a record of its own.
+        assert_eq!(search("auth", &[]).len(), 4);
+        // `oauth` contains `auth` but doesn't start with it.
+        assert!(search("auth", &["msg"]).iter().all(|v| v["msg"] != "oauth token"));
+        assert_eq!(search("AUTH us", &[]), [json!({ "msg": "Authorize user", "url": "http://x/login" })]);
+        assert_eq!(search("auth", &["url"]), [json!({ "msg": "oauth token", "url": "http://x/authenticate" })]);
+        assert_eq!(search("auth", &["meta.tag"]), [json!({ "meta": { "tag": "authority" }, "msg": "plain" })]);
+        assert_eq!(search("auth", &["msg", "url"]).len(), 2);
+        assert!(search("oau tok", &["url"]).is_empty());
+        // A numeric word still walks the field's string terms.
+        assert_eq!(search("12", &["code"]), [json!({ "code": "A-1234", "msg": "ticket" })]);
+    }
+
+    #[test]
+    fn ngram_mode_matches_infix_that_text_mode_misses() {
+        let records = extract_records(
+            &json!([{ "db": "PostgreSQL 16" }, { "db": "sqlite" }]),
+            "test.json",
+        );
+        let engine = Engine::with_options(&IndexOptions {
+            ngram: Some((3, 4)),
+            ..Default::default()
+        })
+        .unwrap();
+        engine.index_records(&records).unwrap();
+
+        let ngram = engine.search("gres", &[], &mode(MatchMode::Ngram), 10, 0).unwrap();
+        assert_eq!(ngram.len(), 1);
+        assert_eq!(ngram[0].record.value, json!({ "db": "PostgreSQL 16" }));
+        assert!(engine.search("gres", &["db".to_string()], &mode(MatchMode::Ngram), 10, 0).is_err());
+
+        let text = engine.search("gres", &[], &mode(MatchMode::Text), 10, 0).unwrap();
+        assert!(text.is_empty());
+
+        assert!(engine.search("gr", &[], &mode(MatchMode::Ngram), 10, 0).is_err());
+    }
+}
@@ -0,0 +1,207 @@
+use anyhow::{Context, Result};
+use serde_json::Value;
+use std::collections::HashSet;
+use std::fs::{File, Metadata};
+use std::io::{Read, Seek, SeekFrom};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+use crate::cli::SearchArgs;
+use crate::engine::{
+    dedup_results, filter_equal, filter_presence, Engine, Extract, FieldEq, IndexOptions, Record,
+    SearchOptions,
+};
+use crate::output::{format_result_lines, project_fields, OutputOptions};
+
+/// How often the followed file is checked for appended data.
+pub const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Tail an NDJSON file: index the existing lines, then keep indexing lines
+/// as they are appended and emit every match not emitted before.
+///
+/// Truncation or replacement of the file (log rotation) starts over from the
+/// beginning of the new file. Runs until `stop` is set.
+pub fn follow(
+    path: &Path,
+    args: &SearchArgs,
+    options: &OutputOptions,
+    poll: Duration,
+    stop: &AtomicBool,
+    mut emit: impl FnMut(String),
+) -> Result<()> {
+    let file_label = path.to_string_lossy().to_string();
+    let index_options = IndexOptions::from_args(args)?;
+    let equal = FieldEq::parse_all(&args.eq)?;
+    let schema = args.schema.as_deref().map(crate::schema::load).transpose()?;
+    let mut state = FollowState::new(&index_options)?;
+
+    while !stop.load(Ordering::Relaxed) {
+        let meta = match std::fs::metadata(path) {
+            Ok(meta) => meta,
+            Err(_) => {
+                // Rotated away and not yet recreated.
+                std::thread::sleep(poll);
+                continue;
+            }
+        };
+
+        if state.rotated(&meta) {
+            eprintln!("Warning: {} was truncated or replaced; following from start", file_label);
+            state = FollowState::new(&index_options)?;
+        }
+        state.identity = file_identity(&meta);
+
+        let records = state.read_new_records(path, &file_label, Extract::from_args(args))?;
+        if !records.is_empty() {
+            state.indexed += records.len();
+            state.engine.index_records(&records)?;
+
+            let search_options = SearchOptions::from_args(args)?;
+            let mut results =
+                state.engine.search(&args.query, &args.field, &search_options, state.indexed, 0)?;
+            filter_presence(&mut results, &args.has, &args.missing);
+            filter_equal(&mut results, &equal, args.numbers);
+            if let Some(schema) = &schema {
+                results.retain(|r| schema.is_valid(&r.record.value));
+            }
+            dedup_results(&mut results, args.dedup_policy());
+            results.retain(|r| state.emitted.insert(r.record.pointer.clone()));
+            if search_options.explain {
+                state.engine.explain(&args.query, &args.field, &search_options, &mut results)?;
+            }
+            if args.highlight {
+                state.engine.highlight(&args.query, &args.r#match, &mut results, |value| {
+                    project_fields(value, &options.projection)
+                })?;
+            }
+
+            for line in format_result_lines(&results, options) {
+                emit(line);
+            }
+        }
+
+        std::thread::sleep(poll);
+    }
+
+    Ok(())
+}
+
+struct FollowState {
+    engine: Engine,
+    offset: u64,
+    identity: Option<u64>,
+    pending: Vec<u8>,
+    line: usize,
+    indexed: usize,
+    emitted: HashSet<String>,
+}
+
+impl FollowState {
+    fn new(index_options: &IndexOptions) -> Result<Self> {
+        Ok(FollowState {
+            engine: Engine::with_options(index_options)?,
+            offset: 0,
+            identity: None,
+            pending: Vec::new(),
+            line: 0,
+            indexed: 0,
+            emitted: HashSet::new(),
+        })
+    }
+
+    fn rotated(&self, meta: &Metadata) -> bool {
+        meta.len() < self.offset
+            || (self.identity.is_some() && file_identity(meta) != self.identity)
+    }
+
+    /// Read bytes appended since the last poll and turn every complete line
+    /// into records. A trailing partial line is kept for the next poll.
+    fn read_new_records(&mut self, path: &Path, file_label: &str, extract: Extract) -> Result<Vec<Record>> {
+        let mut file = File::open(path).with_context(|| format!("Failed to open {}", file_label))?;
+        file.seek(SeekFrom::Start(self.offset))
+            .with_context(|| format!("Failed to seek in {}", file_label))?;
+        let read = file
+            .read_to_end(&mut self.pending)
+            .with_context(|| format!("Failed to read {}", file_label))?;
+        self.offset += read as u64;
+
+        let mut records = Vec::new();
+        while let Some(newline) = self.pending.iter().position(|&b| b == b'\n') {
+            let raw: Vec<u8> = self.pending.drain(..=newline).collect();
+            let text = String::from_utf8_lossy(&raw);
+            let text = text.trim();
+            if !text.is_empty() {
+                match serde_json::from_str::<Value>(text) {
+                    Ok(value) => records.extend(extract.line_records(&value, self.line, file_label)),
+                    Err(e) => eprintln!("Warning: skipping {} line {}: {}", file_label, self.line + 1, e),
+                }
+            }
+            self.line += 1;
+        }
+
+        Ok(records)
+    }
+}
+
+#[cfg(unix)]
+fn file_identity(meta: &Metadata) -> Option<u64> {
+    use std::os::unix::fs::MetadataExt;
+    Some(meta.ino())
+}
+
+#[cfg(not(unix))]
+fn file_identity(_meta: &Metadata) -> Option<u64> {
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::follow;
+    use crate::cli::SearchArgs;
+    use crate::output::OutputOptions;
+    use clap::Parser;
+    use std::fs::{self, OpenOptions};
+    use std::io::Write;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::{mpsc, Arc};
+    use std::time::Duration;
+    use tempfile::tempdir;
+
+    #[test]
+    fn follow_emits_appended_matching_line() {
+        let temp = tempdir().unwrap();
+        let path = temp.path().join("app.ndjson");
+        fs::write(&path, "{\"level\":\"info\",\"msg\":\"started\"}\n").unwrap();
+
+        let args = SearchArgs::parse_from(["search", "-q", "error", path.to_str().unwrap()]);
+        let stop = Arc::new(AtomicBool::new(false));
+        let (tx, rx) = mpsc::channel();
+
+        let handle = {
+            let path = path.clone();
+            let stop = Arc::clone(&stop);
+            std::thread::spawn(move || {
+                follow(
+                    &path,
+                    &args,
+                    &OutputOptions::default(),
+                    Duration::from_millis(20),
+                    &stop,
+                    |line| tx.send(line).unwrap(),
+                )
+            })
+        };
+
+        std::thread::sleep(Duration::from_millis(100));
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        writeln!(file, "{{\"level\":\"error\",\"msg\":\"disk full\"}}").unwrap();
+
+        let line = rx.recv_timeout(Duration::from_secs(5)).expect("no match emitted");
+        stop.store(true, Ordering::Relaxed);
+        handle.join().unwrap().unwrap();
+
+        assert_eq!(line, "{\"level\":\"error\",\"msg\":\"disk full\"}");
+        assert!(rx.try_recv().is_err());
+    }
+}
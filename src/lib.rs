@@ -0,0 +1,95 @@
+//! JSON full-text search and pointer-based editing, as used by the `jsonai`
+//! command line tool.
+//!
+//! Records are extracted from a JSON document with [`extract_records`],
+//! indexed by an [`Engine`] and searched with [`Engine::search`]; [`search`]
+//! does all three in one call. Files are edited with the `json_*` functions.
+//!
+//! The library works on parsed documents. Reading inputs (directories, globs,
+//! NDJSON) and assembling the CLI's response envelope from `search` flags
+//! stay in the binary: parse your own JSON and hand it to [`extract_records`],
+//! then build an [`Envelope`] from the results if you want the same shape.
+
+/// Command-line definitions of the `jsonai` binary. Not part of the library
+/// API; the option enums library types take are re-exported at the root.
+#[doc(hidden)]
+pub mod cli;
+pub mod engine;
+pub mod manipulate;
+pub mod output;
+
+// Public only for the `jsonai` binary: the on-disk index, jq filters, JSON
+// Schema checks and the `stats`/`tree` commands are not library API.
+#[doc(hidden)]
+pub mod cache;
+#[doc(hidden)]
+pub mod query;
+#[doc(hidden)]
+pub mod schema;
+#[doc(hidden)]
+pub mod stats;
+#[doc(hidden)]
+pub mod tree;
+
+use anyhow::Result;
+
+pub use cli::{Dedup, FacetCase, Lang, MatchMode, NumberMatch, OutputMode, Scoring};
+pub use engine::{extract_records, Engine, IndexOptions, Record, SearchOptions, SearchResult};
+pub use manipulate::{
+    json_add, json_delete, json_diff, json_merge, json_patch, json_rename, json_set, WriteOptions,
+};
+pub use output::{Envelope, Hit, Meta, OutputOptions, PlanEnvelope};
+
+/// Index `records` in memory and return the top `limit` matches for `query`,
+/// searching `fields` only when any are given. The index is built with
+/// whatever `options` needs: exact or n-gram fields for those match modes,
+/// and the fields its `ranges` filter on.
+pub fn search(
+    records: &[Record],
+    query: &str,
+    fields: &[String],
+    options: &SearchOptions,
+    limit: usize,
+) -> Result<Vec<SearchResult>> {
+    let engine = Engine::with_options(&IndexOptions::for_search(options)?)?;
+    engine.index_records(records)?;
+    let mut results = engine.search(query, fields, options, limit, 0)?;
+    if options.explain {
+        engine.explain(query, fields, options, &mut results)?;
+    }
+    Ok(results)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{extract_records, search, MatchMode, SearchOptions};
+    use crate::engine::NumericRange;
+    use serde_json::json;
+
+    #[test]
+    fn search_indexes_and_queries_records() {
+        let records = extract_records(
+            &json!([{ "name": "alice", "role": "admin" }, { "name": "bob", "role": "user" }]),
+            "users.json",
+        );
+        let results = search(&records, "admin", &[], &SearchOptions::default(), 10).unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].record.pointer, "/0");
+    }
+
+    #[test]
+    fn search_builds_the_fields_ngram_and_range_searches_need() {
+        let records = extract_records(
+            &json!([{ "db": "postgresql", "port": 5432 }, { "db": "postgresql", "port": 80 }]),
+            "services.json",
+        );
+        let options = SearchOptions {
+            match_mode: MatchMode::Ngram,
+            ranges: vec![NumericRange::parse("port:5000..").unwrap()],
+            ..Default::default()
+        };
+        let results = search(&records, "gres", &[], &options, 10).unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].record.value["port"], json!(5432));
+    }
+}
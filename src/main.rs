@@ -1,29 +1,45 @@
-mod cli;
-mod engine;
-mod manipulate;
-mod output;
-mod query;
+mod follow;
+mod serve;
+
+use jsonai::{cache, cli, engine, manipulate, output, query, schema, stats, tree};
 
 use anyhow::{bail, Context, Result};
-use clap::Parser;
+use clap::{Parser, ValueEnum};
+use rayon::prelude::*;
+use serde::Serialize;
 use serde_json::Value;
-use std::io::{self, Read};
+use std::collections::BTreeSet;
+use std::io::{self, Read, Write};
 use std::path::Path;
+use std::time::SystemTime;
 
-use cli::{Cli, Commands, SearchArgs};
-use engine::{dedup_results, extract_records, Engine, Record};
-use output::{format_output, format_plan_output};
+use cli::{Cli, Commands, OnOverflow, SearchArgs};
+use engine::{
+    dedup_results, filter_equal, filter_presence, sort_by_field, sort_by_location, Ascend, Engine,
+    Extract, FieldEq, FieldOption, IndexOptions, Record, SearchOptions, SortKey,
+};
+use output::{
+    format_output, format_plan_output, write_stream, Cursor, Meta, OutputOptions, PlanOptions, Projection,
+};
 
 fn main() {
     let cli = Cli::parse();
 
     // stdout (search/fields): compact by default, --pretty to opt-in
     let stdout_pretty = cli.pretty;
-    // file writes (set/add/delete/patch): pretty by default, --compact to opt-out
-    let file_pretty = !cli.compact;
+    // file writes (set/add/delete/rename/patch/merge): pretty by default, --compact to opt-out
+    let edit = manipulate::WriteOptions {
+        pretty: !cli.compact,
+        inline_max: cli.pretty_arrays_inline,
+        // in-place edits: confirm first when asked to, unless --yes
+        confirm: cli.require_confirm && !cli.yes,
+        strict_pointer: cli.strict_pointer,
+        ..Default::default()
+    };
+    let strict_pointer = cli.strict_pointer;
 
     let exit_code = match cli.command {
-        Commands::Cat(args) => match run_cat(args, stdout_pretty) {
+        Commands::Cat(args) => match run_cat(args, strict_pointer, stdout_pretty) {
             Ok(_) => 0,
             Err(e) => {
                 eprintln!("Error: {:#}", e);
@@ -50,27 +66,29 @@ fn main() {
                 2
             }
         },
-        Commands::Set(args) => match manipulate::json_set(
-            &args.file,
-            &args.pointer,
-            &args.value,
-            args.output.as_deref(),
-            args.dry_run,
-            file_pretty,
-        ) {
+        Commands::Set(args) => match run_set(args, &edit) {
             Ok(_) => 0,
             Err(e) => {
                 eprintln!("Error: {:#}", e);
                 2
             }
         },
-        Commands::Add(args) => match manipulate::json_add(
+        Commands::Add(args) => {
+            let write = edit_options(args.output, args.dry_run, args.stdout, args.backup, &edit);
+            match expand_value(&args.value, args.expand_env, &args.expand_env_default, &write)
+                .and_then(|value| manipulate::json_add(&args.file, &args.pointer, &value, &write))
+            {
+                Ok(_) => 0,
+                Err(e) => {
+                    eprintln!("Error: {:#}", e);
+                    2
+                }
+            }
+        }
+        Commands::Delete(args) => match manipulate::json_delete(
             &args.file,
             &args.pointer,
-            &args.value,
-            args.output.as_deref(),
-            args.dry_run,
-            file_pretty,
+            &edit_options(args.output, args.dry_run, args.stdout, args.backup, &edit),
         ) {
             Ok(_) => 0,
             Err(e) => {
@@ -78,12 +96,11 @@ fn main() {
                 2
             }
         },
-        Commands::Delete(args) => match manipulate::json_delete(
+        Commands::Rename(args) => match manipulate::json_rename(
             &args.file,
             &args.pointer,
-            args.output.as_deref(),
-            args.dry_run,
-            file_pretty,
+            &args.new_name,
+            &write_options(args.output, args.dry_run, args.stdout, &edit),
         ) {
             Ok(_) => 0,
             Err(e) => {
@@ -94,17 +111,86 @@ fn main() {
         Commands::Patch(args) => match manipulate::json_patch(
             &args.file,
             args.patch.as_deref(),
-            args.output.as_deref(),
-            args.dry_run,
-            file_pretty,
+            args.keep_going,
+            args.merge,
+            &edit_options(args.output, args.dry_run, args.stdout, args.backup, &edit),
         ) {
+            Ok(true) => 0,
+            // --keep-going skipped some operations; the rest were written.
+            Ok(false) => 1,
+            Err(e) => {
+                eprintln!("Error: {:#}", e);
+                2
+            }
+        },
+        Commands::Merge(args) => match manipulate::json_merge(
+            &args.base,
+            &args.patch,
+            &write_options(args.output, args.dry_run, args.stdout, &edit),
+        ) {
+            Ok(_) => 0,
+            Err(e) => {
+                eprintln!("Error: {:#}", e);
+                2
+            }
+        },
+        Commands::Diff(args) => match run_diff(args, stdout_pretty) {
+            Ok(true) => 0,
+            Ok(false) => 1,
+            Err(e) => {
+                eprintln!("Error: {:#}", e);
+                2
+            }
+        },
+        Commands::Query(mut args) => match query_inputs(&args.input).and_then(|inputs| {
+            args.input = inputs;
+            query::run_query(&args, stdout_pretty)
+        }) {
+            Ok(true) => 0,
+            Ok(false) => 1,
+            Err(e) => {
+                eprintln!("Error: {:#}", e);
+                2
+            }
+        },
+        Commands::Serve(args) => match run_serve(args, stdout_pretty) {
+            Ok(_) => 0,
+            Err(e) => {
+                eprintln!("Error: {:#}", e);
+                2
+            }
+        },
+        Commands::DebugTokens(args) => match run_debug_tokens(args, strict_pointer, stdout_pretty) {
+            Ok(_) => 0,
+            Err(e) => {
+                eprintln!("Error: {:#}", e);
+                2
+            }
+        },
+        Commands::Distinct(args) => match run_distinct(args, stdout_pretty) {
+            Ok(true) => 0,
+            Ok(false) => 1,
+            Err(e) => {
+                eprintln!("Error: {:#}", e);
+                2
+            }
+        },
+        Commands::Validate(args) => match run_validate(args, stdout_pretty) {
+            Ok(true) => 0,
+            Ok(false) => 1,
+            Err(e) => {
+                eprintln!("Error: {:#}", e);
+                2
+            }
+        },
+        Commands::Stats(args) => match run_stats(args, stdout_pretty) {
             Ok(_) => 0,
             Err(e) => {
                 eprintln!("Error: {:#}", e);
                 2
             }
         },
-        Commands::Query(args) => match query::run_query(&args.filter, &args.input, stdout_pretty) {
+        Commands::Tree(args) => match run_tree(args) {
             Ok(_) => 0,
             Err(e) => {
                 eprintln!("Error: {:#}", e);
@@ -116,24 +202,164 @@ fn main() {
     std::process::exit(exit_code);
 }
 
-fn run_cat(args: cli::CatArgs, pretty: bool) -> Result<()> {
+/// Per-command write destination on top of the global edit settings.
+fn write_options(
+    output: Option<String>,
+    dry_run: bool,
+    stdout: bool,
+    edit: &manipulate::WriteOptions,
+) -> manipulate::WriteOptions {
+    manipulate::WriteOptions {
+        output,
+        dry_run,
+        stdout,
+        ..edit.clone()
+    }
+}
+
+/// The write destination plus `--backup`/`--backup-suffix`/`--force`, for
+/// the commands that take them.
+fn edit_options(
+    output: Option<String>,
+    dry_run: bool,
+    stdout: bool,
+    backup: cli::BackupArgs,
+    edit: &manipulate::WriteOptions,
+) -> manipulate::WriteOptions {
+    manipulate::WriteOptions {
+        backup: backup.suffix.or_else(|| backup.enabled.then(|| ".bak".to_string())),
+        overwrite_backup: backup.force,
+        ..write_options(output, dry_run, stdout, edit)
+    }
+}
+
+fn run_set(args: cli::SetArgs, edit: &manipulate::WriteOptions) -> Result<()> {
+    let write = edit_options(args.output, args.dry_run, args.stdout, args.backup, edit);
+    let value = expand_value(&args.value, args.expand_env, &args.expand_env_default, &write)?;
+    let precondition = match (args.test, args.test_value, args.if_equals) {
+        (Some(pointer), Some(value), _) => Some(manipulate::Precondition {
+            pointer,
+            value: serde_json::from_str(&value)
+                .with_context(|| format!("Invalid JSON in --test-value: {}", value))?,
+        }),
+        // Compare-and-swap: the value being replaced must be the expected one.
+        (_, _, Some(value)) => Some(manipulate::Precondition {
+            pointer: args.pointer.clone(),
+            value: serde_json::from_str(&value)
+                .with_context(|| format!("Invalid JSON in --if-equals: {}", value))?,
+        }),
+        _ => None,
+    };
+    manipulate::json_set(
+        &args.file,
+        &args.pointer,
+        &value,
+        precondition.as_ref(),
+        &write,
+    )
+}
+
+/// The `set`/`add` value with `${VAR}` expanded when `--expand-env` (or a
+/// `--expand-env-default`) is given. An `@file` (or `@-`) value is read
+/// first, so its contents are expanded rather than the file name.
+fn expand_value(value: &str, expand_env: bool, defaults: &[String], write: &manipulate::WriteOptions) -> Result<String> {
+    if !expand_env && defaults.is_empty() {
+        return Ok(value.to_string());
+    }
+    let text = match value.strip_prefix('@') {
+        Some("-") => {
+            write.check_stdin_free("value")?;
+            let mut buf = String::new();
+            io::stdin()
+                .read_to_string(&mut buf)
+                .context("Failed to read value from stdin")?;
+            buf
+        }
+        Some(path) => std::fs::read_to_string(path).with_context(|| format!("Failed to read value file {}", path))?,
+        None => value.to_string(),
+    };
+    manipulate::expand_env(&text, defaults, |name| std::env::var(name).ok())
+}
+
+fn run_cat(args: cli::CatArgs, strict_pointer: bool, pretty: bool) -> Result<()> {
+    write_cat(&args, strict_pointer, pretty, &mut io::stdout().lock())
+}
+
+/// Print the input, or the value `--pointer` resolves to, sliced and
+/// key-sorted as requested.
+fn write_cat(args: &cli::CatArgs, strict_pointer: bool, pretty: bool, out: &mut impl Write) -> Result<()> {
     let value = load_json_value(&args.input)?;
 
     let output_value = match &args.pointer {
-        Some(ptr) => {
-            let resolved = value
-                .pointer(ptr)
-                .with_context(|| format!("Pointer {} not found", ptr))?;
-            resolved.clone()
-        }
+        Some(ptr) => resolve_cat_pointer(&value, ptr, args.null_on_missing, strict_pointer)?,
         None => value,
     };
 
-    let output = output::to_json(&output_value, pretty);
-    println!("{}", output);
+    let output_value = slice_array(output_value, args.head, args.tail, args.strict)?;
+
+    let output = if args.sort_keys {
+        output::to_json_sorted(&output_value, pretty)
+    } else {
+        output::to_json(&output_value, pretty)
+    };
+    writeln!(out, "{}", output)?;
     Ok(())
 }
 
+fn resolve_cat_pointer(
+    value: &Value,
+    pointer: &str,
+    null_on_missing: bool,
+    strict_pointer: bool,
+) -> Result<Value> {
+    // Outside --strict-pointer, anything but an RFC 6901 pointer is read
+    // as a dotted path.
+    let dotted = (!strict_pointer && !pointer.is_empty() && !pointer.starts_with('/')).then_some(pointer);
+    let pointer = match dotted {
+        Some(path) => manipulate::dotted_to_pointer(path),
+        None => pointer.to_string(),
+    };
+    if strict_pointer {
+        manipulate::validate_pointer_strict(&pointer)?;
+    }
+    let pointer = match manipulate::resolve_user_pointer(value, &pointer, strict_pointer) {
+        Ok(pointer) => pointer,
+        Err(_) if null_on_missing => return Ok(Value::Null),
+        Err(e) => return Err(e),
+    };
+    match value.pointer(&pointer) {
+        Some(resolved) => Ok(resolved.clone()),
+        None if null_on_missing => Ok(Value::Null),
+        None => match dotted {
+            Some(path) => bail!("Path {} not found (read as pointer {})", path, pointer),
+            None => bail!("Pointer {} not found", pointer),
+        },
+    }
+}
+
+/// Apply `--head`/`--tail` to an array value. Non-arrays pass through
+/// unchanged unless `strict` is set.
+fn slice_array(value: Value, head: Option<usize>, tail: Option<usize>, strict: bool) -> Result<Value> {
+    if head.is_none() && tail.is_none() {
+        return Ok(value);
+    }
+
+    match value {
+        Value::Array(mut arr) => {
+            if let Some(n) = head {
+                arr.truncate(n);
+            }
+            if let Some(n) = tail {
+                let start = arr.len().saturating_sub(n);
+                arr.drain(..start);
+            }
+            Ok(Value::Array(arr))
+        }
+        _ if strict => bail!("--head/--tail require an array value"),
+        other => Ok(other),
+    }
+}
+
 fn load_json_value(input: &str) -> Result<Value> {
     if input == "-" {
         let mut buf = String::new();
@@ -144,19 +370,162 @@ fn load_json_value(input: &str) -> Result<Value> {
     } else {
         let content =
             std::fs::read_to_string(input).with_context(|| format!("Failed to read {}", input))?;
-        serde_json::from_str(&content).with_context(|| format!("Invalid JSON in {}", input))
+        manipulate::parse_document(&content, input)
     }
 }
 
 fn run_search(args: SearchArgs, pretty: bool) -> Result<bool> {
-    let (records, files_searched) = load_records(&args.input)?;
+    if matches!(args.output, cli::OutputMode::Csv) && (args.stream || args.follow) {
+        bail!("--output csv can't be combined with --stream or --follow: the header row needs every result first");
+    }
+    if args.follow && !args.dump_config {
+        return run_follow(&args);
+    }
+
+    let index_options = IndexOptions::from_args(&args)?;
+    if args.stream_input {
+        let engine = Engine::with_options(&index_options)?;
+        stream_index(&engine, &args)?;
+        let mut stdout = io::stdout().lock();
+        let has_matches = write_search(&engine, &args, FileCounts::single(), pretty, &mut stdout)?;
+        stdout.flush()?;
+        return Ok(has_matches);
+    }
+
+    let (records, files) = load_search_records(&args)?;
+
+    if args.dump_config {
+        println!("{}", output::to_json(&effective_config(&args, &records, files), pretty));
+        return Ok(true);
+    }
+
+    let engine = match &args.index_dir {
+        Some(dir) => cache::open(Path::new(dir), &index_options, Extract::from_args(&args), &records)?,
+        None => {
+            let engine = Engine::with_options(&index_options)?;
+            engine.index_records(&records)?;
+            engine
+        }
+    };
+
+    let mut stdout = io::stdout().lock();
+    let has_matches = write_search(&engine, &args, files, pretty, &mut stdout)?;
+    stdout.flush()?;
+
+    Ok(has_matches)
+}
+
+/// Index a top-level JSON array from the search input (`--stream-input`)
+/// one element at a time, so the document is never held in memory whole.
+fn stream_index(engine: &Engine, args: &SearchArgs) -> Result<()> {
+    let input = args.input.as_deref().context("No input given")?;
+    check_since(input, args.since.as_deref().map(parse_since).transpose()?)?;
+    let extract = Extract::from_args(args);
+    let (reader, file): (Box<dyn Read>, &str) = if input == "-" {
+        (Box::new(io::stdin().lock()), "stdin")
+    } else {
+        let handle = std::fs::File::open(input).with_context(|| format!("Failed to read {}", input))?;
+        (Box::new(handle), input)
+    };
+
+    let mut writer = engine.writer()?;
+    let mut count = 0;
+    for_each_array_element(io::BufReader::new(reader), |index, value| {
+        for record in extract.line_records(&value, index, file) {
+            writer.add(&record)?;
+            count += 1;
+        }
+        Ok(())
+    })
+    .with_context(|| format!("--stream-input needs a JSON array in {}", file))?;
+    writer.commit()?;
+
+    if count == 0 {
+        bail!("No JSON objects found in input");
+    }
+    Ok(())
+}
+
+/// Parse a JSON array from `reader`, handing each element to `each` as soon
+/// as it is read.
+fn for_each_array_element(
+    reader: impl Read,
+    each: impl FnMut(usize, Value) -> Result<()>,
+) -> Result<()> {
+    use serde::de::{Deserializer, Error, SeqAccess, Visitor};
+
+    struct Elements<F>(F);
+
+    impl<'de, F: FnMut(usize, Value) -> Result<()>> Visitor<'de> for Elements<F> {
+        type Value = ();
+
+        fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+            f.write_str("a JSON array")
+        }
+
+        fn visit_seq<A: SeqAccess<'de>>(mut self, mut seq: A) -> Result<(), A::Error> {
+            let mut index = 0;
+            while let Some(value) = seq.next_element::<Value>()? {
+                (self.0)(index, value).map_err(|e| A::Error::custom(format!("{:#}", e)))?;
+                index += 1;
+            }
+            Ok(())
+        }
+    }
+
+    let mut deserializer = serde_json::Deserializer::from_reader(reader);
+    deserializer.deserialize_seq(Elements(each))?;
+    deserializer.end()?;
+    Ok(())
+}
+
+/// Load the records selected by the search input (or `--files-from`),
+/// sorted for deterministic indexing.
+fn load_search_records(args: &SearchArgs) -> Result<(Vec<Record>, FileCounts)> {
+    let since = args.since.as_deref().map(parse_since).transpose()?;
+    let extract = Extract::from_args(args);
+    let (mut records, files) = match (&args.files_from, &args.input) {
+        (Some(manifest), _) => load_files_from(manifest, since, args.strict, extract)?,
+        (None, Some(input)) => load_records(input, since, extract)?,
+        (None, None) => bail!("No input given"),
+    };
 
     if records.is_empty() {
         bail!("No JSON objects found in input");
     }
 
-    let engine = Engine::new()?;
-    engine.index_records(&records)?;
+    sort_records(&mut records);
+    Ok((records, files))
+}
+
+/// Run a search against an already-indexed engine and render the output.
+/// Returns the rendered output and whether anything matched.
+fn execute_search(
+    engine: &Engine,
+    args: &SearchArgs,
+    files: FileCounts,
+    pretty: bool,
+) -> Result<(String, bool)> {
+    let mut buf = Vec::new();
+    let has_matches = write_search(engine, args, files, pretty, &mut buf)?;
+    let output = String::from_utf8(buf).context("Search output is not valid UTF-8")?;
+    Ok((output.trim_end_matches('\n').to_string(), has_matches))
+}
+
+/// Run a search and write its output to `out`. With `--stream`, results are
+/// written one line at a time as they are rendered. Returns whether
+/// anything matched.
+fn write_search(
+    engine: &Engine,
+    args: &SearchArgs,
+    files: FileCounts,
+    pretty: bool,
+    out: &mut dyn Write,
+) -> Result<bool> {
+    let input_label = match &args.files_from {
+        Some(manifest) => format!("--files-from {}", manifest),
+        None => args.input.clone().unwrap_or_default(),
+    };
 
     let fields = if !args.field.is_empty() {
         args.field.clone()
@@ -164,132 +533,866 @@ fn run_search(args: SearchArgs, pretty: bool) -> Result<bool> {
         vec![]
     };
 
-    // When plan mode is possible, fetch more results so facets are accurate
-    let search_limit = if args.plan || !args.no_overflow {
+    let on_overflow = if args.no_overflow {
+        OnOverflow::All
+    } else {
+        args.on_overflow.clone()
+    };
+
+    if args.only_files {
+        let files = matching_files(engine, args, &fields)?;
+        writeln!(out, "{}", output::to_json(&files, pretty))?;
+        return Ok(!files.is_empty());
+    }
+
+    // When plan mode is possible, fetch more results so facets are accurate.
+    // Presence and equality filters and --transform drop results after the
+    // search, location and field order reorder them, and per-file counts
+    // cover them all, so these need every match.
+    let equal = FieldEq::parse_all(&args.eq)?;
+    let transform = args
+        .transform
+        .as_deref()
+        .map(query::compile)
+        .transpose()
+        .context("Invalid --transform filter")?;
+    let sort_key = args.sort.as_deref().map(SortKey::parse).transpose()?;
+    let schema = args.schema.as_deref().map(schema::load).transpose()?;
+    let cursor = args.cursor.as_deref().map(Cursor::decode).transpose()?;
+    let ascend = match (args.top_level, args.ascend) {
+        (true, _) => Some(Ascend::TopLevel),
+        (false, Some(levels)) => Some(Ascend::Levels(levels)),
+        (false, None) => None,
+    };
+    let full_fetch = !args.has.is_empty()
+        || schema.is_some()
+        || !args.missing.is_empty()
+        || !equal.is_empty()
+        || args.order_by_location
+        || sort_key.is_some()
+        || args.count_by_file
+        || !args.group_by.is_empty()
+        || transform.is_some();
+    // A cursor resumes the search itself while results keep the engine's
+    // ranking; otherwise (and after --ascend, which replaces the records)
+    // its place is looked up among every match.
+    let after = cursor
+        .as_ref()
+        .filter(|_| !full_fetch && ascend.is_none())
+        .map(Cursor::search_after);
+    let full_fetch = full_fetch || (cursor.is_some() && after.is_none());
+    let plan_possible = args.plan || on_overflow != OnOverflow::All;
+    // Otherwise one result more than the page tells whether a next page
+    // exists; it is dropped again before counting.
+    let look_ahead = !full_fetch && !plan_possible;
+    let search_limit = if full_fetch {
+        engine.doc_count()?.max(1)
+    } else if plan_possible {
         std::cmp::max(args.limit + args.offset, args.threshold * 2)
     } else {
-        args.limit + args.offset
+        args.limit + args.offset + 1
     };
 
-    let mut results = engine.search(&args.query, &fields, &args.r#match, search_limit, 0)?;
+    let fetch_limit = args.max_fetch.map_or(search_limit, |max| search_limit.min(max));
 
-    dedup_results(&mut results);
+    let search_options = SearchOptions {
+        after,
+        ..SearchOptions::from_args(args)?
+    };
+    let mut results = engine.search(&args.query, &fields, &search_options, fetch_limit, 0)?;
+    // `--max-fetch` cut the fetch short, so counts and facets only cover
+    // the matches fetched.
+    let sampled = (fetch_limit < search_limit && results.len() == fetch_limit).then_some(fetch_limit);
+
+    filter_presence(&mut results, &args.has, &args.missing);
+    filter_equal(&mut results, &equal, args.numbers);
+    if let Some(schema) = &schema {
+        results.retain(|r| schema.is_valid(&r.record.value));
+    }
+    dedup_results(&mut results, args.dedup_policy());
 
+    if let Some(ascend) = ascend {
+        engine.ascend_results(&mut results, ascend)?;
+    }
+    if args.order_by_location {
+        sort_by_location(&mut results);
+    }
+    if let Some(key) = &sort_key {
+        sort_by_field(&mut results, key);
+    }
+    if let Some(filter) = &transform {
+        let mut transformed = Vec::with_capacity(results.len());
+        for mut result in results {
+            if let Some(value) = query::transform(filter, result.record.value)? {
+                result.record.value = value;
+                transformed.push(result);
+            }
+        }
+        results = transformed;
+    }
+
+    let has_more = look_ahead && results.len() > args.limit + args.offset;
+    if look_ahead {
+        results.truncate(args.limit + args.offset);
+    }
     let total_matched = results.len();
+    let options = OutputOptions {
+        map_keys_with_file: files.searched > 1,
+        ..output_options(args, pretty)?
+    };
+    if !args.group_by.is_empty() {
+        output::redact_results(&mut results, &options.projection);
+        let groups = output::group_counts(&results, &args.group_by, &args.facet_case);
+        writeln!(out, "{}", output::to_json(&groups, pretty))?;
+        return Ok(total_matched > 0);
+    }
 
-    // Overflow detection: plan mode forced, or results exceed threshold
-    let overflow = args.plan || (!args.no_overflow && total_matched > args.threshold);
-    if overflow {
+    let offset = match &cursor {
+        Some(_) if search_options.after.is_some() => 0,
+        Some(cursor) => cursor.resume_index(&results)?,
+        None => args.offset,
+    };
+
+    // Size of the page that would be returned, when a byte budget applies
+    let page_bytes = args.overflow_bytes.map(|_| {
+        let start = offset.min(total_matched);
+        let end = start.saturating_add(args.limit).min(total_matched);
+        output::rendered_size(&results[start..end], &options)
+    });
+    let over_bytes = matches!((page_bytes, args.overflow_bytes), (Some(size), Some(max)) if size > max);
+    let over_threshold = total_matched > args.threshold || over_bytes;
+
+    let counts_by_file = args.count_by_file.then(|| {
+        let mut counts = std::collections::BTreeMap::new();
+        for result in &results {
+            *counts.entry(result.record.file.clone()).or_insert(0) += 1;
+        }
+        counts
+    });
+
+    // Overflow detection: plan mode forced, or results exceed threshold or byte budget
+    if args.plan || (over_threshold && on_overflow == OnOverflow::Plan) {
+        output::redact_results(&mut results, &options.projection);
         let output = format_plan_output(
             &results,
             total_matched,
             args.threshold,
-            Some(files_searched),
+            Some(files.searched),
             &args.query,
-            &args.input,
+            &input_label,
+            &PlanOptions {
+                facet_by_file: args.facet_by_file,
+                facet_case: args.facet_case.clone(),
+                sort_keys: args.sort_keys,
+                output_bytes: page_bytes.filter(|_| over_bytes),
+                sampled,
+                counts_by_file,
+                explain: plan_reason(
+                    args.plan,
+                    total_matched,
+                    args.threshold,
+                    page_bytes.zip(args.overflow_bytes).filter(|_| over_bytes),
+                ),
+            },
             pretty,
         );
-        println!("{}", output);
+        writeln!(out, "{}", output)?;
         return Ok(true);
     }
 
-    if args.offset > 0 && args.offset < results.len() {
-        results = results.into_iter().skip(args.offset).collect();
-    }
-
+    results.drain(..offset.min(results.len()));
     if results.len() > args.limit {
         results.truncate(args.limit);
     }
+    let next_cursor = match results.last() {
+        Some(last) if has_more || offset + results.len() < total_matched => Some(Cursor::after(last).encode()),
+        _ => None,
+    };
 
-    let select_fields = args
-        .select
-        .as_ref()
-        .map(|s| s.split(',').map(|f| f.trim().to_string()).collect());
-
-    let output = format_output(
-        &results,
-        total_matched,
-        args.limit,
-        &args.output,
-        args.bare,
-        args.count_only,
-        &select_fields,
-        Some(files_searched),
-        args.max_bytes,
-        pretty,
-    );
+    if search_options.explain {
+        engine.explain(&args.query, &fields, &search_options, &mut results)?;
+    }
 
-    println!("{}", output);
+    if args.highlight {
+        engine.highlight(&args.query, &args.r#match, &mut results, |value| {
+            output::project_fields(value, &options.projection)
+        })?;
+    }
+
+    if let Some(id_field) = &args.id_pointer {
+        for result in &mut results {
+            result.locator =
+                Some(engine.key_locator(&result.record.file, &result.record.pointer, id_field)?);
+        }
+    }
+
+    let meta = Meta {
+        total: total_matched,
+        limit: args.limit,
+        files_searched: Some(files.searched),
+        files_skipped: args.since.as_ref().map(|_| files.skipped),
+        overflow: over_threshold && on_overflow == OnOverflow::Truncate,
+        hint: (args.explain && total_matched == 0).then(|| engine::zero_result_hint(&args.r#match, args.lang)),
+        counts_by_file,
+        next_cursor,
+        ..Default::default()
+    };
+    if args.stream {
+        write_stream(&results, meta, &options, out)?;
+    } else {
+        writeln!(out, "{}", format_output(&results, meta, &options))?;
+    }
 
     Ok(total_matched > 0)
 }
 
-fn load_records(input: &str) -> Result<(Vec<Record>, usize)> {
+/// Why a search answered with a plan: `--plan`, the result count over
+/// `--threshold`, and/or the page size over `--overflow-bytes` (given as
+/// `(size, max)`).
+fn plan_reason(forced: bool, total: usize, threshold: usize, over_bytes: Option<(usize, usize)>) -> String {
+    let mut reasons = Vec::new();
+    if forced {
+        reasons.push("forced by --plan".to_string());
+    }
+    if total > threshold {
+        reasons.push(format!("count {} > threshold {}", total, threshold));
+    }
+    if let Some((size, max)) = over_bytes {
+        reasons.push(format!("output {} bytes > overflow-bytes {}", size, max));
+    }
+    reasons.join("; ")
+}
+
+/// Settings a search actually runs with, after defaults and input
+/// resolution. Printed by `--dump-config`.
+#[derive(Serialize)]
+struct EffectiveConfig {
+    query: String,
+    match_mode: String,
+    fields: Vec<String>,
+    scoring: String,
+    dedup: String,
+    output: String,
+    limit: usize,
+    offset: usize,
+    threshold: usize,
+    on_overflow: String,
+    tokenizer: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    lang: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    max_terms: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    since: Option<String>,
+    input: String,
+    files: Vec<String>,
+    files_searched: usize,
+    files_skipped: usize,
+}
+
+fn effective_config(args: &SearchArgs, records: &[Record], files: FileCounts) -> EffectiveConfig {
+    let on_overflow = if args.no_overflow {
+        OnOverflow::All
+    } else {
+        args.on_overflow.clone()
+    };
+
+    // Records are sorted by file, so consecutive dedup yields each file once.
+    let mut paths: Vec<String> = records.iter().map(|r| r.file.clone()).collect();
+    paths.dedup();
+
+    EffectiveConfig {
+        query: args.query.clone(),
+        match_mode: value_name(&args.r#match),
+        fields: args.field.clone(),
+        scoring: value_name(&args.scoring),
+        dedup: value_name(args.dedup_policy()),
+        output: value_name(&args.output),
+        limit: args.limit,
+        offset: args.offset,
+        threshold: args.threshold,
+        on_overflow: value_name(&on_overflow),
+        tokenizer: engine::TOKENIZER,
+        lang: args.lang.as_ref().map(value_name),
+        max_terms: args.max_terms,
+        since: args.since.clone(),
+        input: match &args.files_from {
+            Some(manifest) => format!("--files-from {}", manifest),
+            None => args.input.clone().unwrap_or_default(),
+        },
+        files: paths,
+        files_searched: files.searched,
+        files_skipped: files.skipped,
+    }
+}
+
+fn value_name<T: ValueEnum>(value: &T) -> String {
+    value
+        .to_possible_value()
+        .map(|v| v.get_name().to_string())
+        .unwrap_or_default()
+}
+
+/// Sorted, distinct files with at least one match (`--only-files`). Every
+/// match is collected, since limits and overflow don't apply to a file list.
+fn matching_files(engine: &Engine, args: &SearchArgs, fields: &[String]) -> Result<Vec<String>> {
+    let mut results = engine.search(
+        &args.query,
+        fields,
+        &SearchOptions::from_args(args)?,
+        engine.doc_count()?.max(1),
+        0,
+    )?;
+    filter_presence(&mut results, &args.has, &args.missing);
+    filter_equal(&mut results, &FieldEq::parse_all(&args.eq)?, args.numbers);
+    if let Some(schema) = args.schema.as_deref().map(schema::load).transpose()? {
+        results.retain(|r| schema.is_valid(&r.record.value));
+    }
+    let files: std::collections::BTreeSet<String> =
+        results.into_iter().map(|r| r.record.file).collect();
+    Ok(files.into_iter().collect())
+}
+
+fn run_serve(args: cli::ServeArgs, pretty: bool) -> Result<()> {
+    let field_options = FieldOption::parse_all(&args.field_option)?;
+    let (mut records, files) = load_records(&args.input, None, Extract::Tree)?;
+    if records.is_empty() {
+        bail!("No JSON objects found in input");
+    }
+    sort_records(&mut records);
+
+    // Requests pick their own match mode, so exact must be ready too.
+    let engine = Engine::with_options(&IndexOptions {
+        field_options,
+        exact: true,
+        ..Default::default()
+    })?;
+    engine.index_records(&records)?;
+
+    let listener = std::net::TcpListener::bind((args.bind.as_str(), args.port))
+        .with_context(|| format!("Failed to bind {}:{}", args.bind, args.port))?;
+    eprintln!("Listening on http://{}", listener.local_addr()?);
+
+    let state = std::sync::Arc::new(serve::ServerState {
+        engine,
+        input: args.input,
+        files,
+        pretty,
+    });
+    serve::serve(listener, state, Default::default())
+}
+
+fn run_debug_tokens(args: cli::DebugTokensArgs, strict_pointer: bool, pretty: bool) -> Result<()> {
+    let value = load_json_value(&args.input)?;
+    let record = match &args.pointer {
+        Some(ptr) => resolve_cat_pointer(&value, ptr, false, strict_pointer)?,
+        None => value,
+    };
+
+    let engine = Engine::with_options(&IndexOptions {
+        ngram: IndexOptions::ngram_range(&args.r#match, args.ngram_min, args.ngram_max)?,
+        exact: matches!(args.r#match, cli::MatchMode::Exact),
+        lang: args.lang,
+        ..Default::default()
+    })?;
+    println!("{}", output::to_json(&engine.analyze(&record)?, pretty));
+    Ok(())
+}
+
+/// Print every distinct value of `--field` across all records of the
+/// input. Returns whether any record had the field.
+fn run_distinct(args: cli::DistinctArgs, pretty: bool) -> Result<bool> {
+    let (records, _) = load_records(&args.input, None, Extract::Tree)?;
+    let values = output::distinct_values(&records, &args.field);
+    println!("{}", output::to_json(&values, pretty));
+    Ok(!values.is_empty())
+}
+
+/// Print the schema violations of the input. Returns whether it is valid.
+fn run_validate(args: cli::ValidateArgs, pretty: bool) -> Result<bool> {
+    let schema = schema::load(&args.schema)?;
+    let value = load_json_value(&args.input)?;
+    let violations = schema::validate(&schema, &value);
+    println!("{}", output::to_json(&violations, pretty));
+    Ok(violations.is_empty())
+}
+
+fn run_stats(args: cli::StatsArgs, pretty: bool) -> Result<()> {
+    let value = load_json_value(&args.input)?;
+    let stats = stats::summarize(&value, &args.input);
+    println!("{}", output::to_json(&stats, pretty));
+    Ok(())
+}
+
+fn run_tree(args: cli::TreeArgs) -> Result<()> {
+    let value = load_json_value(&args.input)?;
+    println!("{}", tree::render(&value, args.depth));
+    Ok(())
+}
+
+/// Print the patch turning `left` into `right`. Returns whether they differ.
+/// Print the patch from the left input to the right one. Returns whether
+/// they are equal, like `diff(1)`'s exit status.
+fn run_diff(args: cli::DiffArgs, pretty: bool) -> Result<bool> {
+    if args.left == "-" && args.right == "-" {
+        bail!("Only one of the two inputs can be stdin");
+    }
+    let left = load_json_value(&args.left)?;
+    let right = load_json_value(&args.right)?;
+    let ops = manipulate::json_diff(&left, &right);
+    println!("{}", output::to_json(&ops, pretty));
+    Ok(ops.is_empty())
+}
+
+fn run_follow(args: &SearchArgs) -> Result<bool> {
+    let input = args.input.as_deref().unwrap_or_default();
+    let path = Path::new(input);
+    if !path.is_file() {
+        bail!("--follow requires a single NDJSON file (got {:?})", input);
+    }
+
+    let options = output_options(args, false)?;
+    let stop = std::sync::atomic::AtomicBool::new(false);
+    follow::follow(path, args, &options, follow::POLL_INTERVAL, &stop, |line| {
+        println!("{}", line);
+        let _ = io::stdout().flush();
+    })?;
+
+    Ok(true)
+}
+
+fn output_options(args: &SearchArgs, pretty: bool) -> Result<OutputOptions> {
+    let select_regex = args
+        .select_regex
+        .as_deref()
+        .map(regex::Regex::new)
+        .transpose()
+        .context("Invalid --select-regex")?;
+    Ok(OutputOptions {
+        mode: args.output.clone(),
+        bare: args.bare,
+        count_only: args.count_only,
+        projection: Projection {
+            select: args.select.as_deref().map(split_field_list),
+            exclude: args.select_exclude.as_deref().map(split_field_list),
+            select_regex,
+            select_regex_deep: args.select_regex_deep,
+            redact: args.redact.clone(),
+            redact_hash: args.redact_hash,
+        },
+        max_bytes: args.max_bytes,
+        pretty,
+        sort_keys: args.sort_keys,
+        value_fields: args.value_field.clone(),
+        clip_record_bytes: args.clip_record_bytes,
+        map_keys_with_file: false,
+    })
+}
+
+/// Order records by (file, pointer) so doc ids, and therefore score ties,
+/// don't depend on filesystem walk order.
+fn sort_records(records: &mut [Record]) {
+    records.sort_by(|a, b| a.file.cmp(&b.file).then_with(|| a.pointer.cmp(&b.pointer)));
+}
+
+/// Split a comma-separated field list, trimming whitespace around entries.
+fn split_field_list(list: &str) -> Vec<String> {
+    list.split(',').map(|f| f.trim().to_string()).collect()
+}
+
+/// Files seen while loading search input.
+#[derive(Default, Clone, Copy)]
+struct FileCounts {
+    searched: usize,
+    /// Matched the input but were older than `--since`
+    skipped: usize,
+}
+
+impl FileCounts {
+    fn single() -> Self {
+        FileCounts {
+            searched: 1,
+            skipped: 0,
+        }
+    }
+}
+
+/// Parse `--since`: a duration before now (`1h`, `30m`) or an RFC 3339
+/// timestamp (`2025-02-22T10:00:00Z`).
+fn parse_since(since: &str) -> Result<SystemTime> {
+    if let Ok(duration) = humantime::parse_duration(since) {
+        return SystemTime::now()
+            .checked_sub(duration)
+            .with_context(|| format!("--since {} reaches before the epoch", since));
+    }
+    humantime::parse_rfc3339_weak(since).map_err(|_| {
+        anyhow::anyhow!(
+            "Invalid --since {:?}: expected a duration like 1h or 30m, or an RFC 3339 timestamp",
+            since
+        )
+    })
+}
+
+fn load_records(
+    input: &str,
+    since: Option<SystemTime>,
+    extract: Extract,
+) -> Result<(Vec<Record>, FileCounts)> {
+    let path = Path::new(input);
+    if input == "-" || path.is_file() {
+        check_since(input, since)?;
+    }
+
     if input == "-" {
         let mut buf = String::new();
         io::stdin()
             .read_to_string(&mut buf)
             .context("Failed to read stdin")?;
         let value: Value = serde_json::from_str(&buf).context("Invalid JSON from stdin")?;
-        let records = extract_records(&value, "stdin");
-        Ok((records, 1))
+        let records = extract.records(&value, "stdin");
+        Ok((records, FileCounts::single()))
     } else {
-        let path = Path::new(input);
+        if path.is_file() {
+            let records = load_file(input, extract)?;
+            Ok((records, FileCounts::single()))
+        } else if path.is_dir() {
+            load_directory(input, since, extract)
+        } else {
+            load_glob(input, since, extract)
+        }
+    }
+}
+
+/// Fail when a single-file input (or stdin, which has no modification time)
+/// can't pass `--since`.
+fn check_since(input: &str, since: Option<SystemTime>) -> Result<()> {
+    match since {
+        Some(_) if input == "-" => bail!("--since compares file modification times, which stdin doesn't have"),
+        Some(since) if modified_before(Path::new(input), since) => {
+            bail!("{} was not modified since the --since cutoff", input)
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Whether `path` was last modified before `since`. Files whose time can't
+/// be read are kept.
+fn modified_before(path: &Path, since: SystemTime) -> bool {
+    let modified = std::fs::metadata(path).and_then(|m| m.modified());
+    modified.map(|m| m < since).unwrap_or(false)
+}
+
+/// Load exactly the files listed (one per line) in `manifest`, or stdin for
+/// "-", leaving out those older than `since`. Globs and gitignore rules are
+/// not applied.
+fn load_files_from(
+    manifest: &str,
+    since: Option<SystemTime>,
+    strict: bool,
+    extract: Extract,
+) -> Result<(Vec<Record>, FileCounts)> {
+    let listing = if manifest == "-" {
+        let mut buf = String::new();
+        io::stdin()
+            .read_to_string(&mut buf)
+            .context("Failed to read file list from stdin")?;
+        buf
+    } else {
+        std::fs::read_to_string(manifest)
+            .with_context(|| format!("Failed to read file list {}", manifest))?
+    };
+
+    let mut all_records = Vec::new();
+    let mut file_count = 0;
+    let mut skipped = 0;
+
+    for path in listing.lines().map(str::trim).filter(|l| !l.is_empty()) {
+        if since.is_some_and(|since| modified_before(Path::new(path), since)) {
+            skipped += 1;
+            continue;
+        }
+        match load_file(path, extract) {
+            Ok(records) => {
+                all_records.extend(records);
+                file_count += 1;
+            }
+            Err(e) if strict => return Err(e),
+            Err(e) => {
+                eprintln!("Warning: skipping {}: {}", path, e);
+            }
+        }
+    }
+
+    if file_count == 0 {
+        if skipped > 0 {
+            bail!(
+                "No files listed in {} were modified since the --since cutoff ({} skipped)",
+                manifest,
+                skipped
+            );
+        }
+        bail!("No JSON files could be loaded from {}", manifest);
+    }
+
+    Ok((
+        all_records,
+        FileCounts {
+            searched: file_count,
+            skipped,
+        },
+    ))
+}
+
+/// How an input file is parsed, chosen by its extension.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum InputFormat {
+    /// One JSON document
+    Json,
+    /// One JSON value per line (`.ndjson`, `.jsonl`)
+    Ndjson,
+    /// One YAML document (`.yaml`, `.yml`)
+    Yaml,
+}
+
+impl InputFormat {
+    /// Format for a known extension; `None` for anything else.
+    fn from_path(path: &Path) -> Option<Self> {
+        match path.extension()?.to_str()?.to_ascii_lowercase().as_str() {
+            "json" => Some(InputFormat::Json),
+            "ndjson" | "jsonl" => Some(InputFormat::Ndjson),
+            "yaml" | "yml" => Some(InputFormat::Yaml),
+            _ => None,
+        }
+    }
+}
+
+/// Load one file, parsed by extension. Files with an unknown extension are
+/// read as JSON.
+fn load_file(path: &str, extract: Extract) -> Result<Vec<Record>> {
+    let content =
+        std::fs::read_to_string(path).with_context(|| format!("Failed to read {}", path))?;
+
+    match InputFormat::from_path(Path::new(path)).unwrap_or(InputFormat::Json) {
+        InputFormat::Json => match serde_json::from_str::<Value>(&content) {
+            Ok(value) => Ok(extract.records(&value, path)),
+            Err(_) if looks_like_ndjson(&content) => load_lines(&content, path, extract),
+            Err(e) => Err(e).with_context(|| format!("Invalid JSON in {}", path)),
+        },
+        InputFormat::Ndjson => load_lines(&content, path, extract),
+        InputFormat::Yaml => Ok(extract.records(&manipulate::parse_document(&content, path)?, path)),
+    }
+}
+
+/// Whether a file that failed to parse as one document is line-delimited
+/// instead: several non-empty lines, the first a complete JSON value.
+fn looks_like_ndjson(content: &str) -> bool {
+    let mut lines = content.lines().filter(|l| !l.trim().is_empty());
+    let first_parses = lines
+        .next()
+        .is_some_and(|line| serde_json::from_str::<Value>(line).is_ok());
+    first_parses && lines.next().is_some()
+}
+
+/// Records of an NDJSON file, pointers prefixed with the 0-based line index.
+/// A line that fails to parse is skipped with a warning; the file only
+/// fails when no line parses.
+fn load_lines(content: &str, path: &str, extract: Extract) -> Result<Vec<Record>> {
+    let mut records = Vec::new();
+    let mut parsed = 0;
+    let mut first_error = None;
+
+    for (line, text) in content.lines().enumerate() {
+        if text.trim().is_empty() {
+            continue;
+        }
+        match serde_json::from_str::<Value>(text) {
+            Ok(value) => {
+                parsed += 1;
+                records.extend(extract.line_records(&value, line, path));
+            }
+            Err(e) => {
+                eprintln!("Warning: skipping {} line {}: {}", path, line + 1, e);
+                first_error.get_or_insert((line, e));
+            }
+        }
+    }
 
-        if path.is_file() {
-            let records = load_file(input)?;
-            Ok((records, 1))
-        } else if path.is_dir() {
-            load_directory(input)
-        } else {
-            load_glob(input)
+    match first_error {
+        Some((line, e)) if parsed == 0 => {
+            Err(e).with_context(|| format!("Invalid JSON in {} line {}", path, line + 1))
         }
+        _ => Ok(records),
     }
 }
 
-fn load_file(path: &str) -> Result<Vec<Record>> {
-    let content =
-        std::fs::read_to_string(path).with_context(|| format!("Failed to read {}", path))?;
-    let value: Value =
-        serde_json::from_str(&content).with_context(|| format!("Invalid JSON in {}", path))?;
-    Ok(extract_records(&value, path))
+/// Load every file under `dir` in a known format (`.json`, `.ndjson`,
+/// `.jsonl`, `.yaml`, `.yml`).
+fn load_directory(
+    dir: &str,
+    since: Option<SystemTime>,
+    extract: Extract,
+) -> Result<(Vec<Record>, FileCounts)> {
+    let pattern = format!("{}/**/*", dir);
+    load_matching(&pattern, since, extract, |path| InputFormat::from_path(path).is_some())
 }
 
-fn load_directory(dir: &str) -> Result<(Vec<Record>, usize)> {
-    let pattern = format!("{}/**/*.json", dir);
-    load_glob(&pattern)
+fn load_glob(
+    pattern: &str,
+    since: Option<SystemTime>,
+    extract: Extract,
+) -> Result<(Vec<Record>, FileCounts)> {
+    load_matching(pattern, since, extract, |_| true)
 }
 
-fn load_glob(pattern: &str) -> Result<(Vec<Record>, usize)> {
-    let matcher = glob::Pattern::new(pattern).context("Invalid glob pattern")?;
+/// The files matching `pattern` that `accept` also lets through, skipping
+/// gitignored ones.
+fn matching_paths(pattern: &str, accept: impl Fn(&Path) -> bool) -> Result<Vec<std::path::PathBuf>> {
+    let matchers = expand_braces(pattern)
+        .iter()
+        .map(|p| glob::Pattern::new(p))
+        .collect::<Result<Vec<_>, _>>()
+        .context("Invalid glob pattern")?;
     let search_root = glob_search_root(pattern);
     let walk_root = glob_walk_root(&search_root);
 
+    let mut paths = walk_files_respecting_gitignore(&walk_root)?;
+    paths.retain(|path| matchers.iter().any(|m| path_matches_glob(m, path)) && accept(path));
+    Ok(paths)
+}
+
+/// Load the files matching `pattern` that `accept` also lets through.
+fn load_matching(
+    pattern: &str,
+    since: Option<SystemTime>,
+    extract: Extract,
+    accept: impl Fn(&Path) -> bool,
+) -> Result<(Vec<Record>, FileCounts)> {
     let mut all_records = Vec::new();
     let mut file_count = 0;
+    let mut skipped = 0;
 
-    for path in walk_files_respecting_gitignore(&walk_root)? {
-        if !path_matches_glob(&matcher, &path) {
+    let mut paths = Vec::new();
+    for path in matching_paths(pattern, accept)? {
+        if since.is_some_and(|since| modified_before(&path, since)) {
+            skipped += 1;
             continue;
         }
 
-        let path_str = path.to_string_lossy().to_string();
-        match load_file(&path_str) {
+        // Records name their file as a string, which later commands take
+        // back as a path; a lossy name would point at the wrong file.
+        match path.into_os_string().into_string() {
+            Ok(path) => paths.push(path),
+            Err(path) => eprintln!("Warning: skipping {}: path is not valid UTF-8", Path::new(&path).display()),
+        }
+    }
+
+    // Files are parsed in parallel; sorting the paths first keeps the
+    // records in the same order from run to run.
+    paths.sort();
+    let loaded: Vec<Result<Vec<Record>>> = paths.par_iter().map(|path| load_file(path, extract)).collect();
+    for (path, result) in paths.iter().zip(loaded) {
+        match result {
             Ok(records) => {
                 all_records.extend(records);
                 file_count += 1;
             }
             Err(e) => {
-                eprintln!("Warning: skipping {}: {}", path_str, e);
+                eprintln!("Warning: skipping {}: {}", path, e);
             }
         }
     }
 
     if file_count == 0 {
+        if skipped > 0 {
+            bail!(
+                "No JSON files matching pattern {} were modified since the --since cutoff ({} skipped)",
+                pattern,
+                skipped
+            );
+        }
         bail!("No JSON files found matching pattern: {}", pattern);
     }
 
-    Ok((all_records, file_count))
+    Ok((
+        all_records,
+        FileCounts {
+            searched: file_count,
+            skipped,
+        },
+    ))
+}
+
+/// The files `query` reads for its inputs: files and "-" as given, then
+/// every file in a known format under a directory, and every file matching
+/// a glob, each sorted by path.
+fn query_inputs(inputs: &[String]) -> Result<Vec<String>> {
+    let mut files = Vec::new();
+    for input in inputs {
+        let path = Path::new(input);
+        // A plain path that doesn't exist is read anyway, for the usual
+        // "Failed to read" error.
+        if input == "-" || path.is_file() || (!path.is_dir() && !is_glob(input)) {
+            files.push(input.clone());
+            continue;
+        }
+
+        let mut paths = if path.is_dir() {
+            matching_paths(&format!("{}/**/*", input), |p| InputFormat::from_path(p).is_some())?
+        } else {
+            matching_paths(input, |_| true)?
+        };
+        if paths.is_empty() {
+            bail!("No JSON files found matching pattern: {}", input);
+        }
+        paths.sort();
+        for path in paths {
+            match path.to_str() {
+                Some(path) => files.push(path.to_string()),
+                None => eprintln!("Warning: skipping {}: path is not valid UTF-8", path.display()),
+            }
+        }
+    }
+    Ok(files)
+}
+
+/// Expand shell-style brace alternatives, which `glob::Pattern` doesn't
+/// support: `data/{users,orders}/*.json` becomes one pattern per
+/// alternative. Braces nest, and braces without a comma are kept as is.
+fn expand_braces(pattern: &str) -> Vec<String> {
+    let bytes = pattern.as_bytes();
+    for (open, _) in pattern.match_indices('{') {
+        let mut depth = 0;
+        let mut commas = Vec::new();
+        let mut close = None;
+        for (i, &b) in bytes.iter().enumerate().skip(open) {
+            match b {
+                b'{' => depth += 1,
+                b'}' => {
+                    depth -= 1;
+                    if depth == 0 {
+                        close = Some(i);
+                        break;
+                    }
+                }
+                b',' if depth == 1 => commas.push(i),
+                _ => {}
+            }
+        }
+        let Some(close) = close else { break };
+        if commas.is_empty() {
+            continue;
+        }
+
+        let (prefix, suffix) = (&pattern[..open], &pattern[close + 1..]);
+        let mut bounds = vec![open];
+        bounds.extend(&commas);
+        bounds.push(close);
+        return bounds
+            .windows(2)
+            .flat_map(|w| expand_braces(&format!("{}{}{}", prefix, &pattern[w[0] + 1..w[1]], suffix)))
+            .collect();
+    }
+    vec![pattern.to_string()]
 }
 
 fn walk_files_respecting_gitignore(root: &Path) -> Result<Vec<std::path::PathBuf>> {
@@ -303,7 +1406,8 @@ fn walk_files_respecting_gitignore(root: &Path) -> Result<Vec<std::path::PathBuf
         .git_global(true)
         .git_exclude(true)
         .parents(true)
-        .require_git(false);
+        .require_git(false)
+        .filter_entry(|entry| !cache::is_cache_path(entry.path()));
 
     for entry in builder.build() {
         let entry = entry.with_context(|| format!("Failed to walk {}", root.display()))?;
@@ -337,10 +1441,19 @@ fn path_has_ignored_runtime_dir(path: &Path) -> bool {
     })
 }
 
+/// Whether `input` has any glob syntax, and so is a pattern rather than a path.
+fn is_glob(input: &str) -> bool {
+    input.contains(is_glob_char)
+}
+
+fn is_glob_char(c: char) -> bool {
+    matches!(c, '*' | '?' | '[' | '{')
+}
+
 fn glob_search_root(pattern: &str) -> std::path::PathBuf {
     let wildcard_start = pattern
         .char_indices()
-        .find(|(_, c)| matches!(c, '*' | '?' | '[' | '{'))
+        .find(|(_, c)| is_glob_char(*c))
         .map(|(idx, _)| idx);
 
     let prefix = wildcard_start.map(|idx| &pattern[..idx]).unwrap_or(pattern);
@@ -403,13 +1516,20 @@ fn path_matches_glob(matcher: &glob::Pattern, path: &Path) -> bool {
 fn run_fields(args: cli::FieldsArgs, pretty: bool) -> Result<()> {
     let content = std::fs::read_to_string(&args.input)
         .with_context(|| format!("Failed to read {}", args.input))?;
-    let value: Value = serde_json::from_str(&content)
-        .with_context(|| format!("Invalid JSON in {}", args.input))?;
+    let value = manipulate::parse_document(&content, &args.input)?;
 
-    let mut fields = Vec::new();
-    collect_field_paths(&value, "", &mut fields);
-    fields.sort();
-    fields.dedup();
+    let fields = if args.schema {
+        schema::field_paths(&value)
+    } else {
+        let walk = FieldWalk {
+            max_depth: args.max_depth,
+            leaves_only: args.leaves_only,
+            sample: args.sample,
+        };
+        let mut fields = BTreeSet::new();
+        collect_field_paths(&value, "", 0, &walk, &mut fields);
+        fields.into_iter().collect()
+    };
 
     let output = output::to_json(&fields, pretty);
     println!("{}", output);
@@ -417,36 +1537,89 @@ fn run_fields(args: cli::FieldsArgs, pretty: bool) -> Result<()> {
     Ok(())
 }
 
-fn collect_field_paths(value: &Value, prefix: &str, fields: &mut Vec<String>) {
+/// Which paths `fields` lists.
+struct FieldWalk {
+    /// Longest path to list, in keys (`--max-depth`)
+    max_depth: Option<usize>,
+    /// List only paths with no fields below them (`--leaves-only`)
+    leaves_only: bool,
+    /// Array elements to look at, from the start; all when unset (`--sample`)
+    sample: Option<usize>,
+}
+
+impl FieldWalk {
+    /// The elements of `arr` whose paths are listed. Their paths are
+    /// unioned, so a key missing from the first element is still found.
+    fn elements<'a>(&self, arr: &'a [Value]) -> impl Iterator<Item = &'a Value> {
+        arr.iter().take(self.sample.unwrap_or(arr.len()))
+    }
+}
+
+fn collect_field_paths(value: &Value, prefix: &str, depth: usize, walk: &FieldWalk, fields: &mut BTreeSet<String>) {
     match value {
         Value::Object(map) => {
+            if walk.max_depth.is_some_and(|max| depth >= max) {
+                return;
+            }
             for (key, val) in map {
                 let path = if prefix.is_empty() {
                     key.clone()
                 } else {
                     format!("{}.{}", prefix, key)
                 };
-                fields.push(path.clone());
-                collect_field_paths(val, &path, fields);
+                // Under --max-depth, a path whose fields are cut off is the
+                // deepest one listed, so it counts as a leaf.
+                let cut_off = walk.max_depth.is_some_and(|max| depth + 1 >= max);
+                if !walk.leaves_only || cut_off || is_leaf(val, walk) {
+                    fields.insert(path.clone());
+                }
+                collect_field_paths(val, &path, depth + 1, walk, fields);
             }
         }
         Value::Array(arr) => {
-            if let Some(first) = arr.first() {
-                collect_field_paths(first, prefix, fields);
+            for element in walk.elements(arr) {
+                collect_field_paths(element, prefix, depth, walk, fields);
             }
         }
         _ => {}
     }
 }
 
+/// Whether `value` holds a value with no fields below it: a scalar, an empty
+/// container, or an array with any such element. An array mixing scalars
+/// and objects is therefore a leaf as well as the parent of its objects'
+/// fields.
+fn is_leaf(value: &Value, walk: &FieldWalk) -> bool {
+    match value {
+        Value::Object(map) => map.is_empty(),
+        Value::Array(arr) => {
+            let mut elements = walk.elements(arr).peekable();
+            elements.peek().is_none() || elements.any(|element| is_leaf(element, walk))
+        }
+        _ => true,
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use super::{load_directory, load_glob};
+    use super::{
+        collect_field_paths, effective_config, execute_search, expand_braces, load_directory, load_file, load_files_from,
+        load_glob, load_records, load_search_records, parse_since, query_inputs, resolve_cat_pointer, run_set, slice_array, write_cat, sort_records,
+        stream_index, Cursor, FieldWalk, FileCounts,
+    };
+    use crate::cli::{CatArgs, SearchArgs, SetArgs};
+    use clap::Parser;
+    use crate::engine::{extract_records, Engine, Extract, IndexOptions, Record, SearchOptions};
     use serde_json::json;
+    use std::collections::BTreeSet;
     use std::fs;
     use std::path::Path;
     use tempfile::tempdir;
 
+    fn new_engine() -> Engine {
+        Engine::with_options(&IndexOptions::default()).unwrap()
+    }
+
     fn write_json(path: &Path, value: serde_json::Value) {
         fs::write(path, serde_json::to_string(&value).unwrap()).unwrap();
     }
@@ -460,13 +1633,65 @@ mod tests {
         write_json(&temp.path().join("target/ignored.json"), json!({ "msg": "ignored" }));
         write_json(&temp.path().join("keep.json"), json!({ "msg": "kept" }));
 
-        let (records, file_count) = load_directory(temp.path().to_str().unwrap()).unwrap();
+        let (records, files) = load_directory(temp.path().to_str().unwrap(), None, Extract::Tree).unwrap();
 
-        assert_eq!(file_count, 1);
+        assert_eq!(files.searched, 1);
         assert!(records.iter().all(|r| !r.file.ends_with("target/ignored.json")));
         assert!(records.iter().any(|r| r.file.ends_with("keep.json")));
     }
 
+    #[test]
+    fn query_inputs_expand_directories_and_globs_in_order() {
+        let temp = tempdir().unwrap();
+        let dir = temp.path().join("data");
+        fs::create_dir(&dir).unwrap();
+        write_json(&dir.join("b.json"), json!(2));
+        write_json(&dir.join("a.json"), json!(1));
+        fs::write(dir.join("notes.txt"), "not json").unwrap();
+        let single = temp.path().join("single.json");
+        write_json(&single, json!(0));
+
+        let names = |inputs: &[String]| {
+            let files = query_inputs(inputs).unwrap();
+            files
+                .iter()
+                .map(|f| Path::new(f).file_name().unwrap().to_str().unwrap().to_string())
+                .collect::<Vec<_>>()
+        };
+        let input = |p: &Path| p.to_str().unwrap().to_string();
+
+        assert_eq!(names(&[input(&single), input(&dir)]), ["single.json", "a.json", "b.json"]);
+        assert_eq!(names(&[format!("{}/*.txt", input(&dir))]), ["notes.txt"]);
+        assert!(query_inputs(&[format!("{}/*.yaml", input(&dir))]).is_err());
+        // Missing plain paths pass through, to fail with the read error.
+        assert_eq!(names(&[input(&dir.join("nosuch.json"))]), ["nosuch.json"]);
+    }
+
+    #[test]
+    fn load_directory_orders_records_by_file_and_skips_bad_files() {
+        let temp = tempdir().unwrap();
+        for (i, name) in ["d", "b", "e", "a", "c"].iter().enumerate() {
+            write_json(&temp.path().join(format!("{}.json", name)), json!([{ "n": i }, { "n": i + 10 }]));
+        }
+        fs::write(temp.path().join("broken.json"), "{ nope").unwrap();
+
+        let (records, files) = load_directory(temp.path().to_str().unwrap(), None, Extract::Tree).unwrap();
+
+        assert_eq!(files.searched, 5);
+        let order: Vec<(String, u64)> = records
+            .iter()
+            .map(|r| {
+                let stem = Path::new(&r.file).file_stem().unwrap().to_str().unwrap().to_string();
+                (stem, r.value["n"].as_u64().unwrap())
+            })
+            .collect();
+        let expected: Vec<(String, u64)> = [("a", 3), ("b", 1), ("c", 4), ("d", 0), ("e", 2)]
+            .iter()
+            .flat_map(|&(name, i)| [(name.to_string(), i), (name.to_string(), i + 10)])
+            .collect();
+        assert_eq!(order, expected);
+    }
+
     #[test]
     fn load_glob_respects_gitignore() {
         let temp = tempdir().unwrap();
@@ -477,13 +1702,41 @@ mod tests {
         write_json(&temp.path().join("keep.json"), json!({ "msg": "kept" }));
 
         let pattern = format!("{}/**/*.json", temp.path().display());
-        let (records, file_count) = load_glob(&pattern).unwrap();
+        let (records, files) = load_glob(&pattern, None, Extract::Tree).unwrap();
 
-        assert_eq!(file_count, 1);
+        assert_eq!(files.searched, 1);
         assert!(records.iter().all(|r| !r.file.ends_with("target/ignored.json")));
         assert!(records.iter().any(|r| r.file.ends_with("keep.json")));
     }
 
+    #[test]
+    fn expand_braces_handles_commas_and_nesting() {
+        assert_eq!(expand_braces("data/*.json"), vec!["data/*.json"]);
+        assert_eq!(expand_braces("{a,b}.json"), vec!["a.json", "b.json"]);
+        assert_eq!(
+            expand_braces("{x,y/{p,q}}/{1,2}"),
+            vec!["x/1", "x/2", "y/p/1", "y/p/2", "y/q/1", "y/q/2"]
+        );
+        assert_eq!(expand_braces("{solo}/{a,}"), vec!["{solo}/a", "{solo}/"]);
+    }
+
+    #[test]
+    fn load_glob_matches_brace_alternative_directories() {
+        let temp = tempdir().unwrap();
+        for dir in ["users", "orders", "logs"] {
+            fs::create_dir(temp.path().join(dir)).unwrap();
+            write_json(&temp.path().join(dir).join("a.json"), json!({ "dir": dir }));
+        }
+
+        let pattern = format!("{}/{{users,orders}}/*.json", temp.path().display());
+        let (records, files) = load_glob(&pattern, None, Extract::Tree).unwrap();
+
+        assert_eq!(files.searched, 2);
+        let mut dirs: Vec<&str> = records.iter().map(|r| r.value["dir"].as_str().unwrap()).collect();
+        dirs.sort();
+        assert_eq!(dirs, vec!["orders", "users"]);
+    }
+
     #[test]
     fn load_directory_ignores_worktoolai_dir() {
         let temp = tempdir().unwrap();
@@ -495,9 +1748,9 @@ mod tests {
         );
         write_json(&temp.path().join("keep.json"), json!({ "msg": "kept" }));
 
-        let (records, file_count) = load_directory(temp.path().to_str().unwrap()).unwrap();
+        let (records, files) = load_directory(temp.path().to_str().unwrap(), None, Extract::Tree).unwrap();
 
-        assert_eq!(file_count, 1);
+        assert_eq!(files.searched, 1);
         assert!(
             records
                 .iter()
@@ -518,9 +1771,9 @@ mod tests {
         write_json(&temp.path().join("keep.json"), json!({ "msg": "kept" }));
 
         let pattern = format!("{}/**/*.json", temp.path().display());
-        let (records, file_count) = load_glob(&pattern).unwrap();
+        let (records, files) = load_glob(&pattern, None, Extract::Tree).unwrap();
 
-        assert_eq!(file_count, 1);
+        assert_eq!(files.searched, 1);
         assert!(
             records
                 .iter()
@@ -528,4 +1781,863 @@ mod tests {
         );
         assert!(records.iter().any(|r| r.file.ends_with("keep.json")));
     }
+
+    #[test]
+    fn load_directory_searches_json_and_ndjson_together() {
+        let temp = tempdir().unwrap();
+        write_json(&temp.path().join("a.json"), json!([{ "level": "error", "src": "json" }]));
+        fs::write(
+            temp.path().join("b.ndjson"),
+            "{\"level\":\"info\"}\n\n{\"level\":\"error\",\"src\":\"ndjson\"}\n",
+        )
+        .unwrap();
+        fs::write(temp.path().join("c.jsonl"), "{not json}\n").unwrap();
+        fs::write(temp.path().join("notes.txt"), "error").unwrap();
+
+        let (mut records, files) = load_directory(temp.path().to_str().unwrap(), None, Extract::Tree).unwrap();
+        assert_eq!(files.searched, 2);
+
+        sort_records(&mut records);
+        let engine = new_engine();
+        engine.index_records(&records).unwrap();
+        let mut found: Vec<(String, String)> = engine
+            .search("error", &[], &SearchOptions::default(), 10, 0)
+            .unwrap()
+            .into_iter()
+            .map(|r| (r.record.value["src"].as_str().unwrap().to_string(), r.record.pointer))
+            .collect();
+        found.sort();
+
+        assert_eq!(
+            found,
+            vec![("json".to_string(), "/0".to_string()), ("ndjson".to_string(), "/2".to_string())]
+        );
+    }
+
+    #[test]
+    fn slice_array_head_and_tail_of_pointer_value() {
+        let doc = json!({ "items": [1, 2, 3, 4, 5] });
+        let items = doc.pointer("/items").unwrap().clone();
+
+        let head = slice_array(items.clone(), Some(2), None, false).unwrap();
+        assert_eq!(head, json!([1, 2]));
+
+        let tail = slice_array(items.clone(), None, Some(2), false).unwrap();
+        assert_eq!(tail, json!([4, 5]));
+
+        let all = slice_array(items, Some(10), None, false).unwrap();
+        assert_eq!(all, json!([1, 2, 3, 4, 5]));
+    }
+
+    #[test]
+    fn slice_array_non_array_passes_through_unless_strict() {
+        let value = json!({ "a": 1 });
+        assert_eq!(slice_array(value.clone(), Some(1), None, false).unwrap(), value);
+        assert!(slice_array(value, Some(1), None, true).is_err());
+    }
+
+    fn search_pointers(mut records: Vec<Record>) -> Vec<(String, String, f32)> {
+        sort_records(&mut records);
+        let engine = new_engine();
+        engine.index_records(&records).unwrap();
+        engine
+            .search("shared", &[], &SearchOptions::default(), 20, 0)
+            .unwrap()
+            .into_iter()
+            .map(|r| (r.record.file, r.record.pointer, r.score))
+            .collect()
+    }
+
+    #[test]
+    fn sorted_records_give_identical_output_across_load_orders() {
+        let mut records = extract_records(&json!([{ "t": "shared" }, { "t": "shared" }]), "b.json");
+        records.extend(extract_records(&json!([{ "t": "shared" }, { "t": "shared" }]), "a.json"));
+
+        let mut shuffled = records.clone();
+        shuffled.reverse();
+        shuffled.swap(0, 2);
+
+        assert_eq!(search_pointers(records), search_pointers(shuffled));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn load_directory_skips_non_utf8_paths() {
+        use std::ffi::OsStr;
+        use std::os::unix::ffi::OsStrExt;
+
+        let temp = tempdir().unwrap();
+        let bad = temp.path().join(OsStr::from_bytes(b"bad\xff.json"));
+        write_json(&bad, json!({ "msg": "hidden" }));
+        write_json(&temp.path().join("good.json"), json!({ "msg": "kept" }));
+
+        let (records, files) = load_directory(temp.path().to_str().unwrap(), None, Extract::Tree).unwrap();
+
+        assert_eq!(files.searched, 1);
+        assert!(records.iter().all(|r| r.file.ends_with("good.json")));
+        assert!(records.iter().all(|r| !r.file.contains('\u{FFFD}')));
+    }
+
+    #[test]
+    fn load_file_reads_line_delimited_json_and_skips_bad_lines() {
+        let temp = tempdir().unwrap();
+        // A .json extension, but one value per line.
+        let path = temp.path().join("export.json");
+        fs::write(&path, "{\"id\":1}\n{oops\n\n{\"id\":3}\n").unwrap();
+
+        let records = load_file(path.to_str().unwrap(), Extract::Tree).unwrap();
+        let pointers: Vec<&str> = records.iter().map(|r| r.pointer.as_str()).collect();
+        assert_eq!(pointers, vec!["/0", "/3"]);
+
+        // A broken single document is still an error, not an empty NDJSON file.
+        fs::write(&path, "{\"id\":\n1,").unwrap();
+        assert!(load_file(path.to_str().unwrap(), Extract::Tree).is_err());
+    }
+
+    #[test]
+    fn load_file_reads_yaml() {
+        let temp = tempdir().unwrap();
+        let path = temp.path().join("config.yml");
+        fs::write(&path, "services:\n  - name: api\n    port: 8080\n  - name: db\n").unwrap();
+
+        let records = load_file(path.to_str().unwrap(), Extract::Tree).unwrap();
+        let api = records.iter().find(|r| r.pointer == "/services/0").unwrap();
+        assert_eq!(api.value, json!({ "name": "api", "port": 8080 }));
+    }
+
+    #[test]
+    fn whole_document_indexes_only_the_root() {
+        let temp = tempdir().unwrap();
+        let path = temp.path().join("config.json");
+        write_json(
+            &path,
+            json!({ "server": { "tls": { "cert": "/etc/ssl/site.pem" } }, "workers": [{ "name": "indexer" }] }),
+        );
+        let path = path.to_str().unwrap();
+
+        let records = load_file(path, Extract::Document).unwrap();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].pointer, "");
+        assert!(load_file(path, Extract::Tree).unwrap().len() > 1);
+
+        let engine = new_engine();
+        engine.index_records(&records).unwrap();
+        for query in ["site.pem", "indexer"] {
+            let results = engine.search(query, &[], &SearchOptions::default(), 10, 0).unwrap();
+            assert_eq!(results.len(), 1, "{}", query);
+            assert_eq!(results[0].record.pointer, "");
+        }
+    }
+
+    #[test]
+    fn load_files_from_loads_only_listed_files() {
+        let temp = tempdir().unwrap();
+        for name in ["a.json", "b.json", "c.json"] {
+            write_json(&temp.path().join(name), json!({ "name": name }));
+        }
+        let manifest = temp.path().join("manifest.txt");
+        fs::write(
+            &manifest,
+            format!(
+                "{}\n\n{}\n",
+                temp.path().join("a.json").display(),
+                temp.path().join("c.json").display()
+            ),
+        )
+        .unwrap();
+
+        let (records, files) = load_files_from(manifest.to_str().unwrap(), None, false, Extract::Tree).unwrap();
+
+        assert_eq!(files.searched, 2);
+        assert!(records.iter().any(|r| r.file.ends_with("a.json")));
+        assert!(records.iter().any(|r| r.file.ends_with("c.json")));
+        assert!(records.iter().all(|r| !r.file.ends_with("b.json")));
+    }
+
+    #[test]
+    fn load_files_from_missing_file_warns_unless_strict() {
+        let temp = tempdir().unwrap();
+        write_json(&temp.path().join("a.json"), json!({ "name": "a" }));
+        let manifest = temp.path().join("manifest.txt");
+        fs::write(
+            &manifest,
+            format!(
+                "{}\n{}\n",
+                temp.path().join("a.json").display(),
+                temp.path().join("missing.json").display()
+            ),
+        )
+        .unwrap();
+        let manifest = manifest.to_str().unwrap();
+
+        let (_, files) = load_files_from(manifest, None, false, Extract::Tree).unwrap();
+        assert_eq!(files.searched, 1);
+        assert!(load_files_from(manifest, None, true, Extract::Tree).is_err());
+    }
+
+    #[test]
+    fn cat_prints_value_at_pointer() {
+        let temp = tempdir().unwrap();
+        let file = temp.path().join("data.json");
+        write_json(&file, json!({ "foo": { "bar": [1, 2] }, "other": true }));
+
+        let cat = |argv: &[&str]| {
+            let args = CatArgs::parse_from([&["cat", file.to_str().unwrap()], argv].concat());
+            let mut out = Vec::new();
+            write_cat(&args, false, false, &mut out).unwrap();
+            String::from_utf8(out).unwrap()
+        };
+
+        assert_eq!(cat(&["-p", "/foo"]), "{\"bar\":[1,2]}\n");
+        assert_eq!(cat(&[]), "{\"foo\":{\"bar\":[1,2]},\"other\":true}\n");
+    }
+
+    #[test]
+    fn resolve_cat_pointer_missing_is_null_when_requested() {
+        let doc = json!({ "a": { "b": 1 } });
+
+        assert_eq!(resolve_cat_pointer(&doc, "/a/b", false, false).unwrap(), json!(1));
+        assert_eq!(resolve_cat_pointer(&doc, "/a/missing", true, false).unwrap(), json!(null));
+        assert!(resolve_cat_pointer(&doc, "/a/missing", false, false).is_err());
+    }
+
+    #[test]
+    fn resolve_cat_pointer_accepts_dotted_paths() {
+        let doc = json!({ "a": { "b": [{ "c": 1 }] }, "x.y": 2 });
+
+        assert_eq!(resolve_cat_pointer(&doc, "a.b.0.c", false, false).unwrap(), json!(1));
+        assert_eq!(resolve_cat_pointer(&doc, "/a/b/0/c", false, true).unwrap(), json!(1));
+        assert_eq!(resolve_cat_pointer(&doc, "/x.y", false, false).unwrap(), json!(2));
+        assert!(resolve_cat_pointer(&doc, "a.b", false, true).is_err());
+
+        let err = resolve_cat_pointer(&doc, "a.missing", false, false).unwrap_err().to_string();
+        assert_eq!(err, "Path a.missing not found (read as pointer /a/missing)");
+    }
+
+    #[test]
+    fn resolve_cat_pointer_accepts_key_locator() {
+        let doc = json!({ "items": [{ "id": "b", "v": 2 }, { "id": "a", "v": 1 }] });
+
+        assert_eq!(resolve_cat_pointer(&doc, "/items/{id=a}/v", false, false).unwrap(), json!(1));
+        assert_eq!(resolve_cat_pointer(&doc, "/items/{id=z}", true, false).unwrap(), json!(null));
+    }
+
+    #[test]
+    fn id_pointer_adds_locator_to_hits() {
+        let engine = new_engine();
+        let doc = json!({ "items": [{ "id": "abc", "name": "widget" }] });
+        engine.index_records(&extract_records(&doc, "inv.json")).unwrap();
+
+        let args = SearchArgs::parse_from([
+            "search", "-q", "widget", "-o", "hit", "--id-pointer", "id", "inv.json",
+        ]);
+        let (output, _) = execute_search(&engine, &args, FileCounts::single(), false).unwrap();
+        let envelope: serde_json::Value = serde_json::from_str(&output).unwrap();
+
+        assert_eq!(envelope["hits"][0]["pointer"], json!("/items/0"));
+        assert_eq!(envelope["hits"][0]["locator"], json!("/items/{id=abc}"));
+    }
+
+    #[test]
+    fn stream_input_indexes_array_elements_like_a_loaded_file() {
+        let temp = tempdir().unwrap();
+        let path = temp.path().join("big.json");
+        write_json(&path, json!([{ "id": 1, "t": "skip" }, { "id": 2, "t": "hit", "tags": [{ "t": "hit" }] }]));
+        let path = path.to_str().unwrap();
+
+        let engine = new_engine();
+        let args = SearchArgs::parse_from(["search", "-q", "hit", "-o", "hit", "--stream-input", path]);
+        stream_index(&engine, &args).unwrap();
+        let (output, _) = execute_search(&engine, &args, FileCounts::single(), false).unwrap();
+        let envelope: serde_json::Value = serde_json::from_str(&output).unwrap();
+        let pointers: Vec<&str> = envelope["hits"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|h| h["pointer"].as_str().unwrap())
+            .collect();
+        assert_eq!(pointers, vec!["/1/tags/0"]);
+
+        write_json(Path::new(path), json!({ "not": "an array" }));
+        let err = stream_index(&new_engine(), &args).unwrap_err();
+        assert!(format!("{:#}", err).contains("needs a JSON array"), "{:#}", err);
+    }
+
+    fn over_threshold_search(extra: &[&str]) -> serde_json::Value {
+        let mut argv = vec!["--threshold", "3"];
+        argv.extend_from_slice(extra);
+        search_five_items(&argv)
+    }
+
+    fn search_five_items(extra: &[&str]) -> serde_json::Value {
+        let items: Vec<serde_json::Value> = (0..5).map(|i| json!({ "id": i, "t": "hit" })).collect();
+        let mut records = extract_records(&serde_json::Value::Array(items), "items.json");
+        sort_records(&mut records);
+        let engine = new_engine();
+        engine.index_records(&records).unwrap();
+
+        let mut argv = vec!["search", "-q", "hit", "--limit", "2"];
+        argv.extend_from_slice(extra);
+        argv.push("items.json");
+        let args = SearchArgs::parse_from(argv);
+
+        let (output, _) = execute_search(&engine, &args, FileCounts::single(), false).unwrap();
+        serde_json::from_str(&output).unwrap()
+    }
+
+    #[test]
+    fn on_overflow_plan_returns_plan() {
+        let output = over_threshold_search(&[]);
+        assert_eq!(output["meta"]["overflow"], json!(true));
+        assert!(output.get("plan").is_some());
+        assert_eq!(output["results"], json!([]));
+    }
+
+    #[test]
+    fn plan_explains_count_trigger() {
+        let output = over_threshold_search(&[]);
+        assert_eq!(output["meta"]["explain"], json!("count 5 > threshold 3"));
+
+        let output = search_five_items(&["--overflow-bytes", "10"]);
+        let explain = output["meta"]["explain"].as_str().unwrap();
+        assert!(explain.starts_with("output ") && explain.ends_with(" bytes > overflow-bytes 10"), "{}", explain);
+    }
+
+    #[test]
+    fn max_fetch_caps_matches_and_plan_notes_sampling() {
+        // Plan mode fetches threshold * 2 = 4 of the 5 matches...
+        let output = search_five_items(&["--threshold", "2"]);
+        assert_eq!(output["meta"]["total"], json!(4));
+        assert!(output["meta"].get("sampled").is_none());
+
+        // ...unless --max-fetch caps it lower.
+        let output = search_five_items(&["--threshold", "2", "--max-fetch", "3"]);
+        assert_eq!(output["meta"]["total"], json!(3));
+        assert_eq!(output["meta"]["sampled"], json!(3));
+        assert_eq!(output["plan"]["facets"]["t"], json!([["hit", 3]]));
+
+        let output = search_five_items(&["--threshold", "2", "--max-fetch", "10"]);
+        assert!(output["meta"].get("sampled").is_none());
+    }
+
+    #[test]
+    fn plan_explains_forced_plan() {
+        let output = search_five_items(&["--plan"]);
+        assert_eq!(output["meta"]["explain"], json!("forced by --plan"));
+
+        let output = over_threshold_search(&["--plan"]);
+        assert_eq!(output["meta"]["explain"], json!("forced by --plan; count 5 > threshold 3"));
+    }
+
+    #[test]
+    fn count_by_file_counts_every_match_despite_limit() {
+        let mut records = extract_records(&json!([{ "t": "hit" }, { "t": "hit" }, { "t": "miss" }]), "a.json");
+        records.extend(extract_records(&json!([{ "t": "hit" }]), "b.json"));
+        sort_records(&mut records);
+        let engine = new_engine();
+        engine.index_records(&records).unwrap();
+        let files = FileCounts {
+            searched: 2,
+            skipped: 0,
+        };
+
+        let args = SearchArgs::parse_from(["search", "-q", "hit", "--limit", "1", "--count-by-file", "data/"]);
+        let (output, _) = execute_search(&engine, &args, files, false).unwrap();
+        let output: serde_json::Value = serde_json::from_str(&output).unwrap();
+        assert_eq!(output["results"].as_array().unwrap().len(), 1);
+        assert_eq!(output["meta"]["counts_by_file"], json!({ "a.json": 2, "b.json": 1 }));
+
+        let args = SearchArgs::parse_from(["search", "-q", "hit", "--plan", "--count-by-file", "data/"]);
+        let (output, _) = execute_search(&engine, &args, files, false).unwrap();
+        let output: serde_json::Value = serde_json::from_str(&output).unwrap();
+        assert!(output.get("plan").is_some());
+        assert_eq!(output["meta"]["counts_by_file"], json!({ "a.json": 2, "b.json": 1 }));
+
+        let args = SearchArgs::parse_from(["search", "-q", "hit", "data/"]);
+        let (output, _) = execute_search(&engine, &args, files, false).unwrap();
+        let output: serde_json::Value = serde_json::from_str(&output).unwrap();
+        assert!(output["meta"].get("counts_by_file").is_none());
+    }
+
+    #[test]
+    fn on_overflow_truncate_returns_limited_results_flagged() {
+        let output = over_threshold_search(&["--on-overflow", "truncate"]);
+        assert_eq!(output["meta"]["overflow"], json!(true));
+        assert_eq!(output["meta"]["total"], json!(5));
+        assert_eq!(output["results"].as_array().unwrap().len(), 2);
+        assert!(output.get("plan").is_none());
+    }
+
+    #[test]
+    fn on_overflow_all_matches_no_overflow() {
+        let all = over_threshold_search(&["--on-overflow", "all"]);
+        let no_overflow = over_threshold_search(&["--no-overflow"]);
+        assert_eq!(all, no_overflow);
+        assert!(all["meta"].get("overflow").is_none());
+        assert_eq!(all["results"].as_array().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn since_skips_files_modified_before_cutoff() {
+        let temp = tempdir().unwrap();
+        write_json(&temp.path().join("old.json"), json!({ "msg": "old" }));
+        write_json(&temp.path().join("new.json"), json!({ "msg": "new" }));
+
+        let two_hours_ago = std::time::SystemTime::now() - std::time::Duration::from_secs(7200);
+        fs::File::options()
+            .write(true)
+            .open(temp.path().join("old.json"))
+            .unwrap()
+            .set_modified(two_hours_ago)
+            .unwrap();
+
+        let since = parse_since("1h").unwrap();
+        let (records, files) = load_directory(temp.path().to_str().unwrap(), Some(since), Extract::Tree).unwrap();
+
+        assert_eq!(files.searched, 1);
+        assert_eq!(files.skipped, 1);
+        assert!(records.iter().all(|r| r.file.ends_with("new.json")));
+
+        // A single file and a --files-from list are held to the cutoff too.
+        let old = temp.path().join("old.json");
+        let new = temp.path().join("new.json");
+        assert!(load_records(old.to_str().unwrap(), Some(since), Extract::Tree).is_err());
+        assert!(load_records(new.to_str().unwrap(), Some(since), Extract::Tree).is_ok());
+        assert!(load_records("-", Some(since), Extract::Tree).is_err());
+
+        let manifest = temp.path().join("manifest.txt");
+        fs::write(&manifest, format!("{}\n{}\n", old.display(), new.display())).unwrap();
+        let (records, files) = load_files_from(manifest.to_str().unwrap(), Some(since), false, Extract::Tree).unwrap();
+        assert_eq!(files.searched, 1);
+        assert_eq!(files.skipped, 1);
+        assert!(records.iter().all(|r| r.file.ends_with("new.json")));
+    }
+
+    #[test]
+    fn parse_since_accepts_durations_and_timestamps() {
+        assert!(parse_since("30m").is_ok());
+        assert_eq!(
+            parse_since("2025-02-22T10:00:00Z").unwrap(),
+            std::time::UNIX_EPOCH + std::time::Duration::from_secs(1740218400)
+        );
+        assert!(parse_since("yesterday-ish").is_err());
+    }
+
+    #[test]
+    fn dump_config_reflects_overridden_limit_and_resolved_files() {
+        let temp = tempdir().unwrap();
+        write_json(&temp.path().join("a.json"), json!({ "msg": "a" }));
+        fs::create_dir_all(temp.path().join("nested")).unwrap();
+        write_json(&temp.path().join("nested/b.json"), json!({ "msg": "b" }));
+        fs::write(temp.path().join("notes.txt"), "not json").unwrap();
+
+        let dir = temp.path().to_str().unwrap();
+        let args = SearchArgs::parse_from(["search", "-q", "msg", "--limit", "5", "--dump-config", dir]);
+        let (records, files) = load_search_records(&args).unwrap();
+        let config = serde_json::to_value(effective_config(&args, &records, files)).unwrap();
+
+        assert_eq!(config["limit"], json!(5));
+        assert_eq!(config["match_mode"], json!("text"));
+        assert_eq!(config["on_overflow"], json!("plan"));
+        assert_eq!(config["files_searched"], json!(2));
+        let expected: Vec<String> = [temp.path().join("a.json"), temp.path().join("nested/b.json")]
+            .iter()
+            .map(|p| p.to_string_lossy().to_string())
+            .collect();
+        assert_eq!(config["files"], json!(expected));
+    }
+
+    #[test]
+    fn sort_keys_applies_to_search_output_only() {
+        let engine = new_engine();
+        engine
+            .index_records(&extract_records(&json!([{ "name": "Ada", "id": 1 }]), "users.json"))
+            .unwrap();
+
+        let args = SearchArgs::parse_from(["search", "-q", "Ada", "--sort-keys", "users.json"]);
+        let (output, _) = execute_search(&engine, &args, FileCounts::single(), false).unwrap();
+        assert!(output.starts_with(r#"{"meta":{"files_searched":1,"limit":20,"#));
+        assert!(output.ends_with(r#""results":[{"id":1,"name":"Ada"}]}"#));
+
+        let args = SearchArgs::parse_from(["search", "-q", "Ada", "users.json"]);
+        let (output, _) = execute_search(&engine, &args, FileCounts::single(), false).unwrap();
+        assert!(output.starts_with(r#"{"meta":{"total":1,"#));
+    }
+
+    #[test]
+    fn cursor_pages_through_every_result_once() {
+        let engine = new_engine();
+        let docs: Vec<serde_json::Value> = (0..7)
+            .map(|i| json!({ "msg": format!("error {}", "x ".repeat(i)), "id": i }))
+            .collect();
+        engine.index_records(&extract_records(&json!(docs), "log.json")).unwrap();
+
+        let run = |extra: &[&str]| {
+            let args = SearchArgs::parse_from([&["search", "-q", "error", "--no-overflow"], extra, &["log.json"]].concat());
+            let (output, _) = execute_search(&engine, &args, FileCounts::single(), false).unwrap();
+            serde_json::from_str::<serde_json::Value>(&output).unwrap()
+        };
+        let ids = |page: &serde_json::Value| -> Vec<u64> {
+            page["results"].as_array().unwrap().iter().map(|r| r["id"].as_u64().unwrap()).collect()
+        };
+
+        let all = ids(&run(&["--limit", "7"]));
+        assert!(run(&["--limit", "7"])["meta"].get("next_cursor").is_none());
+
+        let mut seen = Vec::new();
+        let mut page = run(&["--limit", "3"]);
+        loop {
+            seen.extend(ids(&page));
+            match page["meta"]["next_cursor"].as_str() {
+                Some(cursor) => page = run(&["--limit", "3", "--cursor", cursor]),
+                None => break,
+            }
+        }
+        assert_eq!(seen, all);
+
+        // A page cut short by --max-bytes resumes after its last result.
+        let first = run(&["--limit", "7", "--max-bytes", "150"]);
+        let kept = ids(&first);
+        assert!(kept.len() < 7);
+        let rest = run(&["--limit", "7", "--cursor", first["meta"]["next_cursor"].as_str().unwrap()]);
+        assert_eq!([kept, ids(&rest)].concat(), all);
+
+        let args = SearchArgs::parse_from(["search", "-q", "error", "--cursor", "not a cursor", "log.json"]);
+        assert!(execute_search(&engine, &args, FileCounts::single(), false).is_err());
+
+        // The look-ahead result only tells that a next page exists.
+        let page = run(&["--limit", "2"]);
+        assert_eq!(page["meta"]["total"], json!(2));
+        assert!(page["meta"]["next_cursor"].is_string());
+
+        // Under --scoring none every score ties; the place is found by record.
+        let mut seen = Vec::new();
+        let mut page = run(&["--limit", "3", "--scoring", "none"]);
+        loop {
+            seen.extend(ids(&page));
+            match page["meta"]["next_cursor"].as_str() {
+                Some(cursor) => page = run(&["--limit", "3", "--scoring", "none", "--cursor", cursor]),
+                None => break,
+            }
+        }
+        seen.sort();
+        assert_eq!(seen, (0..7).collect::<Vec<u64>>());
+
+        // A cursor whose record is gone is an error, not a guess.
+        let gone = Cursor {
+            file: "log.json".to_string(),
+            pointer: "/99".to_string(),
+            score: 1.0,
+        };
+        let args = SearchArgs::parse_from(["search", "-q", "error", "--cursor", &gone.encode(), "log.json"]);
+        assert!(execute_search(&engine, &args, FileCounts::single(), false).is_err());
+    }
+
+    #[test]
+    fn redacted_values_stay_out_of_value_group_and_plan_output() {
+        let engine = new_engine();
+        engine
+            .index_records(&extract_records(
+                &json!([{ "event": "login", "password": "hunter2" }, { "event": "login", "password": "hunter2" }]),
+                "users.json",
+            ))
+            .unwrap();
+        let run = |extra: &[&str]| {
+            let mut argv = vec!["search", "-q", "login", "--redact", "password", "users.json"];
+            argv.extend(extra);
+            let (output, _) = execute_search(&engine, &SearchArgs::parse_from(argv), FileCounts::single(), false).unwrap();
+            output
+        };
+
+        for output in [
+            run(&["-o", "value", "--value-field", "password"]),
+            run(&["--group-by", "password"]),
+            run(&["--plan"]),
+        ] {
+            assert!(!output.contains("hunter2"), "{}", output);
+            assert!(output.contains("***"), "{}", output);
+        }
+    }
+
+    #[test]
+    fn explain_adds_hint_only_when_nothing_matched() {
+        let engine = new_engine();
+        engine
+            .index_records(&extract_records(&json!([{ "msg": "Connection Refused" }]), "log.json"))
+            .unwrap();
+
+        let run = |argv: &[&str]| {
+            let args = SearchArgs::parse_from(argv);
+            let (output, _) = execute_search(&engine, &args, FileCounts::single(), false).unwrap();
+            serde_json::from_str::<serde_json::Value>(&output).unwrap()
+        };
+
+        let missed = run(&["search", "-q", "connection refused", "-m", "regex", "--explain", "log.json"]);
+        assert_eq!(missed["meta"]["total"], json!(0));
+        assert!(missed["meta"]["hint"].as_str().unwrap().contains("one whole word"));
+
+        let hit = run(&["search", "-q", "Refused", "-m", "regex", "--explain", "log.json"]);
+        assert_eq!(hit["meta"]["total"], json!(1));
+        assert!(hit["meta"].get("hint").is_none());
+
+        let stemmed = run(&["search", "-q", "zzz", "-m", "regex", "--lang", "en", "--explain", "log.json"]);
+        assert!(stemmed["meta"]["hint"].as_str().unwrap().contains("must match the stem"));
+    }
+
+    #[test]
+    fn schema_keeps_only_valid_records() {
+        let temp = tempdir().unwrap();
+        let schema = temp.path().join("user.schema.json");
+        write_json(&schema, json!({ "required": ["name", "email"] }));
+
+        let engine = new_engine();
+        let doc = json!([{ "name": "ada", "email": "a@x" }, { "name": "ada" }]);
+        engine.index_records(&extract_records(&doc, "users.json")).unwrap();
+
+        let args = SearchArgs::parse_from([
+            "search", "-q", "ada", "--bare", "--schema", schema.to_str().unwrap(), "users.json",
+        ]);
+        let (output, _) = execute_search(&engine, &args, FileCounts::single(), false).unwrap();
+        assert_eq!(output, r#"[{"email":"a@x","name":"ada"}]"#);
+
+        engine.index_records(&extract_records(&json!({ "name": "ada" }), "guests.json")).unwrap();
+        let args = SearchArgs::parse_from([
+            "search", "-q", "ada", "--only-files", "--schema", schema.to_str().unwrap(), "users.json",
+        ]);
+        let (output, _) = execute_search(&engine, &args, FileCounts::single(), false).unwrap();
+        assert_eq!(output, r#"["users.json"]"#);
+    }
+
+    #[test]
+    fn stream_prints_results_then_meta_trailer() {
+        let engine = new_engine();
+        let doc = json!([{ "level": "error", "n": 1 }, { "level": "error", "n": 2 }, { "level": "info" }]);
+        engine.index_records(&extract_records(&doc, "log.json")).unwrap();
+
+        let args = SearchArgs::parse_from(["search", "-q", "error", "--stream", "--limit", "1", "log.json"]);
+        let (output, _) = execute_search(&engine, &args, FileCounts::single(), false).unwrap();
+        let lines: Vec<serde_json::Value> =
+            output.lines().map(|l| serde_json::from_str(l).unwrap()).collect();
+
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0]["level"], json!("error"));
+        assert_eq!(lines[1]["meta"]["total"], json!(2));
+        assert_eq!(lines[1]["meta"]["returned"], json!(1));
+        assert_eq!(lines[1]["meta"]["truncated"], json!(true));
+    }
+
+    #[test]
+    fn only_files_lists_each_matching_file_once() {
+        let temp = tempdir().unwrap();
+        write_json(&temp.path().join("a.json"), json!([{ "level": "error" }, { "level": "error" }]));
+        write_json(&temp.path().join("b.json"), json!({ "level": "info" }));
+        write_json(&temp.path().join("c.json"), json!({ "nested": { "level": "error" } }));
+
+        let dir = temp.path().to_str().unwrap();
+        let args = SearchArgs::parse_from(["search", "-q", "error", "--only-files", "--limit", "1", dir]);
+        let (records, files) = load_search_records(&args).unwrap();
+        let engine = new_engine();
+        engine.index_records(&records).unwrap();
+
+        let (output, matched) = execute_search(&engine, &args, files, false).unwrap();
+        let expected = vec![
+            temp.path().join("a.json").to_string_lossy().to_string(),
+            temp.path().join("c.json").to_string_lossy().to_string(),
+        ];
+        assert!(matched);
+        assert_eq!(serde_json::from_str::<Vec<String>>(&output).unwrap(), expected);
+    }
+
+    #[test]
+    fn order_by_location_sorts_by_file_then_numeric_pointer() {
+        let engine = new_engine();
+        let mut records = Vec::new();
+        for file in ["b.json", "a.json"] {
+            let doc: Vec<serde_json::Value> = (0..12)
+                .map(|i| json!({ "t": if i % 5 == 0 { "hit hit hit" } else { "hit" } }))
+                .collect();
+            records.extend(extract_records(&serde_json::Value::Array(doc), file));
+        }
+        sort_records(&mut records);
+        engine.index_records(&records).unwrap();
+
+        let args = SearchArgs::parse_from([
+            "search", "-q", "hit", "-o", "hit", "--order-by-location", "--dedup", "none", "--no-overflow", "--limit",
+            "14", "in",
+        ]);
+        let (output, _) = execute_search(&engine, &args, FileCounts::single(), false).unwrap();
+        let envelope: serde_json::Value = serde_json::from_str(&output).unwrap();
+        let located: Vec<String> = envelope["hits"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|h| format!("{}{}", h["file"].as_str().unwrap(), h["pointer"].as_str().unwrap()))
+            .collect();
+
+        let mut expected: Vec<String> = (0..12).map(|i| format!("a.json/{}", i)).collect();
+        expected.extend(["b.json/0", "b.json/1"].map(String::from));
+        assert_eq!(located, expected);
+    }
+
+    #[test]
+    fn transform_rewrites_records_and_drops_empty_outputs() {
+        let engine = new_engine();
+        let doc = json!([
+            { "name": "ada", "role": "admin" },
+            { "name": "bob", "role": "dev admin" }
+        ]);
+        engine.index_records(&extract_records(&doc, "users.json")).unwrap();
+
+        let run = |filter: &str| {
+            let args = SearchArgs::parse_from([
+                "search", "-q", "admin", "--order-by-location", "--transform", filter, "--bare", "users.json",
+            ]);
+            let (output, _) = execute_search(&engine, &args, FileCounts::single(), false).unwrap();
+            serde_json::from_str::<serde_json::Value>(&output).unwrap()
+        };
+
+        assert_eq!(
+            run(".name |= ascii_upcase"),
+            json!([{ "name": "ADA", "role": "admin" }, { "name": "BOB", "role": "dev admin" }])
+        );
+        assert_eq!(run("select(.role == \"admin\") | .name"), json!(["ada"]));
+
+        // Records the filter drops don't leave a short page or count in the total.
+        let args = SearchArgs::parse_from([
+            "search", "-q", "admin", "--order-by-location", "--limit", "1", "--no-overflow",
+            "--transform", "select(.name == \"bob\")", "users.json",
+        ]);
+        let (output, _) = execute_search(&engine, &args, FileCounts::single(), false).unwrap();
+        let page: serde_json::Value = serde_json::from_str(&output).unwrap();
+        assert_eq!(page["results"][0]["name"], json!("bob"));
+        assert_eq!(page["meta"]["total"], json!(1));
+
+        // A broken filter is reported even when the output is a plan.
+        let args = SearchArgs::parse_from(["search", "-q", "admin", "--plan", "--transform", ".[", "users.json"]);
+        assert!(execute_search(&engine, &args, FileCounts::single(), false).is_err());
+    }
+
+    #[test]
+    fn overflow_bytes_plans_for_few_huge_records() {
+        let engine = new_engine();
+        let blob = "x".repeat(5_000);
+        let doc = json!([{ "kind": "dump", "body": blob }, { "kind": "dump", "body": blob }]);
+        engine.index_records(&extract_records(&doc, "big.json")).unwrap();
+
+        let run = |extra: &[&str]| {
+            let mut argv = vec!["search", "-q", "dump", "-f", "kind"];
+            argv.extend_from_slice(extra);
+            argv.push("big.json");
+            let args = SearchArgs::parse_from(argv);
+            let (output, _) = execute_search(&engine, &args, FileCounts::single(), false).unwrap();
+            serde_json::from_str::<serde_json::Value>(&output).unwrap()
+        };
+
+        let planned = run(&["--overflow-bytes", "4096"]);
+        assert!(planned.get("plan").is_some());
+        assert!(planned["meta"]["output_bytes"].as_u64().unwrap() > 4096);
+
+        let roomy = run(&["--overflow-bytes", "100000"]);
+        assert_eq!(roomy["meta"]["returned"], json!(2));
+
+        let truncated = run(&["--overflow-bytes", "4096", "--on-overflow", "truncate"]);
+        assert_eq!(truncated["meta"]["overflow"], json!(true));
+    }
+
+    #[test]
+    fn field_paths_respect_max_depth_and_leaves_only() {
+        let doc = json!([{ "id": 1, "user": { "name": "ada", "address": { "city": "x" } }, "tags": ["a"] }]);
+        let paths = |max_depth: Option<usize>, leaves_only: bool| {
+            let mut fields = BTreeSet::new();
+            let walk = FieldWalk {
+                max_depth,
+                leaves_only,
+                sample: None,
+            };
+            collect_field_paths(&doc, "", 0, &walk, &mut fields);
+            fields.into_iter().collect::<Vec<_>>()
+        };
+
+        assert_eq!(paths(None, false), ["id", "tags", "user", "user.address", "user.address.city", "user.name"]);
+        assert_eq!(paths(Some(2), false), ["id", "tags", "user", "user.address", "user.name"]);
+        assert_eq!(paths(None, true), ["id", "tags", "user.address.city", "user.name"]);
+        assert_eq!(paths(Some(2), true), ["id", "tags", "user.address", "user.name"]);
+        assert_eq!(paths(Some(1), true), ["id", "tags", "user"]);
+    }
+
+    #[test]
+    fn field_paths_union_every_array_element() {
+        let doc = json!({ "items": [{ "id": 1 }, { "id": 2, "note": "late" }, { "id": 3, "extra": { "x": true } }] });
+        let paths = |sample: Option<usize>| {
+            let walk = FieldWalk {
+                max_depth: None,
+                leaves_only: false,
+                sample,
+            };
+            let mut fields = BTreeSet::new();
+            collect_field_paths(&doc, "", 0, &walk, &mut fields);
+            fields.into_iter().collect::<Vec<_>>()
+        };
+
+        assert_eq!(paths(None), ["items", "items.extra", "items.extra.x", "items.id", "items.note"]);
+        assert_eq!(paths(Some(2)), ["items", "items.id", "items.note"]);
+    }
+
+    #[test]
+    fn leaves_only_keeps_arrays_that_mix_scalars_and_objects() {
+        let doc = json!({ "tags": ["a", { "k": 1 }], "items": [{ "id": 1 }], "empty": [] });
+        let walk = FieldWalk {
+            max_depth: None,
+            leaves_only: true,
+            sample: None,
+        };
+        let mut fields = BTreeSet::new();
+        collect_field_paths(&doc, "", 0, &walk, &mut fields);
+
+        // `tags` holds a scalar, so it is a leaf besides the parent of `tags.k`.
+        assert_eq!(fields.into_iter().collect::<Vec<_>>(), ["empty", "items.id", "tags", "tags.k"]);
+    }
+
+    #[test]
+    fn set_if_equals_writes_only_over_the_expected_value() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("doc.json");
+        std::fs::write(&path, r#"{"version":3}"#).unwrap();
+        let file = path.to_str().unwrap();
+        let set = |value: &str, expected: &str| {
+            let args = SetArgs::parse_from(["set", "-p", "/version", "--if-equals", expected, value, file]);
+            run_set(args, &Default::default())
+        };
+
+        set("4", "3").unwrap();
+        assert_eq!(std::fs::read_to_string(file).unwrap().trim(), r#"{"version":4}"#);
+
+        let err = set("5", "3").unwrap_err();
+        assert!(format!("{:#}", err).contains("Precondition failed"), "{:#}", err);
+        assert_eq!(std::fs::read_to_string(file).unwrap().trim(), r#"{"version":4}"#);
+    }
+
+    #[test]
+    fn set_expands_variables_inside_a_value_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("doc.json");
+        std::fs::write(&path, r#"{"greeting":null}"#).unwrap();
+        let value = dir.path().join("value.json");
+        std::fs::write(&value, r#"{"text": "${JSONAI_TEST_GREETING}"}"#).unwrap();
+
+        let args = SetArgs::parse_from([
+            "set",
+            "-p",
+            "/greeting",
+            "--expand-env-default",
+            r#"JSONAI_TEST_GREETING=say "hi""#,
+            &format!("@{}", value.display()),
+            path.to_str().unwrap(),
+        ]);
+        run_set(args, &Default::default()).unwrap();
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap().trim(),
+            r#"{"greeting":{"text":"say \"hi\""}}"#
+        );
+    }
 }
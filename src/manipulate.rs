@@ -1,6 +1,6 @@
 use anyhow::{bail, Context, Result};
 use serde_json::Value;
-use std::io::{self, Read};
+use std::io::{self, BufRead, Read, Write};
 
 // ---------------------------------------------------------------------------
 // JSON Pointer helpers
@@ -12,6 +12,83 @@ fn unescape_segment(seg: &str) -> String {
     seg.replace("~1", "/").replace("~0", "~")
 }
 
+/// Escape a single key for use as a JSON Pointer segment per RFC 6901:
+/// `~` -> `~0`, then `/` -> `~1`.
+fn escape_segment(seg: &str) -> String {
+    seg.replace('~', "~0").replace('/', "~1")
+}
+
+/// Guess the RFC 6901 pointer a user meant when they passed a path without
+/// a leading slash: `foo/bar` -> `/foo/bar`, `foo.bar` -> `/foo/bar`.
+fn normalize_pointer_for_error(path: &str) -> String {
+    if path.contains('/') {
+        format!("/{}", path)
+    } else {
+        dotted_to_pointer(path)
+    }
+}
+
+/// RFC 6901 pointer for a dotted path: `a.b.0.c` -> `/a/b/0/c`, each
+/// segment escaped.
+pub fn dotted_to_pointer(path: &str) -> String {
+    path.split('.')
+        .map(|seg| format!("/{}", escape_segment(seg)))
+        .collect()
+}
+
+/// Check a user-supplied pointer up front, before any file is read, so
+/// typos get an actionable message instead of failing mid-operation.
+pub fn validate_pointer(pointer: &str) -> Result<()> {
+    if pointer.is_empty() || pointer.starts_with('/') {
+        return Ok(());
+    }
+    bail!(
+        "JSON Pointer must start with '/' (got {:?}); did you mean `{}`?",
+        pointer,
+        normalize_pointer_for_error(pointer)
+    )
+}
+
+/// `--strict-pointer`: accept only canonical RFC 6901 pointers, empty or
+/// starting with `/`, where every `~` is escaped as `~0` or `~1`.
+pub fn validate_pointer_strict(pointer: &str) -> Result<()> {
+    if !pointer.is_empty() && !pointer.starts_with('/') {
+        bail!(
+            "JSON Pointer must start with '/' (got {:?}); --strict-pointer accepts only RFC 6901 pointers",
+            pointer
+        );
+    }
+    let mut chars = pointer.chars();
+    while let Some(c) = chars.next() {
+        if c == '~' && !matches!(chars.next(), Some('0' | '1')) {
+            bail!(
+                "Invalid escape in JSON Pointer {:?}: '~' must be followed by 0 or 1 (--strict-pointer)",
+                pointer
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Check a user-supplied pointer before any file is read.
+fn check_pointer(pointer: &str, strict: bool) -> Result<()> {
+    if strict {
+        validate_pointer_strict(pointer)
+    } else {
+        validate_pointer(pointer)
+    }
+}
+
+/// Resolve key-based segments against `root`; in strict mode every segment
+/// is taken literally, as RFC 6901 reads it.
+pub fn resolve_user_pointer(root: &Value, pointer: &str, strict: bool) -> Result<String> {
+    if strict {
+        Ok(pointer.to_string())
+    } else {
+        resolve_locator(root, pointer)
+    }
+}
+
 /// Parse a JSON Pointer string into a vector of unescaped segments.
 /// An empty string means the root document (returns an empty vec).
 fn parse_pointer(pointer: &str) -> Result<Vec<String>> {
@@ -23,10 +100,77 @@ fn parse_pointer(pointer: &str) -> Result<Vec<String>> {
     }
     Ok(pointer[1..]
         .split('/')
-        .map(|s| unescape_segment(s))
+        .map(unescape_segment)
         .collect())
 }
 
+/// Key-based array segment for the element whose `field` is `value`, e.g.
+/// `{id=abc}`. Only scalar values can identify an element.
+pub fn key_segment(field: &str, value: &Value) -> Option<String> {
+    scalar_text(value).map(|text| format!("{{{}={}}}", field, text))
+}
+
+/// Split a `{field=value}` segment.
+fn parse_key_segment(seg: &str) -> Option<(&str, &str)> {
+    seg.strip_prefix('{')?.strip_suffix('}')?.split_once('=')
+}
+
+fn scalar_text(value: &Value) -> Option<String> {
+    match value {
+        Value::String(s) => Some(s.clone()),
+        Value::Number(n) => Some(n.to_string()),
+        Value::Bool(b) => Some(b.to_string()),
+        _ => None,
+    }
+}
+
+/// Rewrite key-based array segments (`/items/{id=abc}/name`) into the index
+/// of the element they identify in `root`, giving a plain RFC 6901 pointer.
+/// Pointers without key-based segments are returned unchanged.
+pub fn resolve_locator(root: &Value, pointer: &str) -> Result<String> {
+    if !pointer.contains('{') {
+        return Ok(pointer.to_string());
+    }
+
+    let mut current = Some(root);
+    let mut resolved = String::new();
+    for seg in parse_pointer(pointer)? {
+        let seg = match (current, parse_key_segment(&seg)) {
+            (Some(Value::Array(arr)), Some((field, wanted))) => {
+                let matches: Vec<usize> = arr
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, item)| {
+                        item.get(field).and_then(scalar_text).as_deref() == Some(wanted)
+                    })
+                    .map(|(i, _)| i)
+                    .collect();
+                match matches.as_slice() {
+                    [idx] => idx.to_string(),
+                    [] => bail!("No array element with {}={:?} at {:?}", field, wanted, resolved),
+                    _ => bail!(
+                        "{} array elements have {}={:?} at {:?}; the locator is ambiguous",
+                        matches.len(),
+                        field,
+                        wanted,
+                        resolved
+                    ),
+                }
+            }
+            _ => seg,
+        };
+
+        current = match current {
+            Some(Value::Object(map)) => map.get(&seg),
+            Some(Value::Array(arr)) => seg.parse::<usize>().ok().and_then(|i| arr.get(i)),
+            _ => None,
+        };
+        resolved.push('/');
+        resolved.push_str(&escape_segment(&seg));
+    }
+    Ok(resolved)
+}
+
 /// Navigate a JSON Pointer to obtain a mutable reference to the target value.
 fn resolve_pointer_mut<'a>(root: &'a mut Value, pointer: &str) -> Result<&'a mut Value> {
     let segments = parse_pointer(pointer)?;
@@ -97,55 +241,553 @@ fn resolve_parent_and_key<'a>(
 // File I/O helpers
 // ---------------------------------------------------------------------------
 
-/// Read and parse a JSON file.
-fn read_json_file(file: &str) -> Result<Value> {
+/// Read and parse a JSON (or YAML) file.
+pub(crate) fn read_json_file(file: &str) -> Result<Value> {
     let content =
         std::fs::read_to_string(file).with_context(|| format!("Failed to read {}", file))?;
-    let value: Value =
-        serde_json::from_str(&content).with_context(|| format!("Invalid JSON in {}", file))?;
-    Ok(value)
+    parse_document(&content, file)
+}
+
+/// Whether `path` names a YAML file (`.yaml`, `.yml`). YAML is read into
+/// the JSON data model and written back as YAML; comments are not kept.
+pub fn is_yaml_path(path: &str) -> bool {
+    std::path::Path::new(path)
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("yaml") || ext.eq_ignore_ascii_case("yml"))
+}
+
+/// Parse the content of the file at `path`: YAML for a YAML file, JSON
+/// otherwise.
+pub fn parse_document(content: &str, path: &str) -> Result<Value> {
+    if is_yaml_path(path) {
+        let yaml = serde_yaml::from_str(content).with_context(|| format!("Invalid YAML in {}", path))?;
+        yaml_to_json(yaml).with_context(|| format!("Invalid YAML in {}", path))
+    } else {
+        serde_json::from_str(content).with_context(|| format!("Invalid JSON in {}", path))
+    }
+}
+
+/// Convert YAML to the JSON data model. JSON keys are strings, so scalar
+/// mapping keys (`1:`, `true:`, `~:`) are stringified as JSON would print
+/// them; sequence or mapping keys are rejected. Tags are dropped.
+fn yaml_to_json(yaml: serde_yaml::Value) -> Result<Value> {
+    use serde_yaml::Value as Yaml;
+    Ok(match yaml {
+        Yaml::Mapping(map) => Value::Object(
+            map.into_iter()
+                .map(|(key, value)| Ok((yaml_key(key)?, yaml_to_json(value)?)))
+                .collect::<Result<_>>()?,
+        ),
+        Yaml::Sequence(items) => Value::Array(items.into_iter().map(yaml_to_json).collect::<Result<_>>()?),
+        Yaml::Tagged(tagged) => yaml_to_json(tagged.value)?,
+        scalar => serde_json::to_value(scalar)?,
+    })
+}
+
+fn yaml_key(key: serde_yaml::Value) -> Result<String> {
+    use serde_yaml::Value as Yaml;
+    Ok(match key {
+        Yaml::String(s) => s,
+        Yaml::Number(n) => n.to_string(),
+        Yaml::Bool(b) => b.to_string(),
+        Yaml::Null => "null".to_string(),
+        Yaml::Tagged(tagged) => yaml_key(tagged.value)?,
+        Yaml::Sequence(_) | Yaml::Mapping(_) => bail!("YAML mapping keys must be scalars to be read as JSON"),
+    })
+}
+
+/// Where an edited document goes.
+#[derive(Clone, Default)]
+pub struct WriteOptions {
+    /// Write to this path instead of in place
+    pub output: Option<String>,
+    /// Preview: print the result and write no file
+    pub dry_run: bool,
+    /// Print the result; the input file is left alone, `output` is still written
+    pub stdout: bool,
+    pub pretty: bool,
+    /// When pretty-printing, keep containers of at most this many scalars on one line
+    pub inline_max: Option<usize>,
+    /// Show the changes and ask before overwriting the input file
+    pub confirm: bool,
+    /// Accept only canonical RFC 6901 pointers (`--strict-pointer`)
+    pub strict_pointer: bool,
+    /// Before overwriting the input file, copy it to the file name plus this suffix
+    pub backup: Option<String>,
+    /// Replace a backup left by an earlier run instead of refusing to write
+    pub overwrite_backup: bool,
+}
+
+impl WriteOptions {
+    /// Whether the input file itself gets overwritten.
+    fn writes_in_place(&self) -> bool {
+        !self.dry_run && !self.stdout && self.output.is_none()
+    }
+
+    /// Fail up front when the edit reads `what` from stdin and the
+    /// confirmation prompt would need stdin for its answer too.
+    pub fn check_stdin_free(&self, what: &str) -> Result<()> {
+        if self.confirm && self.writes_in_place() {
+            bail!(
+                "--require-confirm reads its answer from stdin, which holds the {}; read it from a file or pass --yes",
+                what
+            );
+        }
+        Ok(())
+    }
 }
 
 /// Write the JSON value to the appropriate destination.
-/// - dry_run: print to stdout
+fn write_json(value: &Value, file: &str, options: &WriteOptions) -> Result<()> {
+    write_json_confirmed(
+        value,
+        file,
+        options,
+        &mut io::stdout().lock(),
+        &mut io::stdin().lock(),
+        &mut io::stderr(),
+    )
+}
+
+/// Like [`write_json_to`], but with `confirm` set an in-place write first
+/// prints the changes to `prompt` and only goes ahead on a `y` answer read
+/// from `input`.
+fn write_json_confirmed(
+    value: &Value,
+    file: &str,
+    options: &WriteOptions,
+    out: &mut impl Write,
+    input: &mut impl BufRead,
+    prompt: &mut impl Write,
+) -> Result<()> {
+    if options.confirm && options.writes_in_place() {
+        let before = read_json_file(file)?;
+        if !confirm_write(&before, value, file, input, prompt)? {
+            bail!("Aborted: {} was not modified", file);
+        }
+    }
+    write_json_to(value, file, options, out)
+}
+
+/// Print the RFC 6902 operations turning `before` into `after`, one per
+/// line, then ask whether to write `file`. Anything but `y`/`yes` (including
+/// end of input) declines.
+fn confirm_write(
+    before: &Value,
+    after: &Value,
+    file: &str,
+    input: &mut impl BufRead,
+    prompt: &mut impl Write,
+) -> Result<bool> {
+    for op in diff_values(before, after) {
+        writeln!(prompt, "{}", op)?;
+    }
+    write!(prompt, "Write changes to {}? [y/N] ", file)?;
+    prompt.flush()?;
+
+    let mut answer = String::new();
+    input
+        .read_line(&mut answer)
+        .context("Failed to read confirmation")?;
+    Ok(matches!(answer.trim().to_lowercase().as_str(), "y" | "yes"))
+}
+
+/// RFC 6902 patch turning `left` into `right` (`jsonai diff`); applying it
+/// with [`apply_patch`] yields `right`.
+pub fn json_diff(left: &Value, right: &Value) -> Vec<Value> {
+    diff_values(left, right)
+}
+
+/// RFC 6902 operations that turn `before` into `after`, found by walking
+/// both documents together. Objects are compared key by key and arrays as
+/// described at [`diff_array`].
+fn diff_values(before: &Value, after: &Value) -> Vec<Value> {
+    let mut ops = Vec::new();
+    diff_recursive(before, after, "", &mut ops);
+    ops
+}
+
+fn diff_recursive(before: &Value, after: &Value, path: &str, ops: &mut Vec<Value>) {
+    match (before, after) {
+        (Value::Object(old), Value::Object(new)) => {
+            for (key, old_val) in old {
+                let child = format!("{}/{}", path, escape_segment(key));
+                match new.get(key) {
+                    Some(new_val) => diff_recursive(old_val, new_val, &child, ops),
+                    None => ops.push(serde_json::json!({ "op": "remove", "path": child })),
+                }
+            }
+            for (key, new_val) in new {
+                if !old.contains_key(key) {
+                    let child = format!("{}/{}", path, escape_segment(key));
+                    ops.push(serde_json::json!({ "op": "add", "path": child, "value": new_val }));
+                }
+            }
+        }
+        (Value::Array(old), Value::Array(new)) => diff_array(old, new, path, ops),
+        _ if before != after => {
+            ops.push(serde_json::json!({ "op": "replace", "path": path, "value": after }));
+        }
+        _ => {}
+    }
+}
+
+/// Diff two arrays so the ops apply in order without invalidating each other:
+/// in-place changes first (old indices), then removes from the highest index
+/// down, then adds in ascending order of their final index. Elements kept
+/// unchanged are found with a longest common subsequence, so an insert or a
+/// removal near the front doesn't turn into a replace of every later element.
+fn diff_array(old: &[Value], new: &[Value], path: &str, ops: &mut Vec<Value>) {
+    let mut removes = Vec::new();
+    let mut adds = Vec::new();
+    let (mut i, mut j) = (0, 0);
+
+    // Between two kept elements, pair up what changed position by position
+    // and diff it in place; whatever is left over is removed or added.
+    for (kept_i, kept_j) in common_subsequence(old, new).into_iter().chain([(old.len(), new.len())]) {
+        let paired = (kept_i - i).min(kept_j - j);
+        for k in 0..paired {
+            diff_recursive(&old[i + k], &new[j + k], &format!("{}/{}", path, i + k), ops);
+        }
+        removes.extend(i + paired..kept_i);
+        adds.extend(j + paired..kept_j);
+        i = kept_i + 1;
+        j = kept_j + 1;
+    }
+
+    for index in removes.into_iter().rev() {
+        ops.push(serde_json::json!({ "op": "remove", "path": format!("{}/{}", path, index) }));
+    }
+    for index in adds {
+        let child = format!("{}/{}", path, index);
+        ops.push(serde_json::json!({ "op": "add", "path": child, "value": new[index] }));
+    }
+}
+
+/// Above this many cells the LCS table is skipped and arrays are diffed by index.
+const MAX_LCS_CELLS: usize = 4_000_000;
+
+/// Index pairs of the elements `old` and `new` have in common, in order.
+fn common_subsequence(old: &[Value], new: &[Value]) -> Vec<(usize, usize)> {
+    let prefix = old.iter().zip(new).take_while(|(a, b)| a == b).count();
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let (old_mid, new_mid) = (&old[prefix..old.len() - suffix], &new[prefix..new.len() - suffix]);
+
+    let mut pairs: Vec<(usize, usize)> = (0..prefix).map(|k| (k, k)).collect();
+    let (n, m) = (old_mid.len(), new_mid.len());
+    if n > 0 && m > 0 && n * m <= MAX_LCS_CELLS {
+        // lengths[a][b] = LCS length of old_mid[a..] and new_mid[b..]
+        let mut lengths = vec![vec![0u32; m + 1]; n + 1];
+        for a in (0..n).rev() {
+            for b in (0..m).rev() {
+                lengths[a][b] = if old_mid[a] == new_mid[b] {
+                    lengths[a + 1][b + 1] + 1
+                } else {
+                    lengths[a + 1][b].max(lengths[a][b + 1])
+                };
+            }
+        }
+        let (mut a, mut b) = (0, 0);
+        while a < n && b < m {
+            if old_mid[a] == new_mid[b] {
+                pairs.push((prefix + a, prefix + b));
+                a += 1;
+                b += 1;
+            } else if lengths[a + 1][b] >= lengths[a][b + 1] {
+                a += 1;
+            } else {
+                b += 1;
+            }
+        }
+    }
+    pairs.extend((0..suffix).map(|k| (old.len() - suffix + k, new.len() - suffix + k)));
+    pairs
+}
+
+/// - dry_run: print to `out`, write nothing
+/// - stdout: print to `out`, and write `output` only if given
 /// - output is Some: write to that path
 /// - otherwise: overwrite the original file
-fn write_json(value: &Value, file: &str, output: Option<&str>, dry_run: bool, pretty: bool) -> Result<()> {
-    let serialized = if pretty {
-        serde_json::to_string_pretty(value).context("Failed to serialize JSON output")?
-    } else {
-        serde_json::to_string(value).context("Failed to serialize JSON output")?
+///
+/// A YAML destination (or a YAML `file`, when printing) gets YAML.
+fn write_json_to(value: &Value, file: &str, options: &WriteOptions, out: &mut impl Write) -> Result<()> {
+    let serialize = |path: &str| -> Result<String> {
+        if is_yaml_path(path) {
+            let yaml = serde_yaml::to_string(value).context("Failed to serialize YAML output")?;
+            return Ok(yaml.trim_end().to_string());
+        }
+        Ok(match (options.pretty, options.inline_max) {
+            (true, Some(max_inline)) => crate::output::to_json_inline(value, max_inline),
+            (true, None) => {
+                serde_json::to_string_pretty(value).context("Failed to serialize JSON output")?
+            }
+            (false, _) => serde_json::to_string(value).context("Failed to serialize JSON output")?,
+        })
     };
 
-    if dry_run {
-        println!("{}", serialized);
-        return Ok(());
+    if options.dry_run || options.stdout {
+        writeln!(out, "{}", serialize(file)?).context("Failed to write to stdout")?;
+    }
+
+    let dest = match (&options.output, options.dry_run, options.stdout) {
+        (_, true, _) => return Ok(()),
+        (Some(output), _, _) => output.as_str(),
+        (None, _, true) => return Ok(()),
+        (None, _, false) => {
+            if let Some(suffix) = &options.backup {
+                backup_file(file, suffix, options.overwrite_backup)?;
+            }
+            file
+        }
+    };
+    write_atomically(dest, format!("{}\n", serialize(dest)?).as_bytes())
+}
+
+/// Copy `file` to `file` + `suffix`, refusing to replace an existing backup
+/// unless `overwrite` is set.
+fn backup_file(file: &str, suffix: &str, overwrite: bool) -> Result<()> {
+    let backup = format!("{}{}", file, suffix);
+    if !overwrite && std::path::Path::new(&backup).exists() {
+        bail!(
+            "Backup {} already exists; {} was not modified (use --force to overwrite the backup)",
+            backup,
+            file
+        );
+    }
+    std::fs::copy(file, &backup)
+        .with_context(|| format!("Failed to back up {} to {}", file, backup))?;
+    Ok(())
+}
+
+/// Write `contents` to a temporary file next to `dest`, then rename it over
+/// `dest`, so a crash mid-write leaves either the old file or the new one,
+/// never a truncated one. An existing file's permissions, owner and group
+/// are kept, and a symlink is written through: its target is replaced, not
+/// the link. Anything but a regular file (`/dev/null`, a pipe) is written
+/// directly, as a rename would replace it.
+fn write_atomically(dest: &str, contents: &[u8]) -> Result<()> {
+    let resolved = std::fs::canonicalize(dest).unwrap_or_else(|_| dest.into());
+    let path = resolved.as_path();
+    let existing = std::fs::metadata(path).ok();
+    let write_directly = || std::fs::write(path, contents).with_context(|| format!("Failed to write {}", dest));
+    if existing.as_ref().is_some_and(|metadata| !metadata.is_file()) {
+        return write_directly();
     }
 
-    let dest = output.unwrap_or(file);
-    std::fs::write(dest, format!("{}\n", serialized))
+    let dir = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => std::path::Path::new("."),
+    };
+    let mut temp = tempfile::NamedTempFile::new_in(dir).with_context(|| {
+        format!("Failed to create a temporary file in {} to write {}", dir.display(), dest)
+    })?;
+    temp.write_all(contents)
+        .with_context(|| format!("Failed to write {}", dest))?;
+    if let Some(metadata) = &existing {
+        temp.as_file()
+            .set_permissions(metadata.permissions())
+            .with_context(|| format!("Failed to copy the permissions of {}", dest))?;
+        // A file we may write but not give its owner (someone else's, in a
+        // shared group) is overwritten in place rather than taken over.
+        if !copy_owner(temp.as_file(), metadata) {
+            drop(temp);
+            return write_directly();
+        }
+    }
+    // Flush to disk before the rename, or a crash could expose an empty file.
+    temp.as_file()
+        .sync_all()
         .with_context(|| format!("Failed to write {}", dest))?;
+    temp.persist(path)
+        .with_context(|| format!("Failed to replace {}", dest))?;
     Ok(())
 }
 
+/// Give `file` the owner and group of `metadata`. Returns whether it could.
+#[cfg(unix)]
+fn copy_owner(file: &std::fs::File, metadata: &std::fs::Metadata) -> bool {
+    use std::os::unix::fs::MetadataExt;
+    std::os::unix::fs::fchown(file, Some(metadata.uid()), Some(metadata.gid())).is_ok()
+}
+
+#[cfg(not(unix))]
+fn copy_owner(_file: &std::fs::File, _metadata: &std::fs::Metadata) -> bool {
+    true
+}
+
+/// Take an exclusive lock on `file`, held until the returned handle is
+/// dropped, so a read-check-write isn't interleaved with another jsonai
+/// doing the same. Writes replace the file by rename, so after waiting the
+/// lock may be on a file that is no longer at `file`; then the new one is
+/// locked instead.
+fn lock_file(file: &str) -> Result<std::fs::File> {
+    loop {
+        let handle = std::fs::File::open(file).with_context(|| format!("Failed to read {}", file))?;
+        handle.lock().with_context(|| format!("Failed to lock {}", file))?;
+        let current = std::fs::metadata(file).with_context(|| format!("Failed to read {}", file))?;
+        if same_file(&handle.metadata()?, &current) {
+            return Ok(handle);
+        }
+    }
+}
+
+#[cfg(unix)]
+fn same_file(a: &std::fs::Metadata, b: &std::fs::Metadata) -> bool {
+    use std::os::unix::fs::MetadataExt;
+    a.dev() == b.dev() && a.ino() == b.ino()
+}
+
+#[cfg(not(unix))]
+fn same_file(_a: &std::fs::Metadata, _b: &std::fs::Metadata) -> bool {
+    true
+}
+
 // ---------------------------------------------------------------------------
 // Public operations
 // ---------------------------------------------------------------------------
 
+/// Parse a `set`/`add` value: inline JSON, or `@path` to read the JSON from
+/// a file (`@-` reads `stdin`).
+fn parse_value_arg(value_str: &str, mut stdin: impl Read) -> Result<Value> {
+    let Some(source) = value_str.strip_prefix('@') else {
+        return serde_json::from_str(value_str)
+            .with_context(|| format!("Invalid JSON value: {}", value_str));
+    };
+
+    let content = if source == "-" {
+        let mut buf = String::new();
+        stdin
+            .read_to_string(&mut buf)
+            .context("Failed to read value from stdin")?;
+        buf
+    } else {
+        std::fs::read_to_string(source)
+            .with_context(|| format!("Failed to read value file {}", source))?
+    };
+    let label = if source == "-" { "stdin" } else { source };
+    serde_json::from_str(&content).with_context(|| format!("Invalid JSON value in {}", label))
+}
+
+/// Substitute each `${NAME}` in a `set`/`add` value (`--expand-env`) with
+/// `lookup(NAME)`, falling back to a `NAME=VALUE` entry of `defaults`
+/// (`--expand-env-default`, last one wins). A variable with neither is an
+/// error. Inside a JSON string literal the value is substituted escaped, so
+/// quotes and backslashes in it stay part of the string; elsewhere it is
+/// substituted as-is, before the text is parsed as JSON, so `${PORT}` can be
+/// a number. Other `$` characters are left alone.
+pub fn expand_env(
+    text: &str,
+    defaults: &[String],
+    lookup: impl Fn(&str) -> Option<String>,
+) -> Result<String> {
+    let defaults = defaults
+        .iter()
+        .map(|d| {
+            d.split_once('=')
+                .with_context(|| format!("Invalid --expand-env-default {:?}: expected NAME=VALUE", d))
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    let mut expanded = String::with_capacity(text.len());
+    let mut rest = text;
+    let mut in_string = false;
+    let mut escaped = false;
+    while let Some(start) = rest.find("${") {
+        expanded.push_str(&rest[..start]);
+        for c in rest[..start].chars() {
+            match c {
+                _ if escaped => escaped = false,
+                '\\' if in_string => escaped = true,
+                '"' => in_string = !in_string,
+                _ => {}
+            }
+        }
+        let after = &rest[start + 2..];
+        let end = after
+            .find('}')
+            .with_context(|| format!("Unclosed ${{ in value: {}", text))?;
+        let name = &after[..end];
+        if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+            bail!("Invalid variable name {:?} in value: {}", name, text);
+        }
+        let value = lookup(name)
+            .or_else(|| defaults.iter().rev().find(|(n, _)| *n == name).map(|(_, v)| v.to_string()))
+            .with_context(|| {
+                format!(
+                    "Environment variable {} is not set (give a fallback with --expand-env-default {}=...)",
+                    name, name
+                )
+            })?;
+        if in_string {
+            let quoted = Value::String(value).to_string();
+            expanded.push_str(&quoted[1..quoted.len() - 1]);
+        } else {
+            expanded.push_str(&value);
+        }
+        rest = &after[end + 1..];
+    }
+    expanded.push_str(rest);
+    Ok(expanded)
+}
+
+/// A `set --test POINTER --test-value JSON` precondition: the set is made
+/// only if the value at `pointer` equals `value`, like an RFC 6902 `test`.
+pub struct Precondition {
+    pub pointer: String,
+    pub value: Value,
+}
+
+impl Precondition {
+    /// Fail, naming what was found, unless `root` satisfies the precondition.
+    fn check(&self, root: &mut Value, strict: bool) -> Result<()> {
+        check_pointer(&self.pointer, strict)?;
+        let pointer = resolve_user_pointer(root, &self.pointer, strict)?;
+        let actual = resolve_pointer_mut(root, &pointer)
+            .with_context(|| format!("Precondition failed: {} not found", self.pointer))?;
+        if *actual != self.value {
+            bail!(
+                "Precondition failed: value at {} is {}, expected {}",
+                self.pointer,
+                actual,
+                self.value
+            );
+        }
+        Ok(())
+    }
+}
+
 /// Set (replace) the value at `pointer` with `value_str` (parsed as JSON).
 ///
 /// If the pointer addresses the root (""), the entire document is replaced.
+/// With a `precondition`, nothing is written unless it holds; the file is
+/// locked from the check through the write, so concurrent conditional sets
+/// can't both pass the check.
 pub fn json_set(
     file: &str,
     pointer: &str,
     value_str: &str,
-    output: Option<&str>,
-    dry_run: bool,
-    pretty: bool,
+    precondition: Option<&Precondition>,
+    write: &WriteOptions,
 ) -> Result<()> {
+    check_pointer(pointer, write.strict_pointer)?;
+    if value_str == "@-" {
+        write.check_stdin_free("value")?;
+    }
+    let _lock = precondition.map(|_| lock_file(file)).transpose()?;
     let mut root = read_json_file(file)?;
-    let new_value: Value = serde_json::from_str(value_str)
-        .with_context(|| format!("Invalid JSON value: {}", value_str))?;
+    if let Some(precondition) = precondition {
+        precondition
+            .check(&mut root, write.strict_pointer)
+            .with_context(|| format!("{} was not modified", file))?;
+    }
+    let pointer = &resolve_user_pointer(&root, pointer, write.strict_pointer)?;
+    let new_value = parse_value_arg(value_str, io::stdin())?;
 
     if pointer.is_empty() {
         // Replace the entire document.
@@ -180,7 +822,7 @@ pub fn json_set(
         }
     }
 
-    write_json(&root, file, output, dry_run, pretty)
+    write_json(&root, file, write)
 }
 
 /// Add a value at `pointer`.
@@ -194,13 +836,15 @@ pub fn json_add(
     file: &str,
     pointer: &str,
     value_str: &str,
-    output: Option<&str>,
-    dry_run: bool,
-    pretty: bool,
+    write: &WriteOptions,
 ) -> Result<()> {
+    check_pointer(pointer, write.strict_pointer)?;
+    if value_str == "@-" {
+        write.check_stdin_free("value")?;
+    }
     let mut root = read_json_file(file)?;
-    let new_value: Value = serde_json::from_str(value_str)
-        .with_context(|| format!("Invalid JSON value: {}", value_str))?;
+    let pointer = &resolve_user_pointer(&root, pointer, write.strict_pointer)?;
+    let new_value = parse_value_arg(value_str, io::stdin())?;
 
     if pointer.is_empty() {
         // RFC 6902 "add" with empty pointer replaces the whole document.
@@ -234,22 +878,22 @@ pub fn json_add(
         }
     }
 
-    write_json(&root, file, output, dry_run, pretty)
+    write_json(&root, file, write)
 }
 
 /// Delete the value at `pointer`.
 pub fn json_delete(
     file: &str,
     pointer: &str,
-    output: Option<&str>,
-    dry_run: bool,
-    pretty: bool,
+    write: &WriteOptions,
 ) -> Result<()> {
+    check_pointer(pointer, write.strict_pointer)?;
     if pointer.is_empty() {
         bail!("Cannot delete the root document");
     }
 
     let mut root = read_json_file(file)?;
+    let pointer = &resolve_user_pointer(&root, pointer, write.strict_pointer)?;
     let (parent, key) = resolve_parent_and_key(&mut root, pointer)?;
 
     match parent {
@@ -274,7 +918,47 @@ pub fn json_delete(
         _ => bail!("Parent at pointer is not an object or array"),
     }
 
-    write_json(&root, file, output, dry_run, pretty)
+    write_json(&root, file, write)
+}
+
+/// Move the object member at `pointer` to the key `new_name` in the same
+/// object. Keys are kept in sorted order, so the other members stay where
+/// they were.
+pub fn json_rename(
+    file: &str,
+    pointer: &str,
+    new_name: &str,
+    write: &WriteOptions,
+) -> Result<()> {
+    check_pointer(pointer, write.strict_pointer)?;
+    if pointer.is_empty() {
+        bail!("Cannot rename the root document");
+    }
+
+    let mut root = read_json_file(file)?;
+    let pointer = &resolve_user_pointer(&root, pointer, write.strict_pointer)?;
+    let (parent, key) = resolve_parent_and_key(&mut root, pointer)?;
+
+    match parent {
+        Value::Object(map) => {
+            if !map.contains_key(&key) {
+                bail!("Key {:?} not found; nothing to rename", key);
+            }
+            if key != new_name {
+                if map.contains_key(new_name) {
+                    bail!("Key {:?} already exists; delete it first or pick another name", new_name);
+                }
+                let value = map.remove(&key).expect("key checked above");
+                map.insert(new_name.to_string(), value);
+            }
+        }
+        Value::Array(_) => bail!(
+            "Parent at pointer is an array; array elements have indices, not names, and can't be renamed"
+        ),
+        _ => bail!("Parent at pointer is not an object"),
+    }
+
+    write_json(&root, file, write)
 }
 
 // ---------------------------------------------------------------------------
@@ -284,14 +968,18 @@ pub fn json_delete(
 /// Apply an RFC 6902 JSON Patch document.
 ///
 /// `patch_source` is the path to a file containing the patch array, or `None`
-/// / `"-"` to read from stdin.
+/// / `"-"` to read from stdin. Returns whether every operation applied,
+/// which only `keep_going` lets be false.
 pub fn json_patch(
     file: &str,
     patch_source: Option<&str>,
-    output: Option<&str>,
-    dry_run: bool,
-    pretty: bool,
-) -> Result<()> {
+    keep_going: bool,
+    merge: bool,
+    write: &WriteOptions,
+) -> Result<bool> {
+    if matches!(patch_source, None | Some("-")) {
+        write.check_stdin_free("patch")?;
+    }
     let mut root = read_json_file(file)?;
 
     // Read patch document.
@@ -312,10 +1000,83 @@ pub fn json_patch(
     let patch_doc: Value =
         serde_json::from_str(&patch_str).context("Invalid JSON in patch document")?;
 
+    if merge {
+        if patch_doc.is_array() {
+            bail!("--merge expects a JSON Merge Patch object, but the patch document is an array (drop --merge for RFC 6902 operations)");
+        }
+        merge_patch(&mut root, &patch_doc);
+        write_json(&root, file, write)?;
+        return Ok(true);
+    }
+
     let ops = patch_doc
         .as_array()
         .context("Patch document must be a JSON array of operations")?;
 
+    if write.strict_pointer {
+        for (i, op_val) in ops.iter().enumerate() {
+            for field in ["path", "from"] {
+                if let Some(pointer) = op_val.get(field).and_then(Value::as_str) {
+                    validate_pointer_strict(pointer)
+                        .with_context(|| format!("Patch op {} ({})", i, field))?;
+                }
+            }
+        }
+    }
+
+    let mut all_applied = true;
+    if keep_going {
+        let failures = apply_patch_keep_going(&mut root, ops);
+        all_applied = failures.is_empty();
+        let summary = serde_json::json!({
+            "applied": ops.len() - failures.len(),
+            "failed": failures,
+        });
+        eprintln!("{}", summary);
+    } else {
+        apply_patch(&mut root, ops)?;
+    }
+
+    write_json(&root, file, write)?;
+    Ok(all_applied)
+}
+
+/// An operation `--keep-going` skipped.
+#[derive(Debug, serde::Serialize)]
+pub struct PatchFailure {
+    pub index: usize,
+    pub op: String,
+    pub error: String,
+}
+
+/// Apply every operation that succeeds, in order, and report the ones that
+/// don't. `test` operations are checked in place rather than up front, and
+/// a failing one only skips itself. Each operation runs on a copy that
+/// replaces `root` only on success, so a failed one leaves no partial change
+/// (a `move` that can't add never removes).
+pub fn apply_patch_keep_going(root: &mut Value, ops: &[Value]) -> Vec<PatchFailure> {
+    let mut failures = Vec::new();
+    for (i, op_val) in ops.iter().enumerate() {
+        let mut attempt = root.clone();
+        match apply_patch_op(&mut attempt, op_val, i, false) {
+            Ok(()) => *root = attempt,
+            Err(e) => failures.push(PatchFailure {
+                index: i,
+                op: op_val
+                    .get("op")
+                    .and_then(Value::as_str)
+                    .unwrap_or_default()
+                    .to_string(),
+                error: format!("{:#}", e),
+            }),
+        }
+    }
+    failures
+}
+
+/// Apply a list of RFC 6902 operations to `root` in order. All `test`
+/// operations run first so a failing precondition aborts before any change.
+pub fn apply_patch(root: &mut Value, ops: &[Value]) -> Result<()> {
     // --- Pre-flight: run all `test` operations first so we can abort early ---
     for (i, op_val) in ops.iter().enumerate() {
         let op_name = op_val
@@ -324,16 +1085,16 @@ pub fn json_patch(
             .with_context(|| format!("Patch operation {} missing 'op' field", i))?;
 
         if op_name == "test" {
-            apply_patch_op(&mut root, op_val, i, true)?;
+            apply_patch_op(root, op_val, i, true)?;
         }
     }
 
     // --- Apply all operations in order ---
     for (i, op_val) in ops.iter().enumerate() {
-        apply_patch_op(&mut root, op_val, i, false)?;
+        apply_patch_op(root, op_val, i, false)?;
     }
 
-    write_json(&root, file, output, dry_run, pretty)
+    Ok(())
 }
 
 /// Apply a single RFC 6902 operation.
@@ -538,3 +1299,702 @@ fn extract_and_remove(root: &mut Value, pointer: &str) -> Result<Value> {
         _ => bail!("Parent is not an object or array"),
     }
 }
+
+// ---------------------------------------------------------------------------
+// RFC 7386 JSON Merge Patch
+// ---------------------------------------------------------------------------
+
+/// Deep-merge the JSON document in `patch_file` into `file`.
+pub fn json_merge(file: &str, patch_file: &str, write: &WriteOptions) -> Result<()> {
+    let mut root = read_json_file(file)?;
+    let patch = read_json_file(patch_file)?;
+    merge_patch(&mut root, &patch);
+    write_json(&root, file, write)
+}
+
+/// Apply `patch` to `target` per RFC 7386: objects merge key by key, a
+/// `null` removes the key, and anything else (arrays included) replaces the
+/// target value outright.
+pub fn merge_patch(target: &mut Value, patch: &Value) {
+    let Value::Object(patch_map) = patch else {
+        *target = patch.clone();
+        return;
+    };
+    if !target.is_object() {
+        *target = Value::Object(serde_json::Map::new());
+    }
+    let Value::Object(target_map) = target else {
+        unreachable!("target was just made an object");
+    };
+    for (key, value) in patch_map {
+        if value.is_null() {
+            target_map.remove(key);
+        } else {
+            merge_patch(target_map.entry(key.clone()).or_insert(Value::Null), value);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        apply_patch, apply_patch_keep_going, diff_values, dotted_to_pointer, expand_env, json_diff, json_merge, json_patch, json_rename,
+        json_set, merge_patch, parse_document, parse_value_arg, read_json_file,
+        resolve_locator, Precondition,
+        validate_pointer, validate_pointer_strict, write_json_confirmed, write_json_to, WriteOptions,
+    };
+    use serde_json::json;
+    use std::fs;
+    use tempfile::tempdir;
+
+    #[test]
+    fn dotted_to_pointer_escapes_segments() {
+        assert_eq!(dotted_to_pointer("a.b.0.c"), "/a/b/0/c");
+        assert_eq!(dotted_to_pointer("urls.http://x"), "/urls/http:~1~1x");
+        assert_eq!(dotted_to_pointer("a~b"), "/a~0b");
+    }
+
+    #[test]
+    fn validate_pointer_suggests_leading_slash() {
+        let err = validate_pointer("foo/bar").unwrap_err().to_string();
+        assert!(err.contains("did you mean `/foo/bar`?"), "{}", err);
+    }
+
+    #[test]
+    fn validate_pointer_suggests_pointer_for_dotted_path() {
+        let err = validate_pointer("users.0.name").unwrap_err().to_string();
+        assert!(err.contains("did you mean `/users/0/name`?"), "{}", err);
+    }
+
+    #[test]
+    fn validate_pointer_accepts_canonical_pointers() {
+        assert!(validate_pointer("").is_ok());
+        assert!(validate_pointer("/a/b").is_ok());
+    }
+
+    #[test]
+    fn strict_pointer_rejects_non_canonical_pointers() {
+        assert!(validate_pointer_strict("").is_ok());
+        assert!(validate_pointer_strict("/a~1b/c~0d/{id=x}").is_ok());
+
+        let dotted = validate_pointer_strict("users.0.name").unwrap_err().to_string();
+        assert!(dotted.contains("--strict-pointer"), "{}", dotted);
+        assert!(validate_pointer_strict("/a~2b").is_err());
+        assert!(validate_pointer_strict("/trailing~").is_err());
+        // lenient mode lets stray escapes through
+        assert!(validate_pointer("/a~2b").is_ok());
+    }
+
+    #[test]
+    fn strict_pointer_takes_locator_segments_literally() {
+        let temp = tempdir().unwrap();
+        let file = temp.path().join("doc.json");
+        fs::write(&file, r#"{"items":[{"id":"a","v":0}],"{id=a}":0}"#).unwrap();
+        let file = file.to_str().unwrap();
+
+        let strict = WriteOptions {
+            strict_pointer: true,
+            ..Default::default()
+        };
+        json_set(file, "/{id=a}", "1", None, &strict).unwrap();
+        json_set(file, "/items/{id=a}/v", "2", None, &WriteOptions::default()).unwrap();
+        assert!(json_set(file, "/items/{id=a}/v", "3", None, &strict).is_err());
+
+        let doc: serde_json::Value = serde_json::from_str(&fs::read_to_string(file).unwrap()).unwrap();
+        assert_eq!(doc, json!({ "items": [{ "id": "a", "v": 2 }], "{id=a}": 1 }));
+    }
+
+    #[test]
+    fn set_rejects_bad_pointer_before_reading_file() {
+        let temp = tempdir().unwrap();
+        let missing = temp.path().join("missing.json");
+
+        let err = json_set(missing.to_str().unwrap(), "a.b", "1", None, &WriteOptions::default())
+            .unwrap_err()
+            .to_string();
+
+        assert!(err.contains("did you mean `/a/b`?"), "{}", err);
+    }
+
+    #[test]
+    fn stdout_emits_result_and_leaves_input_unchanged() {
+        let temp = tempdir().unwrap();
+        let file = temp.path().join("doc.json");
+        fs::write(&file, r#"{"a":1}"#).unwrap();
+        let file = file.to_str().unwrap();
+
+        let options = WriteOptions {
+            stdout: true,
+            ..Default::default()
+        };
+        let mut out = Vec::new();
+        write_json_to(&json!({ "a": 2 }), file, &options, &mut out).unwrap();
+
+        assert_eq!(String::from_utf8(out).unwrap(), "{\"a\":2}\n");
+        assert_eq!(fs::read_to_string(file).unwrap(), r#"{"a":1}"#);
+    }
+
+    #[test]
+    fn stdout_with_output_still_writes_output_file() {
+        let temp = tempdir().unwrap();
+        let file = temp.path().join("doc.json");
+        let patch = temp.path().join("patch.json");
+        let dest = temp.path().join("out.json");
+        fs::write(&file, r#"{"a":1}"#).unwrap();
+        fs::write(&patch, r#"[{"op":"replace","path":"/a","value":2}]"#).unwrap();
+
+        let options = WriteOptions {
+            output: Some(dest.to_str().unwrap().to_string()),
+            stdout: true,
+            ..Default::default()
+        };
+        json_patch(file.to_str().unwrap(), patch.to_str(), false, false, &options).unwrap();
+
+        assert_eq!(fs::read_to_string(&file).unwrap(), r#"{"a":1}"#);
+        assert_eq!(fs::read_to_string(&dest).unwrap(), "{\"a\":2}\n");
+    }
+
+    #[test]
+    fn resolve_locator_finds_element_by_key() {
+        let doc = json!({ "items": [{ "id": "a", "n": 1 }, { "id": "b/c", "n": 2 }, { "id": 7 }] });
+
+        assert_eq!(resolve_locator(&doc, "/items/{id=a}/n").unwrap(), "/items/0/n");
+        assert_eq!(resolve_locator(&doc, "/items/{id=b~1c}").unwrap(), "/items/1");
+        assert_eq!(resolve_locator(&doc, "/items/{id=7}").unwrap(), "/items/2");
+        assert_eq!(resolve_locator(&doc, "/items/1/n").unwrap(), "/items/1/n");
+
+        let err = resolve_locator(&doc, "/items/{id=zzz}").unwrap_err().to_string();
+        assert!(err.contains("No array element with id=\"zzz\""), "{}", err);
+    }
+
+    #[test]
+    fn resolve_locator_rejects_ambiguous_key() {
+        let doc = json!([{ "k": 1 }, { "k": 1 }]);
+        let err = resolve_locator(&doc, "/{k=1}").unwrap_err().to_string();
+        assert!(err.contains("ambiguous"), "{}", err);
+    }
+
+    #[test]
+    fn set_through_key_locator_survives_reordering() {
+        let temp = tempdir().unwrap();
+        let file = temp.path().join("doc.json");
+        fs::write(&file, r#"{"items":[{"id":"b","v":0},{"id":"a","v":0}]}"#).unwrap();
+        let file = file.to_str().unwrap();
+
+        json_set(file, "/items/{id=a}/v", "5", None, &WriteOptions::default()).unwrap();
+
+        let doc: serde_json::Value = serde_json::from_str(&fs::read_to_string(file).unwrap()).unwrap();
+        assert_eq!(doc, json!({ "items": [{ "id": "b", "v": 0 }, { "id": "a", "v": 5 }] }));
+    }
+
+    #[test]
+    fn set_with_precondition_applies_only_when_it_holds() {
+        let temp = tempdir().unwrap();
+        let file = temp.path().join("config.json");
+        fs::write(&file, r#"{"version":"2","x":0}"#).unwrap();
+        let file = file.to_str().unwrap();
+
+        let passes = Precondition {
+            pointer: "/version".to_string(),
+            value: json!("2"),
+        };
+        json_set(file, "/x", "1", Some(&passes), &WriteOptions::default()).unwrap();
+        let doc: serde_json::Value = serde_json::from_str(&fs::read_to_string(file).unwrap()).unwrap();
+        assert_eq!(doc, json!({ "version": "2", "x": 1 }));
+
+        let before = fs::read_to_string(file).unwrap();
+        for fails in [
+            Precondition {
+                pointer: "/version".to_string(),
+                value: json!(2),
+            },
+            Precondition {
+                pointer: "/missing".to_string(),
+                value: json!("2"),
+            },
+        ] {
+            let err = json_set(file, "/x", "5", Some(&fails), &WriteOptions::default()).unwrap_err();
+            let message = format!("{:#}", err);
+            assert!(message.contains("Precondition failed"), "{}", message);
+            assert!(message.contains("was not modified"), "{}", message);
+            assert_eq!(fs::read_to_string(file).unwrap(), before);
+        }
+    }
+
+    #[test]
+    fn concurrent_compare_and_swap_lets_exactly_one_through() {
+        let temp = tempdir().unwrap();
+        let file = temp.path().join("counter.json");
+        fs::write(&file, r#"{"n":0}"#).unwrap();
+        let file = file.to_str().unwrap();
+
+        let expected = Precondition {
+            pointer: "/n".to_string(),
+            value: json!(0),
+        };
+        let passed = std::thread::scope(|scope| {
+            let workers: Vec<_> = (1..=8)
+                .map(|n| {
+                    let expected = &expected;
+                    scope.spawn(move || json_set(file, "/n", &n.to_string(), Some(expected), &WriteOptions::default()).is_ok())
+                })
+                .collect();
+            workers.into_iter().map(|w| w.join().unwrap()).filter(|&ok| ok).count()
+        });
+        assert_eq!(passed, 1);
+        let doc: serde_json::Value = serde_json::from_str(&fs::read_to_string(file).unwrap()).unwrap();
+        assert_ne!(doc["n"], json!(0));
+    }
+
+    #[test]
+    fn set_value_from_file() {
+        let temp = tempdir().unwrap();
+        let file = temp.path().join("doc.json");
+        let value = temp.path().join("value.json");
+        fs::write(&file, r#"{"config":null}"#).unwrap();
+        fs::write(&value, r#"{"retries": 3, "hosts": ["a", "b"]}"#).unwrap();
+        let file = file.to_str().unwrap();
+
+        let arg = format!("@{}", value.to_str().unwrap());
+        json_set(file, "/config", &arg, None, &WriteOptions::default()).unwrap();
+
+        let doc: serde_json::Value = serde_json::from_str(&fs::read_to_string(file).unwrap()).unwrap();
+        assert_eq!(doc["config"], json!({ "retries": 3, "hosts": ["a", "b"] }));
+    }
+
+    #[test]
+    fn value_from_stdin_and_parse_position_on_error() {
+        let stdin = std::io::Cursor::new(r#"{"a": [1, 2]}"#);
+        assert_eq!(parse_value_arg("@-", stdin).unwrap(), json!({ "a": [1, 2] }));
+
+        let temp = tempdir().unwrap();
+        let bad = temp.path().join("bad.json");
+        fs::write(&bad, "{\n  \"a\": ,\n}").unwrap();
+        let err = parse_value_arg(&format!("@{}", bad.to_str().unwrap()), std::io::empty()).unwrap_err();
+        let message = format!("{:#}", err);
+        assert!(message.contains("bad.json"), "{}", message);
+        assert!(message.contains("line 2 column 8"), "{}", message);
+    }
+
+    #[test]
+    fn diff_values_patch_reproduces_target() {
+        let before = json!({ "a": 1, "gone": true, "list": [1, 2, 3], "nested": { "k": "v" } });
+        let after = json!({ "a": 2, "list": [1], "nested": { "k": "v", "new": [] } });
+
+        let ops = diff_values(&before, &after);
+        assert!(ops.contains(&json!({ "op": "replace", "path": "/a", "value": 2 })));
+
+        let mut doc = before.clone();
+        apply_patch(&mut doc, &ops).unwrap();
+        assert_eq!(doc, after);
+        assert!(diff_values(&after, &after).is_empty());
+    }
+
+    #[test]
+    fn diff_values_removes_from_highest_index_first() {
+        let before = json!({ "list": ["a", "b", "c", "d", "e"] });
+        let after = json!({ "list": ["b", "d"] });
+
+        let ops = diff_values(&before, &after);
+        assert_eq!(
+            ops,
+            vec![
+                json!({ "op": "remove", "path": "/list/4" }),
+                json!({ "op": "remove", "path": "/list/2" }),
+                json!({ "op": "remove", "path": "/list/0" }),
+            ]
+        );
+
+        let mut doc = before.clone();
+        apply_patch(&mut doc, &ops).unwrap();
+        assert_eq!(doc, after);
+    }
+
+    #[test]
+    fn diff_values_orders_array_insertions_and_removals_reapplicably() {
+        let before = json!({
+            "items": [{ "id": 1 }, { "id": 2, "tag": "x" }, { "id": 3 }, { "id": 4 }],
+            "name": "n"
+        });
+        let after = json!({
+            "items": [{ "id": 0 }, { "id": 1 }, { "id": 2, "tag": "y" }, { "id": 4 }, { "id": 5 }],
+            "name": "m"
+        });
+
+        let ops = diff_values(&before, &after);
+        assert_eq!(
+            ops,
+            vec![
+                json!({ "op": "replace", "path": "/items/1/tag", "value": "y" }),
+                json!({ "op": "remove", "path": "/items/2" }),
+                json!({ "op": "add", "path": "/items/0", "value": { "id": 0 } }),
+                json!({ "op": "add", "path": "/items/4", "value": { "id": 5 } }),
+                json!({ "op": "replace", "path": "/name", "value": "m" }),
+            ]
+        );
+        assert_eq!(diff_values(&before, &after), ops);
+
+        let mut doc = before.clone();
+        apply_patch(&mut doc, &ops).unwrap();
+        assert_eq!(doc, after);
+    }
+
+    #[test]
+    fn expand_env_substitutes_variables_before_parsing() {
+        let lookup = |name: &str| (name == "BASE_URL").then(|| "https://example.com".to_string());
+
+        let expanded = expand_env(r#""${BASE_URL}/api""#, &[], lookup).unwrap();
+        assert_eq!(expanded, r#""https://example.com/api""#);
+        assert_eq!(parse_value_arg(&expanded, std::io::empty()).unwrap(), json!("https://example.com/api"));
+
+        // Defaults only fill in what the environment lacks.
+        let defaults = vec!["PORT=8080".to_string(), "BASE_URL=http://localhost".to_string()];
+        let expanded = expand_env(r#"{"url": "${BASE_URL}", "port": ${PORT}, "cost": "$5"}"#, &defaults, lookup).unwrap();
+        assert_eq!(
+            parse_value_arg(&expanded, std::io::empty()).unwrap(),
+            json!({ "url": "https://example.com", "port": 8080, "cost": "$5" })
+        );
+
+        // Quotes and backslashes in a value stay inside the string.
+        let lookup = |_: &str| Some(r#"say "hi" \ bye"#.to_string());
+        let expanded = expand_env(r#"{"msg": "${MSG}", "path": "C:\\${MSG}"}"#, &[], lookup).unwrap();
+        assert_eq!(
+            parse_value_arg(&expanded, std::io::empty()).unwrap(),
+            json!({ "msg": r#"say "hi" \ bye"#, "path": r#"C:\say "hi" \ bye"# })
+        );
+    }
+
+    #[test]
+    fn expand_env_errors_on_missing_variable() {
+        let err = expand_env(r#""${NOPE}/api""#, &[], |_| None).unwrap_err();
+        let message = format!("{:#}", err);
+        assert!(message.contains("NOPE is not set"), "{}", message);
+
+        assert!(expand_env(r#""${NOPE""#, &[], |_| None).is_err());
+        assert!(expand_env("1", &["NOPE".to_string()], |_| None).is_err());
+    }
+
+    #[test]
+    fn keep_going_applies_the_ops_that_succeed() {
+        let mut doc = json!({ "a": 1, "list": [1, 2] });
+        let ops = vec![
+            json!({ "op": "replace", "path": "/a", "value": 2 }),
+            json!({ "op": "remove", "path": "/missing" }),
+            json!({ "op": "add", "path": "/list/-", "value": 3 }),
+            json!({ "op": "move", "from": "/a", "path": "/nowhere/a" }),
+        ];
+
+        assert!(apply_patch(&mut doc.clone(), &ops).is_err());
+
+        let failures = apply_patch_keep_going(&mut doc, &ops);
+        // The failed move left `/a` where it was.
+        assert_eq!(doc, json!({ "a": 2, "list": [1, 2, 3] }));
+        assert_eq!(failures.len(), 2);
+        assert_eq!(failures[0].index, 1);
+        assert_eq!(failures[0].op, "remove");
+        assert!(failures[0].error.contains("/missing"), "{}", failures[0].error);
+        assert_eq!(failures[1].op, "move");
+    }
+
+    fn confirmed_write(answer: &str) -> (anyhow::Result<()>, String, String) {
+        let temp = tempdir().unwrap();
+        let file = temp.path().join("doc.json");
+        fs::write(&file, r#"{"a":1}"#).unwrap();
+        let file = file.to_str().unwrap();
+
+        let options = WriteOptions {
+            confirm: true,
+            ..Default::default()
+        };
+        let mut prompt = Vec::new();
+        let result = write_json_confirmed(
+            &json!({ "a": 2 }),
+            file,
+            &options,
+            &mut Vec::new(),
+            &mut std::io::Cursor::new(answer),
+            &mut prompt,
+        );
+        (result, fs::read_to_string(file).unwrap(), String::from_utf8(prompt).unwrap())
+    }
+
+    #[test]
+    fn confirm_writes_on_yes() {
+        let (result, contents, prompt) = confirmed_write("y\n");
+        result.unwrap();
+        assert_eq!(contents, "{\"a\":2}\n");
+        assert!(prompt.starts_with(r#"{"op":"replace","path":"/a","value":2}"#), "{}", prompt);
+    }
+
+    #[test]
+    fn confirm_aborts_on_no_or_eof() {
+        for answer in ["n\n", ""] {
+            let (result, contents, _) = confirmed_write(answer);
+            assert!(result.unwrap_err().to_string().contains("Aborted"));
+            assert_eq!(contents, r#"{"a":1}"#);
+        }
+    }
+
+    #[test]
+    fn confirm_refuses_a_value_or_patch_read_from_stdin() {
+        let temp = tempdir().unwrap();
+        let file = temp.path().join("doc.json");
+        fs::write(&file, r#"{"a":1}"#).unwrap();
+        let file = file.to_str().unwrap();
+        let options = WriteOptions {
+            confirm: true,
+            ..Default::default()
+        };
+
+        let err = json_set(file, "/a", "@-", None, &options).unwrap_err();
+        assert!(err.to_string().contains("holds the value"), "{}", err);
+        let err = json_patch(file, Some("-"), false, false, &options).unwrap_err();
+        assert!(err.to_string().contains("holds the patch"), "{}", err);
+        assert_eq!(fs::read_to_string(file).unwrap(), r#"{"a":1}"#);
+    }
+
+    #[test]
+    fn merge_patch_follows_rfc_7386() {
+        let mut target = json!({
+            "title": "Goodbye!",
+            "author": { "givenName": "John", "familyName": "Doe" },
+            "tags": ["example", "sample"],
+            "content": "This will be unchanged"
+        });
+        merge_patch(
+            &mut target,
+            &json!({
+                "title": "Hello!",
+                "phoneNumber": "+01-123-456-7890",
+                "author": { "familyName": null },
+                "tags": ["example"]
+            }),
+        );
+        assert_eq!(
+            target,
+            json!({
+                "title": "Hello!",
+                "author": { "givenName": "John" },
+                "tags": ["example"],
+                "content": "This will be unchanged",
+                "phoneNumber": "+01-123-456-7890"
+            })
+        );
+
+        let mut scalar = json!("a");
+        merge_patch(&mut scalar, &json!({ "b": { "c": null, "d": 1 } }));
+        assert_eq!(scalar, json!({ "b": { "d": 1 } }));
+    }
+
+    #[test]
+    fn json_merge_writes_merged_base_to_output() {
+        let dir = tempdir().unwrap();
+        let base = dir.path().join("base.json");
+        let overlay = dir.path().join("overlay.json");
+        let out = dir.path().join("out.json");
+        fs::write(&base, r#"{"db":{"host":"localhost","port":5432},"debug":true}"#).unwrap();
+        fs::write(&overlay, r#"{"db":{"host":"prod"},"debug":null}"#).unwrap();
+
+        let write = WriteOptions {
+            output: Some(out.to_str().unwrap().to_string()),
+            ..Default::default()
+        };
+        json_merge(base.to_str().unwrap(), overlay.to_str().unwrap(), &write).unwrap();
+
+        let merged: serde_json::Value = serde_json::from_str(&fs::read_to_string(&out).unwrap()).unwrap();
+        assert_eq!(merged, json!({ "db": { "host": "prod", "port": 5432 } }));
+        assert_eq!(
+            fs::read_to_string(&base).unwrap(),
+            r#"{"db":{"host":"localhost","port":5432},"debug":true}"#
+        );
+    }
+
+    #[test]
+    fn json_rename_moves_value_to_new_key() {
+        let dir = tempdir().unwrap();
+        let file = dir.path().join("data.json");
+        fs::write(&file, r#"{"users":[{"mail":"a@x","name":"A"}],"zeta":1}"#).unwrap();
+        let file = file.to_str().unwrap();
+        let write = WriteOptions::default();
+
+        json_rename(file, "/users/0/mail", "email", &write).unwrap();
+        let renamed: serde_json::Value = serde_json::from_str(&fs::read_to_string(file).unwrap()).unwrap();
+        assert_eq!(renamed, json!({ "users": [{ "email": "a@x", "name": "A" }], "zeta": 1 }));
+
+        let err = json_rename(file, "/users/0/email", "name", &write).unwrap_err().to_string();
+        assert!(err.contains("already exists"), "{}", err);
+        let err = json_rename(file, "/users/0", "first", &write).unwrap_err().to_string();
+        assert!(err.contains("array"), "{}", err);
+        let err = json_rename(file, "/users/0/missing", "x", &write).unwrap_err().to_string();
+        assert!(err.contains("not found"), "{}", err);
+    }
+
+    #[test]
+    fn set_writes_yaml_back_to_yaml_files() {
+        let dir = tempdir().unwrap();
+        let file = dir.path().join("config.yaml");
+        fs::write(&file, "db:\n  host: localhost\n  port: 5432\n").unwrap();
+        let file = file.to_str().unwrap();
+
+        json_set(file, "/db/port", "6543", None, &WriteOptions::default()).unwrap();
+
+        let written = fs::read_to_string(file).unwrap();
+        assert_eq!(written, "db:\n  host: localhost\n  port: 6543\n");
+    }
+
+    #[test]
+    fn yaml_scalar_keys_become_strings() {
+        let doc = parse_document("200: ok\ntrue: yes\n~: none\nname: !tag x\n", "codes.yaml").unwrap();
+        assert_eq!(doc, json!({ "200": "ok", "true": "yes", "null": "none", "name": "x" }));
+
+        let err = parse_document("? [a, b]\n: pair\n", "pairs.yaml").unwrap_err();
+        assert!(format!("{:#}", err).contains("keys must be scalars"), "{:#}", err);
+    }
+
+    #[test]
+    fn json_diff_output_applies_back_to_the_right_document() {
+        let cases = [
+            (json!({ "a": 1, "b": { "c": [1, 2, 3] } }), json!({ "a": 2, "b": { "c": [0, 1, 3, 4] }, "d": null })),
+            (json!([{ "id": 1 }, { "id": 2 }, { "id": 3 }]), json!([{ "id": 2 }, { "id": 3, "x": true }])),
+            (json!({ "a/b": "x", "t~": [] }), json!({ "a/b": "y", "t~": ["z"] })),
+            (json!([1, 2]), json!({ "now": "an object" })),
+            (json!("same"), json!("same")),
+        ];
+        for (left, right) in cases {
+            let ops = json_diff(&left, &right);
+            let mut patched = left.clone();
+            apply_patch(&mut patched, &ops).unwrap();
+            assert_eq!(patched, right, "ops {:?}", ops);
+        }
+        assert!(json_diff(&json!({ "a": [1] }), &json!({ "a": [1] })).is_empty());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn write_replaces_file_and_keeps_permissions() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempdir().unwrap();
+        let file = dir.path().join("data.json");
+        fs::write(&file, r#"{"a":1}"#).unwrap();
+        fs::set_permissions(&file, fs::Permissions::from_mode(0o640)).unwrap();
+
+        json_set(file.to_str().unwrap(), "/a", "2", None, &WriteOptions::default()).unwrap();
+
+        assert_eq!(fs::read_to_string(&file).unwrap(), "{\"a\":2}\n");
+        assert_eq!(fs::metadata(&file).unwrap().permissions().mode() & 0o777, 0o640);
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1, "temporary file left behind");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn write_goes_through_a_symlink_to_its_target() {
+        let dir = tempdir().unwrap();
+        let target = dir.path().join("real.json");
+        let link = dir.path().join("link.json");
+        fs::write(&target, r#"{"a":1}"#).unwrap();
+        std::os::unix::fs::symlink(&target, &link).unwrap();
+
+        json_set(link.to_str().unwrap(), "/a", "2", None, &WriteOptions::default()).unwrap();
+
+        assert!(fs::symlink_metadata(&link).unwrap().file_type().is_symlink());
+        assert_eq!(fs::read_to_string(&target).unwrap(), "{\"a\":2}\n");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn write_to_a_device_leaves_the_device_in_place() {
+        use std::os::unix::fs::FileTypeExt;
+
+        let dir = tempdir().unwrap();
+        let file = dir.path().join("data.json");
+        fs::write(&file, r#"{"a":1}"#).unwrap();
+        let to_null = WriteOptions {
+            output: Some("/dev/null".to_string()),
+            ..Default::default()
+        };
+
+        json_set(file.to_str().unwrap(), "/a", "2", None, &to_null).unwrap();
+
+        assert!(fs::metadata("/dev/null").unwrap().file_type().is_char_device());
+        assert_eq!(fs::read_to_string(&file).unwrap(), r#"{"a":1}"#);
+    }
+
+    #[test]
+    fn write_fails_clearly_when_directory_is_missing() {
+        let dir = tempdir().unwrap();
+        let missing = dir.path().join("nope").join("out.json");
+        let err = write_json_to(&json!(1), "in.json", &WriteOptions {
+            output: Some(missing.to_str().unwrap().to_string()),
+            ..Default::default()
+        }, &mut Vec::new())
+        .unwrap_err();
+        assert!(err.to_string().contains("temporary file"), "{}", err);
+    }
+
+    #[test]
+    fn backup_copies_original_once_unless_forced() {
+        let dir = tempdir().unwrap();
+        let file = dir.path().join("data.json");
+        let path = file.to_str().unwrap();
+        let backup = dir.path().join("data.json.bak");
+        fs::write(&file, r#"{"a":1}"#).unwrap();
+        let options = WriteOptions {
+            backup: Some(".bak".to_string()),
+            ..Default::default()
+        };
+
+        json_set(path, "/a", "2", None, &options).unwrap();
+        assert_eq!(fs::read_to_string(&backup).unwrap(), r#"{"a":1}"#);
+        assert_eq!(fs::read_to_string(&file).unwrap(), "{\"a\":2}\n");
+
+        let err = json_set(path, "/a", "3", None, &options).unwrap_err();
+        assert!(err.to_string().contains("--force"), "{}", err);
+        assert_eq!(fs::read_to_string(&file).unwrap(), "{\"a\":2}\n");
+
+        let forced = WriteOptions {
+            overwrite_backup: true,
+            ..options
+        };
+        json_set(path, "/a", "3", None, &forced).unwrap();
+        assert_eq!(fs::read_to_string(&backup).unwrap(), "{\"a\":2}\n");
+    }
+
+    #[test]
+    fn backup_skipped_when_input_is_not_overwritten() {
+        let dir = tempdir().unwrap();
+        let file = dir.path().join("data.json");
+        let out = dir.path().join("out.json");
+        fs::write(&file, r#"{"a":1}"#).unwrap();
+        let options = WriteOptions {
+            backup: Some(".bak".to_string()),
+            output: Some(out.to_str().unwrap().to_string()),
+            ..Default::default()
+        };
+        json_set(file.to_str().unwrap(), "/a", "2", None, &options).unwrap();
+        let dry_run = WriteOptions {
+            output: None,
+            dry_run: true,
+            ..options
+        };
+        json_set(file.to_str().unwrap(), "/a", "2", None, &dry_run).unwrap();
+        assert!(!dir.path().join("data.json.bak").exists());
+    }
+
+    #[test]
+    fn patch_merge_applies_merge_semantics_and_rejects_arrays() {
+        let temp = tempdir().unwrap();
+        let file = temp.path().join("doc.json");
+        let patch = temp.path().join("patch.json");
+        let path = file.to_str().unwrap();
+        fs::write(&file, r#"{"a":{"b":1,"c":2},"d":3}"#).unwrap();
+
+        fs::write(&patch, r#"{"a":{"b":null,"e":4},"d":[5]}"#).unwrap();
+        json_patch(path, patch.to_str(), false, true, &WriteOptions::default()).unwrap();
+        assert_eq!(read_json_file(path).unwrap(), json!({ "a": { "c": 2, "e": 4 }, "d": [5] }));
+
+        fs::write(&patch, r#"[{"op":"remove","path":"/d"}]"#).unwrap();
+        let err = json_patch(path, patch.to_str(), false, true, &WriteOptions::default()).unwrap_err();
+        assert!(err.to_string().contains("array"), "{}", err);
+    }
+}
@@ -1,10 +1,15 @@
+use std::borrow::Cow;
 use std::collections::{BTreeMap, HashMap};
+use std::io::Write;
 
-use serde::Serialize;
+use anyhow::{Context, Result};
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine as _;
+use serde::{Deserialize, Serialize};
 use serde_json::Value;
 
-use crate::cli::OutputMode;
-use crate::engine::SearchResult;
+use crate::cli::{FacetCase, OutputMode};
+use crate::engine::{After, Record, SearchResult};
 
 pub fn to_json<T: Serialize>(value: &T, pretty: bool) -> String {
     if pretty {
@@ -14,6 +19,97 @@ pub fn to_json<T: Serialize>(value: &T, pretty: bool) -> String {
     }
 }
 
+/// Like [`to_json`], but with object keys sorted at every level, including
+/// the fields of envelope structs. Only affects what is printed.
+pub fn to_json_sorted<T: Serialize>(value: &T, pretty: bool) -> String {
+    match serde_json::to_value(value) {
+        Ok(value) => to_json(&sort_keys(value), pretty),
+        Err(_) => String::new(),
+    }
+}
+
+fn sort_keys(value: Value) -> Value {
+    match value {
+        Value::Object(map) => {
+            let mut entries: Vec<(String, Value)> = map.into_iter().collect();
+            entries.sort_by(|a, b| a.0.cmp(&b.0));
+            Value::Object(entries.into_iter().map(|(k, v)| (k, sort_keys(v))).collect())
+        }
+        Value::Array(items) => Value::Array(items.into_iter().map(sort_keys).collect()),
+        other => other,
+    }
+}
+
+/// Pretty-print, except that arrays and objects of at most `max_inline`
+/// scalar elements stay on one line (`[1, 2, 3]`), which keeps small
+/// lists from spreading over many lines in files under version control.
+pub fn to_json_inline(value: &Value, max_inline: usize) -> String {
+    let mut out = String::new();
+    write_inline(value, max_inline, 0, &mut out);
+    out
+}
+
+fn write_inline(value: &Value, max_inline: usize, depth: usize, out: &mut String) {
+    let indent = |depth: usize| "  ".repeat(depth);
+    match value {
+        Value::Array(items) if !items.is_empty() => {
+            if fits_inline(items.iter(), max_inline) {
+                let parts: Vec<String> = items.iter().map(|v| to_json(v, false)).collect();
+                out.push_str(&format!("[{}]", parts.join(", ")));
+                return;
+            }
+            out.push_str("[\n");
+            for (i, item) in items.iter().enumerate() {
+                out.push_str(&indent(depth + 1));
+                write_inline(item, max_inline, depth + 1, out);
+                out.push_str(if i + 1 < items.len() { ",\n" } else { "\n" });
+            }
+            out.push_str(&indent(depth));
+            out.push(']');
+        }
+        Value::Object(map) if !map.is_empty() => {
+            if fits_inline(map.values(), max_inline) {
+                let parts: Vec<String> = map
+                    .iter()
+                    .map(|(k, v)| format!("{}: {}", to_json(k, false), to_json(v, false)))
+                    .collect();
+                out.push_str(&format!("{{{}}}", parts.join(", ")));
+                return;
+            }
+            out.push_str("{\n");
+            for (i, (key, item)) in map.iter().enumerate() {
+                out.push_str(&indent(depth + 1));
+                out.push_str(&to_json(key, false));
+                out.push_str(": ");
+                write_inline(item, max_inline, depth + 1, out);
+                out.push_str(if i + 1 < map.len() { ",\n" } else { "\n" });
+            }
+            out.push_str(&indent(depth));
+            out.push('}');
+        }
+        other => out.push_str(&to_json(other, false)),
+    }
+}
+
+/// A container is kept on one line when it is small and holds no
+/// non-empty containers.
+fn fits_inline<'a>(mut items: impl ExactSizeIterator<Item = &'a Value>, max_inline: usize) -> bool {
+    items.len() <= max_inline
+        && items.all(|v| match v {
+            Value::Array(a) => a.is_empty(),
+            Value::Object(m) => m.is_empty(),
+            _ => true,
+        })
+}
+
+fn render<T: Serialize>(value: &T, pretty: bool, sorted: bool) -> String {
+    if sorted {
+        to_json_sorted(value, pretty)
+    } else {
+        to_json(value, pretty)
+    }
+}
+
 #[derive(Serialize)]
 pub struct Envelope {
     pub meta: Meta,
@@ -21,9 +117,12 @@ pub struct Envelope {
     pub results: Option<Vec<Value>>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub hits: Option<Vec<Hit>>,
+    /// `--output map`: records keyed by location
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub map: Option<serde_json::Map<String, Value>>,
 }
 
-#[derive(Serialize)]
+#[derive(Serialize, Default, Clone)]
 pub struct Meta {
     pub total: usize,
     pub returned: usize,
@@ -31,180 +130,1033 @@ pub struct Meta {
     pub truncated: bool,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub files_searched: Option<usize>,
+    /// Files left out by `--since`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub files_skipped: Option<usize>,
+    /// More results matched than the overflow threshold allowed
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub overflow: bool,
+    /// Why a query may have matched nothing (`--explain`)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hint: Option<String>,
+    /// Results shortened by `--clip-record-bytes`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub clipped: Option<usize>,
+    /// Matches per file before `--offset`/`--limit` (`--count-by-file`)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub counts_by_file: Option<BTreeMap<String, usize>>,
+    /// `--cursor` token for the next page, when more results follow
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub next_cursor: Option<String>,
+}
+
+/// Position in a result list for `--cursor`: just after the result with
+/// this file and pointer, which scored `score`. Tokens are opaque to users:
+/// base64 (URL-safe, no padding) of this struct as JSON.
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct Cursor {
+    #[serde(rename = "f")]
+    pub file: String,
+    #[serde(rename = "p")]
+    pub pointer: String,
+    #[serde(rename = "s")]
+    pub score: f32,
+}
+
+impl Cursor {
+    pub fn after(result: &SearchResult) -> Self {
+        Cursor {
+            file: result.record.file.clone(),
+            pointer: result.record.pointer.clone(),
+            score: result.score,
+        }
+    }
+
+    /// Where the search itself resumes, when results keep the engine's
+    /// ranking.
+    pub fn search_after(&self) -> After {
+        After {
+            score: self.score,
+            file: self.file.clone(),
+            pointer: self.pointer.clone(),
+        }
+    }
+
+    pub fn encode(&self) -> String {
+        URL_SAFE_NO_PAD.encode(serde_json::to_vec(self).unwrap_or_default())
+    }
+
+    pub fn decode(token: &str) -> Result<Self> {
+        let bytes = URL_SAFE_NO_PAD
+            .decode(token.trim())
+            .ok()
+            .context("Invalid --cursor: pass meta.next_cursor from a previous page unchanged")?;
+        serde_json::from_slice(&bytes).context("Invalid --cursor: pass meta.next_cursor from a previous page unchanged")
+    }
+
+    /// Index of the first result of the page to return. Fails when the
+    /// cursor's result no longer matches: its place can't be told from a
+    /// score alone, which ties under `--scoring none` and means nothing
+    /// under `--sort`.
+    pub fn resume_index(&self, results: &[SearchResult]) -> Result<usize> {
+        results
+            .iter()
+            .position(|r| r.record.file == self.file && r.record.pointer == self.pointer)
+            .map(|i| i + 1)
+            .with_context(|| {
+                format!(
+                    "Invalid --cursor: {} {} no longer matches; start over without --cursor",
+                    self.file, self.pointer
+                )
+            })
+    }
+}
+
+/// After `--max-bytes` cut a page short, the cursor resuming right after
+/// the last result whose output was kept in full. `kept` counts output
+/// items, which in value mode are values rather than records.
+fn resume_cursor(results: &[SearchResult], kept: usize, options: &OutputOptions) -> Option<String> {
+    let kept_results = match options.mode {
+        OutputMode::Value => {
+            let mut values = 0;
+            results
+                .iter()
+                .take_while(|r| {
+                    values += record_values(r, options).len();
+                    values <= kept
+                })
+                .count()
+        }
+        _ => kept,
+    };
+    kept_results
+        .checked_sub(1)
+        .and_then(|last| results.get(last))
+        .map(|r| Cursor::after(r).encode())
 }
 
 #[derive(Serialize, Clone)]
 pub struct Hit {
     pub file: String,
     pub pointer: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub locator: Option<String>,
     pub record: Value,
     pub score: f32,
+    /// The record was shortened by `--clip-record-bytes`
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub clipped: bool,
+    /// The matched words in context (`--highlight`)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub snippet: Option<String>,
+    /// How the score was computed (`--explain`)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub explanation: Option<Value>,
 }
 
-pub fn format_output(
-    results: &[SearchResult],
-    total_matched: usize,
-    limit: usize,
-    output_mode: &OutputMode,
-    bare: bool,
-    count_only: bool,
-    select_fields: &Option<Vec<String>>,
-    files_searched: Option<usize>,
-    max_bytes: Option<usize>,
-    pretty: bool,
-) -> String {
-    if count_only {
-        if bare {
-            return total_matched.to_string();
+/// How search results are rendered.
+#[derive(Default)]
+pub struct OutputOptions {
+    pub mode: OutputMode,
+    /// Output bare JSON array instead of envelope
+    pub bare: bool,
+    /// Only report the count
+    pub count_only: bool,
+    pub projection: Projection,
+    /// Max output bytes
+    pub max_bytes: Option<usize>,
+    pub pretty: bool,
+    /// Sort object keys in the printed JSON
+    pub sort_keys: bool,
+    /// In value mode, collect only the values at these (dotted) fields
+    pub value_fields: Vec<String>,
+    /// Shorten the longest strings of any result rendering to more bytes
+    pub clip_record_bytes: Option<usize>,
+    /// Key `--output map` entries by `file#pointer` instead of pointer,
+    /// because more than one file was searched
+    pub map_keys_with_file: bool,
+}
+
+/// Render results. `meta` carries the totals known to the caller;
+/// `returned` and `truncated` are filled in here.
+pub fn format_output(results: &[SearchResult], mut meta: Meta, options: &OutputOptions) -> String {
+    let projection = &options.projection;
+    let pretty = options.pretty;
+    let sorted = options.sort_keys;
+
+    if options.count_only {
+        if options.bare {
+            return meta.total.to_string();
         }
         let envelope = Envelope {
-            meta: Meta {
-                total: total_matched,
-                returned: 0,
-                limit,
-                truncated: false,
-                files_searched,
-            },
+            meta,
             results: None,
             hits: None,
+            map: None,
         };
-        return to_json(&envelope, pretty);
+        return render(&envelope, pretty, sorted);
     }
 
-    match output_mode {
+    meta.truncated = meta.total > meta.limit;
+
+    match options.mode {
         OutputMode::Match => {
+            let mut clipped = 0;
             let all_objects: Vec<Value> = results
                 .iter()
-                .map(|r| project_fields(&r.record.value, select_fields))
+                .map(|r| {
+                    let (value, was_clipped) = clip_record(project_fields(&r.record.value, projection), options);
+                    clipped += usize::from(was_clipped);
+                    value
+                })
                 .collect();
 
-            let (objects, byte_truncated) = truncate_to_budget(&all_objects, max_bytes);
-            let truncated = total_matched > limit || byte_truncated;
+            meta.clipped = (clipped > 0).then_some(clipped);
+            let (objects, byte_truncated) = fit_to_budget(&all_objects, results, options, &meta, |meta| Envelope {
+                meta,
+                results: Some(Vec::new()),
+                hits: None,
+                map: None,
+            });
 
-            if bare {
-                to_json(&objects, pretty)
+            if options.bare {
+                render(&objects, pretty, sorted)
             } else {
+                meta.returned = objects.len();
+                meta.truncated |= byte_truncated;
+                if byte_truncated {
+                    meta.next_cursor = resume_cursor(results, objects.len(), options);
+                }
                 let envelope = Envelope {
-                    meta: Meta {
-                        total: total_matched,
-                        returned: objects.len(),
-                        limit,
-                        truncated,
-                        files_searched,
-                    },
+                    meta,
                     results: Some(objects),
                     hits: None,
+                    map: None,
                 };
-                to_json(&envelope, pretty)
+                render(&envelope, pretty, sorted)
             }
         }
         OutputMode::Hit => {
-            let all_hits: Vec<Hit> = results
+            let all_hits: Vec<Hit> = results.iter().map(|r| hit(r, options)).collect();
+
+            let clipped = all_hits.iter().filter(|h| h.clipped).count();
+            meta.clipped = (clipped > 0).then_some(clipped);
+            let (hits, byte_truncated) = fit_to_budget(&all_hits, results, options, &meta, |meta| Envelope {
+                meta,
+                results: None,
+                hits: Some(Vec::new()),
+                map: None,
+            });
+
+            if options.bare {
+                render(&hits, pretty, sorted)
+            } else {
+                meta.returned = hits.len();
+                meta.truncated |= byte_truncated;
+                if byte_truncated {
+                    meta.next_cursor = resume_cursor(results, hits.len(), options);
+                }
+                let envelope = Envelope {
+                    meta,
+                    results: None,
+                    hits: Some(hits),
+                    map: None,
+                };
+                render(&envelope, pretty, sorted)
+            }
+        }
+        OutputMode::Map => {
+            let mut clipped = 0;
+            let all_entries: Vec<(String, Value)> = results
                 .iter()
-                .map(|r| Hit {
-                    file: r.record.file.clone(),
-                    pointer: r.record.pointer.clone(),
-                    record: project_fields(&r.record.value, select_fields),
-                    score: r.score,
+                .map(|r| {
+                    let (value, was_clipped) = clip_record(project_fields(&r.record.value, projection), options);
+                    clipped += usize::from(was_clipped);
+                    (map_key(r, options), value)
                 })
                 .collect();
 
-            let (hits, byte_truncated) = truncate_to_budget(&all_hits, max_bytes);
-            let truncated = total_matched > limit || byte_truncated;
+            meta.clipped = (clipped > 0).then_some(clipped);
+            // Entries are sized as `["key", record]`, slightly over their
+            // `"key": record` size in the object.
+            let (entries, byte_truncated) = fit_to_budget(&all_entries, results, options, &meta, |meta| Envelope {
+                meta,
+                results: None,
+                hits: None,
+                map: Some(serde_json::Map::new()),
+            });
+            let map: serde_json::Map<String, Value> = entries.into_iter().collect();
 
-            if bare {
-                to_json(&hits, pretty)
+            if options.bare {
+                render(&map, pretty, sorted)
             } else {
+                meta.returned = map.len();
+                meta.truncated |= byte_truncated;
+                if byte_truncated {
+                    meta.next_cursor = resume_cursor(results, map.len(), options);
+                }
                 let envelope = Envelope {
-                    meta: Meta {
-                        total: total_matched,
-                        returned: hits.len(),
-                        limit,
-                        truncated,
-                        files_searched,
-                    },
+                    meta,
                     results: None,
-                    hits: Some(hits),
+                    hits: None,
+                    map: Some(map),
                 };
-                to_json(&envelope, pretty)
+                render(&envelope, pretty, sorted)
+            }
+        }
+        OutputMode::Patch => {
+            let all_nodes: Vec<(String, Value)> = results
+                .iter()
+                .map(|r| {
+                    (
+                        r.record.pointer.clone(),
+                        project_fields(&r.record.value, projection),
+                    )
+                })
+                .collect();
+
+            // A patch is a document in its own right, so it is always bare.
+            // Parent-creating ops make this an estimate.
+            let (nodes, _) = truncate_to_budget(&all_nodes, options.max_bytes, &Layout::bare(pretty));
+            render(&patch_ops(&nodes), pretty, sorted)
+        }
+        OutputMode::Csv => {
+            // Like a patch, a CSV is always bare; `--max-bytes` drops
+            // whole rows from the end.
+            let records: Vec<Value> = results
+                .iter()
+                .map(|r| clip_record(project_fields(&r.record.value, projection), options).0)
+                .collect();
+            let columns = csv_columns(&records, projection);
+            let mut csv = csv_row(columns.iter().map(|c| Cow::Borrowed(c.as_str())));
+            for record in &records {
+                let row = csv_record_row(record, &columns);
+                if options.max_bytes.is_some_and(|max| csv.len() + 1 + row.len() > max) {
+                    break;
+                }
+                csv.push('\n');
+                csv.push_str(&row);
             }
+            csv
         }
         OutputMode::Value => {
+            let mut clipped = 0;
             let all_values: Vec<Value> = results
                 .iter()
-                .flat_map(|r| extract_matching_values(&r.record.value))
+                .flat_map(|r| record_values(r, options))
+                .map(|v| {
+                    let (value, was_clipped) = clip_record(v, options);
+                    clipped += usize::from(was_clipped);
+                    value
+                })
                 .collect();
 
-            let (values, byte_truncated) = truncate_to_budget(&all_values, max_bytes);
-            let truncated = total_matched > limit || byte_truncated;
+            meta.clipped = (clipped > 0).then_some(clipped);
+            let (values, byte_truncated) = fit_to_budget(&all_values, results, options, &meta, |meta| Envelope {
+                meta,
+                results: Some(Vec::new()),
+                hits: None,
+                map: None,
+            });
 
-            if bare {
-                to_json(&values, pretty)
+            if options.bare {
+                render(&values, pretty, sorted)
             } else {
+                meta.returned = values.len();
+                meta.truncated |= byte_truncated;
+                if byte_truncated {
+                    meta.next_cursor = resume_cursor(results, values.len(), options);
+                }
                 let envelope = Envelope {
-                    meta: Meta {
-                        total: total_matched,
-                        returned: values.len(),
-                        limit,
-                        truncated,
-                        files_searched,
-                    },
+                    meta,
                     results: Some(values),
                     hits: None,
+                    map: None,
                 };
-                to_json(&envelope, pretty)
+                render(&envelope, pretty, sorted)
+            }
+        }
+    }
+}
+
+/// Render each result as its own compact JSON line, following the output
+/// mode (one line per record, hit, or extracted value).
+pub fn format_result_lines(results: &[SearchResult], options: &OutputOptions) -> Vec<String> {
+    result_lines(results, options).collect()
+}
+
+/// Lazily render result lines, so streams never hold every line at once.
+fn result_lines<'a>(
+    results: &'a [SearchResult],
+    options: &'a OutputOptions,
+) -> Box<dyn Iterator<Item = String> + 'a> {
+    let projection = &options.projection;
+    let sorted = options.sort_keys;
+    match options.mode {
+        OutputMode::Match => Box::new(results.iter().map(move |r| {
+            let (value, _) = clip_record(project_fields(&r.record.value, projection), options);
+            render(&value, false, sorted)
+        })),
+        OutputMode::Hit => Box::new(results.iter().map(move |r| render(&hit(r, options), false, sorted))),
+        OutputMode::Value => Box::new(
+            results
+                .iter()
+                .flat_map(|r| record_values(r, options))
+                .map(move |v| render(&clip_record(v, options).0, false, sorted)),
+        ),
+        OutputMode::Map => Box::new(results.iter().map(move |r| {
+            let (value, _) = clip_record(project_fields(&r.record.value, projection), options);
+            let entry: serde_json::Map<String, Value> = [(map_key(r, options), value)].into_iter().collect();
+            render(&entry, false, sorted)
+        })),
+        OutputMode::Patch => Box::new(results.iter().map(move |r| {
+            let op = add_op(&r.record.pointer, project_fields(&r.record.value, projection));
+            render(&op, false, sorted)
+        })),
+        OutputMode::Csv => {
+            let records: Vec<Value> = results
+                .iter()
+                .map(|r| clip_record(project_fields(&r.record.value, projection), options).0)
+                .collect();
+            let columns = csv_columns(&records, projection);
+            Box::new(
+                records
+                    .into_iter()
+                    .map(move |record| csv_record_row(&record, &columns)),
+            )
+        }
+    }
+}
+
+/// Column of `--output csv` holding records that aren't objects.
+const CSV_VALUE_COLUMN: &str = "value";
+
+/// `--output csv` columns: the `--select` fields in the order given, or
+/// else every top-level key in order of first appearance, plus a `value`
+/// column when some record isn't an object.
+fn csv_columns(records: &[Value], projection: &Projection) -> Vec<String> {
+    if let Some(fields) = &projection.select {
+        if projection.select_regex.is_none() {
+            return fields.clone();
+        }
+    }
+    let mut columns: Vec<String> = Vec::new();
+    for record in records {
+        let keys: Vec<&str> = match record {
+            Value::Object(map) => map.keys().map(String::as_str).collect(),
+            _ => vec![CSV_VALUE_COLUMN],
+        };
+        for key in keys {
+            if !columns.iter().any(|c| c == key) {
+                columns.push(key.to_string());
+            }
+        }
+    }
+    columns
+}
+
+fn csv_record_row(record: &Value, columns: &[String]) -> String {
+    csv_row(columns.iter().map(|column| {
+        let value = match record {
+            Value::Object(map) => match map.get(column) {
+                Some(value) => Some(Cow::Borrowed(value)),
+                // A dotted `--select` path: the projected record nests it.
+                None => {
+                    let segments: Vec<&str> = column.split('.').filter(|s| *s != "*").collect();
+                    let mut found = Vec::new();
+                    collect_path_values(record, &segments, &mut found);
+                    match found.len() {
+                        0 => None,
+                        1 => found.pop().map(Cow::Owned),
+                        _ => Some(Cow::Owned(Value::Array(found))),
+                    }
+                }
+            },
+            other => (column == CSV_VALUE_COLUMN).then_some(Cow::Borrowed(other)),
+        };
+        match value {
+            None | Some(Cow::Borrowed(Value::Null)) | Some(Cow::Owned(Value::Null)) => Cow::Borrowed(""),
+            Some(Cow::Borrowed(Value::String(s))) => Cow::Borrowed(s.as_str()),
+            Some(Cow::Owned(Value::String(s))) => Cow::Owned(s),
+            Some(other) => Cow::Owned(other.to_string()),
+        }
+    }))
+}
+
+/// One CSV line (RFC 4180): cells holding a comma, quote or line break are
+/// quoted, with quotes doubled.
+fn csv_row<'a>(cells: impl Iterator<Item = Cow<'a, str>>) -> String {
+    let mut row = String::new();
+    for (i, cell) in cells.enumerate() {
+        if i > 0 {
+            row.push(',');
+        }
+        if cell.contains([',', '"', '\n', '\r']) {
+            row.push('"');
+            row.push_str(&cell.replace('"', "\"\""));
+            row.push('"');
+        } else {
+            row.push_str(&cell);
+        }
+    }
+    row
+}
+
+/// Bytes the results would take rendered one compact line each, used to
+/// decide `--overflow-bytes`.
+pub fn rendered_size(results: &[SearchResult], options: &OutputOptions) -> usize {
+    result_lines(results, options).map(|line| line.len() + 1).sum()
+}
+
+/// Write results one compact line at a time, then a `{"meta":...}`
+/// trailer line. `--max-bytes` ends the stream early (the trailer is not
+/// counted against it), which the trailer reports as `truncated`.
+pub fn write_stream(
+    results: &[SearchResult],
+    mut meta: Meta,
+    options: &OutputOptions,
+    out: &mut dyn Write,
+) -> std::io::Result<()> {
+    let mut written = 0;
+    let mut byte_truncated = false;
+
+    if !options.count_only {
+        for line in result_lines(results, options) {
+            if let Some(max) = options.max_bytes {
+                if written + line.len() + 1 > max {
+                    byte_truncated = true;
+                    break;
+                }
+            }
+            writeln!(out, "{}", line)?;
+            written += line.len() + 1;
+            meta.returned += 1;
+        }
+    }
+
+    meta.truncated = meta.total > meta.limit || byte_truncated;
+    if byte_truncated {
+        meta.next_cursor = resume_cursor(results, meta.returned, options);
+    }
+    writeln!(out, "{}", render(&serde_json::json!({ "meta": meta }), false, options.sort_keys))
+}
+
+fn hit(result: &SearchResult, options: &OutputOptions) -> Hit {
+    let (record, clipped) = clip_record(project_fields(&result.record.value, &options.projection), options);
+    Hit {
+        file: result.record.file.clone(),
+        pointer: result.record.pointer.clone(),
+        locator: result.locator.clone(),
+        record,
+        score: result.score,
+        clipped,
+        snippet: result.snippet.clone(),
+        explanation: result.explanation.clone(),
+    }
+}
+
+/// Key of a result in `--output map`: its pointer, prefixed with `file#`
+/// when several files were searched so equal pointers don't collide.
+fn map_key(result: &SearchResult, options: &OutputOptions) -> String {
+    if options.map_keys_with_file {
+        format!("{}#{}", result.record.file, result.record.pointer)
+    } else {
+        result.record.pointer.clone()
+    }
+}
+
+/// Appended to strings shortened by `--clip-record-bytes`.
+const CLIP_MARKER: &str = "…";
+
+/// Apply `--clip-record-bytes`: while the value renders to more than the
+/// limit, shorten its longest string and end it with `…`. Structure is
+/// never dropped, so a record made of many short values can stay over the
+/// limit. Returns the value and whether anything was shortened.
+fn clip_record(mut value: Value, options: &OutputOptions) -> (Value, bool) {
+    let Some(max) = options.clip_record_bytes else {
+        return (value, false);
+    };
+
+    let mut clipped = false;
+    loop {
+        let size = serde_json::to_string(&value).map(|s| s.len()).unwrap_or(0);
+        if size <= max {
+            break;
+        }
+        let Some(longest) = longest_string(&mut value) else {
+            break;
+        };
+        // Keep enough of the string to pay for the excess and the marker.
+        let keep = longest.len().saturating_sub(size - max + CLIP_MARKER.len());
+        if keep + CLIP_MARKER.len() >= longest.len() {
+            break;
+        }
+        let mut cut = keep;
+        while !longest.is_char_boundary(cut) {
+            cut -= 1;
+        }
+        longest.truncate(cut);
+        longest.push_str(CLIP_MARKER);
+        clipped = true;
+    }
+    (value, clipped)
+}
+
+fn longest_string(value: &mut Value) -> Option<&mut String> {
+    match value {
+        Value::String(s) => Some(s),
+        Value::Array(items) => items
+            .iter_mut()
+            .filter_map(longest_string)
+            .max_by_key(|s| s.len()),
+        Value::Object(map) => map
+            .values_mut()
+            .filter_map(longest_string)
+            .max_by_key(|s| s.len()),
+        _ => None,
+    }
+}
+
+fn add_op(path: &str, value: Value) -> Value {
+    serde_json::json!({ "op": "add", "path": path, "value": value })
+}
+
+/// Build `add` operations placing each node at its pointer. Missing
+/// ancestors are created first as empty objects, so the patch applies
+/// cleanly to an empty document (array indices become object keys, which
+/// the same pointers still resolve).
+fn patch_ops(nodes: &[(String, Value)]) -> Vec<Value> {
+    let mut created: Vec<String> = vec![String::new()];
+    let mut ops = Vec::new();
+
+    for (pointer, value) in nodes {
+        let mut ancestor = String::new();
+        let segments: Vec<&str> = pointer.split('/').skip(1).collect();
+        for seg in segments.iter().take(segments.len().saturating_sub(1)) {
+            ancestor.push('/');
+            ancestor.push_str(seg);
+            if !created.contains(&ancestor) {
+                ops.push(add_op(&ancestor, Value::Object(Default::default())));
+                created.push(ancestor.clone());
+            }
+        }
+
+        ops.push(add_op(pointer, value.clone()));
+        created.push(pointer.clone());
+    }
+
+    ops
+}
+
+/// Where the items of a result list sit in the rendered output, so the
+/// byte budget reserves exactly what surrounds them.
+struct Layout {
+    /// Bytes of the output with no items: `[]`, or the whole envelope
+    overhead: usize,
+    /// Pretty-printing indent of the items, `None` when compact
+    indent: Option<usize>,
+}
+
+impl Layout {
+    /// Items in a top-level array.
+    fn bare(pretty: bool) -> Self {
+        Layout {
+            overhead: 2,
+            indent: pretty.then_some(2),
+        }
+    }
+
+    /// Items in the envelope `envelope` builds around an empty list, or a
+    /// bare array under `--bare`. The envelope is measured with the meta it
+    /// would have if nothing were cut, which is never shorter than the one
+    /// finally printed (`truncated` only goes from `false` to `true`).
+    fn new<E: Serialize>(
+        options: &OutputOptions,
+        meta: &Meta,
+        count: usize,
+        envelope: impl FnOnce(Meta) -> E,
+    ) -> Self {
+        if options.bare {
+            return Layout::bare(options.pretty);
+        }
+        let meta = Meta {
+            returned: count,
+            ..meta.clone()
+        };
+        Layout {
+            overhead: render(&envelope(meta), options.pretty, false).len(),
+            indent: options.pretty.then_some(4),
+        }
+    }
+
+    /// Bytes one item adds to the output, separator included.
+    fn item_bytes<T: Serialize>(&self, item: &T, first: bool) -> usize {
+        match self.indent {
+            None => serde_json::to_string(item).map(|s| s.len()).unwrap_or(0) + usize::from(!first),
+            Some(indent) => {
+                let rendered = serde_json::to_string_pretty(item).unwrap_or_default();
+                // Every line is indented, and each item follows ",\n".
+                rendered.len() + rendered.lines().count() * indent + 2
+            }
+        }
+    }
+
+    /// Bytes a non-empty list adds beyond its items: the newline and
+    /// indent before a pretty list's closing bracket.
+    fn closing_bytes(&self) -> usize {
+        self.indent.map_or(0, |indent| indent - 2)
+    }
+}
+
+/// Truncate `items` to the byte budget inside the envelope `envelope`
+/// builds (see [`Layout::new`]). A page cut short gets a `next_cursor`
+/// resuming after the last item kept, so room is made for the longest one
+/// any of `results` could produce.
+fn fit_to_budget<T: Serialize + Clone, E: Serialize>(
+    items: &[T],
+    results: &[SearchResult],
+    options: &OutputOptions,
+    meta: &Meta,
+    envelope: impl Fn(Meta) -> E,
+) -> (Vec<T>, bool) {
+    let layout = Layout::new(options, meta, items.len(), &envelope);
+    let (kept, truncated) = truncate_to_budget(items, options.max_bytes, &layout);
+    if !truncated || options.bare {
+        return (kept, truncated);
+    }
+
+    let meta = Meta {
+        next_cursor: results
+            .iter()
+            .map(|r| Cursor::after(r).encode())
+            .chain(meta.next_cursor.clone())
+            .max_by_key(String::len),
+        ..meta.clone()
+    };
+    let layout = Layout::new(options, &meta, items.len(), &envelope);
+    truncate_to_budget(items, options.max_bytes, &layout)
+}
+
+/// Truncate a list of serializable items so the output they are laid out
+/// in fits within a byte budget. Returns (kept_items, was_truncated). The
+/// first item is always kept.
+fn truncate_to_budget<T: Serialize + Clone>(
+    items: &[T],
+    max_bytes: Option<usize>,
+    layout: &Layout,
+) -> (Vec<T>, bool) {
+    let budget = match max_bytes {
+        Some(b) => b,
+        None => return (items.to_vec(), false),
+    };
+
+    let available = budget.saturating_sub(layout.overhead + layout.closing_bytes());
+    let mut kept = Vec::new();
+    let mut used: usize = 0;
+
+    for item in items {
+        let item_bytes = layout.item_bytes(item, kept.is_empty());
+        if used + item_bytes > available && !kept.is_empty() {
+            return (kept, true);
+        }
+        used += item_bytes;
+        kept.push(item.clone());
+    }
+
+    (kept, false)
+}
+
+/// Field projection applied to every output record.
+#[derive(Default)]
+pub struct Projection {
+    /// Keep only these top-level fields; entries with a `**` segment
+    /// (`**.id`) add every value they match under the entry itself
+    pub select: Option<Vec<String>>,
+    /// Also keep the top-level fields whose name matches
+    pub select_regex: Option<regex::Regex>,
+    /// Apply `select_regex` at every depth, keeping the path to each match
+    pub select_regex_deep: bool,
+    /// Drop these fields (dotted paths)
+    pub exclude: Option<Vec<String>>,
+    /// Replace the values at these dotted paths with a placeholder
+    pub redact: Vec<String>,
+    /// Redact with a SHA-256 digest of the value instead of `***`
+    pub redact_hash: bool,
+}
+
+const REDACTED: &str = "***";
+
+/// `value` as it is printed: selected, excluded and redacted.
+pub fn project_fields(value: &Value, projection: &Projection) -> Value {
+    let selecting = projection.select.is_some() || projection.select_regex.is_some();
+    let mut projected = match value {
+        Value::Object(map) if selecting => {
+            let fields = projection.select.as_deref().unwrap_or_default();
+            let regex = projection.select_regex.as_ref();
+            // Dotted entries keep the path to their value; a top-level key
+            // that itself contains dots is still selected whole.
+            let paths: Vec<Vec<&str>> = fields
+                .iter()
+                .filter(|f| !is_recursive_path(f) && !map.contains_key(f.as_str()))
+                .map(|f| f.split('.').collect())
+                .collect();
+            let paths: Vec<&[&str]> = paths.iter().map(Vec::as_slice).collect();
+            let mut filtered = match prune_to_paths(value, &paths) {
+                Some(Value::Object(pruned)) => pruned,
+                _ => serde_json::Map::new(),
+            };
+            for (k, v) in map {
+                if fields.contains(k) || regex.is_some_and(|re| re.is_match(k)) {
+                    filtered.insert(k.clone(), v.clone());
+                } else if let Some(pruned) = regex
+                    .filter(|_| projection.select_regex_deep)
+                    .and_then(|re| keep_matching_keys(v, re))
+                {
+                    match filtered.get_mut(k) {
+                        Some(existing) => merge_pruned(existing, pruned),
+                        None => {
+                            filtered.insert(k.clone(), pruned);
+                        }
+                    }
+                }
+            }
+            for field in fields.iter().filter(|f| is_recursive_path(f)) {
+                let segments: Vec<&str> = field.split('.').collect();
+                let mut found = Vec::new();
+                collect_recursive_values(value, &segments, &mut found);
+                filtered.insert(field.clone(), Value::Array(found));
+            }
+            Value::Object(filtered)
+        }
+        _ => value.clone(),
+    };
+
+    if let Some(paths) = &projection.exclude {
+        for path in paths {
+            let segments: Vec<&str> = path.split('.').collect();
+            remove_path(&mut projected, &segments);
+        }
+    }
+
+    redact_fields(&mut projected, projection);
+    projected
+}
+
+fn redact_fields(value: &mut Value, projection: &Projection) {
+    for path in &projection.redact {
+        let segments: Vec<&str> = path.split('.').collect();
+        redact_path(value, &segments, projection.redact_hash);
+    }
+}
+
+/// Apply `--redact` to the records of `results` in place, for output read
+/// from whole records rather than projected ones: `--group-by` counts and
+/// plan facets. Projecting them afterwards would redact twice.
+pub fn redact_results(results: &mut [SearchResult], projection: &Projection) {
+    for result in results {
+        redact_fields(&mut result.record.value, projection);
+    }
+}
+
+/// The parts of `value` on the way to any of `paths` (dotted `--select`
+/// entries split into segments), each path's value kept whole. Arrays keep
+/// the elements that have the rest of the path; a `*` segment stands for
+/// every key of an object or element of an array. `None` when no path
+/// exists in `value`.
+fn prune_to_paths(value: &Value, paths: &[&[&str]]) -> Option<Value> {
+    if paths.is_empty() {
+        return None;
+    }
+    if paths.iter().any(|p| p.is_empty()) {
+        return Some(value.clone());
+    }
+    match value {
+        Value::Object(map) => {
+            let kept: serde_json::Map<String, Value> = map
+                .iter()
+                .filter_map(|(k, v)| {
+                    let rest: Vec<&[&str]> = paths
+                        .iter()
+                        .filter(|p| p[0] == k || p[0] == "*")
+                        .map(|p| &p[1..])
+                        .collect();
+                    prune_to_paths(v, &rest).map(|pruned| (k.clone(), pruned))
+                })
+                .collect();
+            (!kept.is_empty()).then_some(Value::Object(kept))
+        }
+        Value::Array(items) => {
+            let rest: Vec<&[&str]> = paths
+                .iter()
+                .map(|p| if p[0] == "*" { &p[1..] } else { *p })
+                .collect();
+            let kept: Vec<Value> = items.iter().filter_map(|item| prune_to_paths(item, &rest)).collect();
+            (!kept.is_empty()).then_some(Value::Array(kept))
+        }
+        _ => None,
+    }
+}
+
+/// Merge two prunings of the same value: objects key by key, anything else
+/// is replaced by `from`.
+fn merge_pruned(into: &mut Value, from: Value) {
+    match (into, from) {
+        (Value::Object(into), Value::Object(from)) => {
+            for (k, v) in from {
+                match into.get_mut(&k) {
+                    Some(existing) => merge_pruned(existing, v),
+                    None => {
+                        into.insert(k, v);
+                    }
+                }
+            }
+        }
+        (into, from) => *into = from,
+    }
+}
+
+/// `--select-regex-deep`: the parts of `value` leading to keys that match,
+/// each matching key with its whole value. `None` when nothing matches.
+fn keep_matching_keys(value: &Value, regex: &regex::Regex) -> Option<Value> {
+    match value {
+        Value::Object(map) => {
+            let kept: serde_json::Map<String, Value> = map
+                .iter()
+                .filter_map(|(k, v)| {
+                    if regex.is_match(k) {
+                        Some((k.clone(), v.clone()))
+                    } else {
+                        keep_matching_keys(v, regex).map(|pruned| (k.clone(), pruned))
+                    }
+                })
+                .collect();
+            (!kept.is_empty()).then_some(Value::Object(kept))
+        }
+        Value::Array(items) => {
+            let kept: Vec<Value> = items.iter().filter_map(|item| keep_matching_keys(item, regex)).collect();
+            (!kept.is_empty()).then_some(Value::Array(kept))
+        }
+        _ => None,
+    }
+}
+
+/// Whether a `--select` entry has a `**` segment, e.g. `**.id`.
+fn is_recursive_path(path: &str) -> bool {
+    path.split('.').any(|seg| seg == "**")
+}
+
+/// Collect the values at a dotted path where a `**` segment stands for any
+/// number of levels (including none), so `**.id` yields every `id` at any
+/// depth. Arrays apply the path to every element, as in `collect_path_values`.
+fn collect_recursive_values(value: &Value, segments: &[&str], out: &mut Vec<Value>) {
+    let Some((first, rest)) = segments.split_first() else {
+        out.push(value.clone());
+        return;
+    };
+
+    match value {
+        Value::Array(arr) => {
+            for item in arr {
+                collect_recursive_values(item, segments, out);
+            }
+        }
+        Value::Object(map) if *first == "**" => {
+            collect_recursive_values(value, rest, out);
+            for child in map.values() {
+                collect_recursive_values(child, segments, out);
+            }
+        }
+        Value::Object(map) => {
+            if let Some(child) = map.get(*first) {
+                collect_recursive_values(child, rest, out);
+            }
+        }
+        _ if *first == "**" => collect_recursive_values(value, rest, out),
+        _ => {}
+    }
+}
+
+/// Remove the field at a dotted path. Arrays along the way apply the
+/// remaining path to every element.
+fn remove_path(value: &mut Value, segments: &[&str]) {
+    let Some((first, rest)) = segments.split_first() else {
+        return;
+    };
+
+    match value {
+        Value::Object(map) => {
+            if rest.is_empty() {
+                map.remove(*first);
+            } else if let Some(child) = map.get_mut(*first) {
+                remove_path(child, rest);
+            }
+        }
+        Value::Array(arr) => {
+            for item in arr {
+                remove_path(item, segments);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Replace the value at a dotted path with a redaction placeholder. Arrays
+/// along the way (including the target itself) redact every element.
+fn redact_path(value: &mut Value, segments: &[&str], hash: bool) {
+    let Some((first, rest)) = segments.split_first() else {
+        match value {
+            Value::Array(arr) => {
+                for item in arr {
+                    redact_path(item, segments, hash);
+                }
+            }
+            other => *other = redacted_value(other, hash),
+        }
+        return;
+    };
+
+    match value {
+        Value::Object(map) => {
+            if let Some(child) = map.get_mut(*first) {
+                redact_path(child, rest, hash);
+            }
+        }
+        Value::Array(arr) => {
+            for item in arr {
+                redact_path(item, segments, hash);
             }
         }
+        _ => {}
     }
 }
 
-/// Truncate a list of serializable items to fit within a byte budget.
-/// Returns (kept_items, was_truncated).
-/// Reserves ~200 bytes for the envelope/meta overhead.
-fn truncate_to_budget<T: Serialize + Clone>(items: &[T], max_bytes: Option<usize>) -> (Vec<T>, bool) {
-    let budget = match max_bytes {
-        Some(b) => b,
-        None => return (items.to_vec(), false),
-    };
-
-    let overhead = 200; // meta + envelope structure
-    let available = budget.saturating_sub(overhead);
-    let mut kept = Vec::new();
-    let mut used: usize = 0;
-
-    for item in items {
-        let item_json = serde_json::to_string(item).unwrap_or_default();
-        let item_bytes = item_json.len() + 2; // comma + newline
-        if used + item_bytes > available && !kept.is_empty() {
-            return (kept, true);
-        }
-        used += item_bytes;
-        kept.push(item.clone());
+fn redacted_value(value: &Value, hash: bool) -> Value {
+    if !hash {
+        return Value::String(REDACTED.to_string());
     }
 
-    (kept, false)
+    use sha2::{Digest, Sha256};
+    let digest = Sha256::digest(serde_json::to_string(value).unwrap_or_default());
+    let hex: String = digest.iter().map(|b| format!("{:02x}", b)).collect();
+    Value::String(format!("sha256:{}", hex))
 }
 
-fn project_fields(value: &Value, select_fields: &Option<Vec<String>>) -> Value {
-    match select_fields {
-        Some(fields) => {
-            if let Value::Object(map) = value {
-                let filtered: serde_json::Map<String, Value> = map
-                    .iter()
-                    .filter(|(k, _)| fields.contains(k))
-                    .map(|(k, v)| (k.clone(), v.clone()))
-                    .collect();
-                Value::Object(filtered)
-            } else {
-                value.clone()
-            }
-        }
-        None => value.clone(),
-    }
+/// The values value mode prints for one result, read from the projected
+/// record so `--select`, `--exclude` and `--redact` apply.
+fn record_values(result: &SearchResult, options: &OutputOptions) -> Vec<Value> {
+    extract_matching_values(&project_fields(&result.record.value, &options.projection), &options.value_fields)
 }
 
-fn extract_matching_values(value: &Value) -> Vec<Value> {
+fn extract_matching_values(value: &Value, fields: &[String]) -> Vec<Value> {
     let mut values = Vec::new();
+    if !fields.is_empty() {
+        for field in fields {
+            let segments: Vec<&str> = field.split('.').collect();
+            collect_path_values(value, &segments, &mut values);
+        }
+        return values;
+    }
+
     match value {
         Value::Object(map) => {
             for val in map.values() {
@@ -221,6 +1173,29 @@ fn extract_matching_values(value: &Value) -> Vec<Value> {
     values
 }
 
+/// Collect the value(s) at a dotted path. Arrays along the way apply the
+/// rest of the path to every element, so `users.email` yields each email.
+fn collect_path_values(value: &Value, segments: &[&str], out: &mut Vec<Value>) {
+    let Some((first, rest)) = segments.split_first() else {
+        out.push(value.clone());
+        return;
+    };
+
+    match value {
+        Value::Object(map) => {
+            if let Some(child) = map.get(*first) {
+                collect_path_values(child, rest, out);
+            }
+        }
+        Value::Array(arr) => {
+            for item in arr {
+                collect_path_values(item, segments, out);
+            }
+        }
+        _ => {}
+    }
+}
+
 // ---------------------------------------------------------------------------
 // Overflow plan
 // ---------------------------------------------------------------------------
@@ -240,15 +1215,51 @@ pub struct PlanMeta {
     pub threshold: usize,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub files_searched: Option<usize>,
+    /// Rendered size of the page that exceeded `--overflow-bytes`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub output_bytes: Option<usize>,
+    /// Fetch size `--max-fetch` capped the search at: counts and facets
+    /// cover only that many matches
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sampled: Option<usize>,
+    /// Matches per file (`--count-by-file`)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub counts_by_file: Option<BTreeMap<String, usize>>,
+    /// What triggered the plan, with the values involved
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub explain: String,
 }
 
+pub type Facets = BTreeMap<String, Vec<(String, usize)>>;
+
 #[derive(Serialize)]
 pub struct Plan {
     pub fields: Vec<FieldInfo>,
-    pub facets: BTreeMap<String, Vec<(String, usize)>>,
+    pub facets: Facets,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub facets_by_file: Option<BTreeMap<String, Facets>>,
     pub commands: Vec<String>,
 }
 
+/// Options controlling how the overflow plan is built.
+#[derive(Default)]
+pub struct PlanOptions {
+    /// Also break facet counts down per source file
+    pub facet_by_file: bool,
+    /// Whether values differing only in case share a facet bucket
+    pub facet_case: FacetCase,
+    /// Sort object keys in the printed JSON
+    pub sort_keys: bool,
+    /// Size of the results that went over `--overflow-bytes`, for meta
+    pub output_bytes: Option<usize>,
+    /// Fetch size when `--max-fetch` cut the search short, for meta
+    pub sampled: Option<usize>,
+    /// Matches per file (`--count-by-file`), for meta
+    pub counts_by_file: Option<BTreeMap<String, usize>>,
+    /// Why the plan was returned, for meta
+    pub explain: String,
+}
+
 #[derive(Serialize)]
 pub struct FieldInfo {
     pub name: String,
@@ -262,15 +1273,36 @@ pub fn build_plan(
     results: &[SearchResult],
     query: &str,
     input: &str,
+    options: &PlanOptions,
 ) -> Plan {
     // field_name -> (distinct values set, value -> count)
     let mut field_stats: HashMap<String, HashMap<String, usize>> = HashMap::new();
+    // file -> field_name -> value -> count
+    let mut file_stats: BTreeMap<String, HashMap<String, HashMap<String, usize>>> = BTreeMap::new();
+    // (field_name, folded value) -> first original spelling seen
+    let mut spellings: HashMap<(String, String), String> = HashMap::new();
 
     for sr in results {
         if let Value::Object(map) = &sr.record.value {
             for (key, val) in map {
                 let entry = field_stats.entry(key.clone()).or_default();
-                let stringified = value_to_facet_string(val);
+                let mut stringified = value_to_facet_string(val);
+                if options.facet_case == FacetCase::Fold {
+                    let folded = stringified.to_lowercase();
+                    spellings
+                        .entry((key.clone(), folded.clone()))
+                        .or_insert(stringified);
+                    stringified = folded;
+                }
+                if options.facet_by_file {
+                    *file_stats
+                        .entry(sr.record.file.clone())
+                        .or_default()
+                        .entry(key.clone())
+                        .or_default()
+                        .entry(stringified.clone())
+                        .or_insert(0) += 1;
+                }
                 *entry.entry(stringified).or_insert(0) += 1;
             }
         }
@@ -288,23 +1320,11 @@ pub fn build_plan(
         .collect();
     fields.sort_by_key(|f| f.distinct);
 
-    // Build facets: only include fields with distinct count <= 20 (low
-    // cardinality). Show top 5 values sorted by count descending.
-    let mut facets: BTreeMap<String, Vec<(String, usize)>> = BTreeMap::new();
-    for (name, value_counts) in &field_stats {
-        if value_counts.len() <= 20 {
-            let mut pairs: Vec<(String, usize)> = value_counts
-                .iter()
-                .map(|(v, &c)| (v.clone(), c))
-                .collect();
-            pairs.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
-            pairs.truncate(5);
-            facets.insert(name.clone(), pairs);
-        }
-    }
+    let mut facets = build_facets(&field_stats);
+    restore_spellings(&mut facets, &spellings);
 
     // Generate command suggestions for each facet field.
-    let commands: Vec<String> = facets
+    let mut commands: Vec<String> = facets
         .keys()
         .map(|field_name| {
             format!(
@@ -314,14 +1334,74 @@ pub fn build_plan(
         })
         .collect();
 
+    let facets_by_file = if options.facet_by_file {
+        let per_file: BTreeMap<String, Facets> = file_stats
+            .iter()
+            .map(|(file, stats)| {
+                let mut file_facets = build_facets(stats);
+                restore_spellings(&mut file_facets, &spellings);
+                (file.clone(), file_facets)
+            })
+            .collect();
+
+        // Narrow to a single file, per field.
+        for (file, file_facets) in &per_file {
+            for field_name in file_facets.keys() {
+                commands.push(format!(
+                    "jsonai search -q {:?} --field {} {}",
+                    query, field_name, file
+                ));
+            }
+        }
+
+        Some(per_file)
+    } else {
+        None
+    };
+
     Plan {
         fields,
         facets,
+        facets_by_file,
         commands,
     }
 }
 
+/// Build facets: only include fields with distinct count <= 20 (low
+/// cardinality). Show top 5 values sorted by count descending.
+fn build_facets(field_stats: &HashMap<String, HashMap<String, usize>>) -> Facets {
+    let mut facets: Facets = BTreeMap::new();
+    for (name, value_counts) in field_stats {
+        if value_counts.len() <= 20 {
+            let mut pairs: Vec<(String, usize)> = value_counts
+                .iter()
+                .map(|(v, &c)| (v.clone(), c))
+                .collect();
+            pairs.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+            pairs.truncate(5);
+            facets.insert(name.clone(), pairs);
+        }
+    }
+    facets
+}
+
+/// Replace case-folded facet values with the original spelling they were
+/// folded from. A no-op when nothing was folded.
+fn restore_spellings(facets: &mut Facets, spellings: &HashMap<(String, String), String>) {
+    if spellings.is_empty() {
+        return;
+    }
+    for (name, pairs) in facets.iter_mut() {
+        for (value, _) in pairs.iter_mut() {
+            if let Some(original) = spellings.get(&(name.clone(), value.clone())) {
+                *value = original.clone();
+            }
+        }
+    }
+}
+
 /// Format the full plan envelope as pretty-printed JSON.
+#[allow(clippy::too_many_arguments)]
 pub fn format_plan_output(
     results: &[SearchResult],
     total_matched: usize,
@@ -329,9 +1409,10 @@ pub fn format_plan_output(
     files_searched: Option<usize>,
     query: &str,
     input: &str,
+    options: &PlanOptions,
     pretty: bool,
 ) -> String {
-    let plan = build_plan(results, query, input);
+    let plan = build_plan(results, query, input, options);
 
     let envelope = PlanEnvelope {
         meta: PlanMeta {
@@ -340,12 +1421,130 @@ pub fn format_plan_output(
             overflow: true,
             threshold,
             files_searched,
+            output_bytes: options.output_bytes,
+            sampled: options.sampled,
+            counts_by_file: options.counts_by_file.clone(),
+            explain: options.explain.clone(),
         },
         plan,
         results: vec![],
     };
 
-    to_json(&envelope, pretty)
+    render(&envelope, pretty, options.sort_keys)
+}
+
+/// One value of `jsonai distinct` and how many times it occurs.
+#[derive(Serialize, Debug, PartialEq)]
+pub struct DistinctValue {
+    pub value: Value,
+    pub count: usize,
+}
+
+/// Every distinct value at a dotted `field` path across `records`, with
+/// occurrence counts, sorted by value. Arrays along the path, or at its
+/// end, contribute each element. Values are told apart as facets are, so
+/// `1` and `"1"` share an entry (the first one seen is shown).
+pub fn distinct_values(records: &[Record], field: &str) -> Vec<DistinctValue> {
+    let segments: Vec<&str> = field.split('.').collect();
+    let mut counts: HashMap<String, DistinctValue> = HashMap::new();
+
+    for record in records {
+        let mut found = Vec::new();
+        collect_values_at(&record.value, &segments, &mut found);
+        for value in found {
+            counts
+                .entry(value_to_facet_string(value))
+                .or_insert_with(|| DistinctValue {
+                    value: value.clone(),
+                    count: 0,
+                })
+                .count += 1;
+        }
+    }
+
+    let mut values: Vec<DistinctValue> = counts.into_values().collect();
+    values.sort_by(|a, b| compare_values(&a.value, &b.value));
+    values
+}
+
+/// `--group-by`: for each field (dotted path), how many of `results` have
+/// each value there, keyed the way plan facets are (strings as is, other
+/// values as JSON). Array values count each element; records without the
+/// field are left out. A single field gives its counts directly, several
+/// give one map of counts per field.
+pub fn group_counts(results: &[SearchResult], fields: &[String], facet_case: &FacetCase) -> Value {
+    let mut groups: BTreeMap<&str, BTreeMap<String, usize>> = BTreeMap::new();
+    for field in fields {
+        let segments: Vec<&str> = field.split('.').collect();
+        let counts = groups.entry(field.as_str()).or_default();
+        // (folded value) -> first original spelling seen
+        let mut spellings: HashMap<String, String> = HashMap::new();
+        for result in results {
+            let mut found = Vec::new();
+            collect_values_at(&result.record.value, &segments, &mut found);
+            for value in found {
+                let mut key = value_to_facet_string(value);
+                if *facet_case == FacetCase::Fold {
+                    let folded = key.to_lowercase();
+                    key = spellings.entry(folded).or_insert(key).clone();
+                }
+                *counts.entry(key).or_insert(0) += 1;
+            }
+        }
+    }
+
+    let to_object = |counts: BTreeMap<String, usize>| {
+        Value::Object(counts.into_iter().map(|(k, c)| (k, Value::from(c))).collect())
+    };
+    if groups.len() == 1 {
+        return groups.into_values().next().map(to_object).unwrap_or_default();
+    }
+    Value::Object(
+        groups
+            .into_iter()
+            .map(|(field, counts)| (field.to_string(), to_object(counts)))
+            .collect(),
+    )
+}
+
+fn collect_values_at<'a>(value: &'a Value, segments: &[&str], found: &mut Vec<&'a Value>) {
+    match (segments.split_first(), value) {
+        (None, Value::Array(items)) => found.extend(items),
+        (None, _) => found.push(value),
+        (Some((first, rest)), Value::Object(map)) => {
+            if let Some(child) = map.get(*first) {
+                collect_values_at(child, rest, found);
+            }
+        }
+        (Some(_), Value::Array(items)) => {
+            for item in items {
+                collect_values_at(item, segments, found);
+            }
+        }
+        (Some(_), _) => {}
+    }
+}
+
+/// Order values null < booleans < numbers < strings < arrays < objects;
+/// numbers numerically, everything else by its facet string.
+fn compare_values(a: &Value, b: &Value) -> std::cmp::Ordering {
+    fn rank(value: &Value) -> u8 {
+        match value {
+            Value::Null => 0,
+            Value::Bool(_) => 1,
+            Value::Number(_) => 2,
+            Value::String(_) => 3,
+            Value::Array(_) => 4,
+            Value::Object(_) => 5,
+        }
+    }
+    rank(a).cmp(&rank(b)).then_with(|| match (a, b) {
+        (Value::Number(x), Value::Number(y)) => x
+            .as_f64()
+            .partial_cmp(&y.as_f64())
+            .unwrap_or(std::cmp::Ordering::Equal),
+        _ => value_to_facet_string(a).cmp(&value_to_facet_string(b)),
+    })
 }
 
 /// Convert a serde_json::Value to a string suitable for facet counting.
@@ -359,3 +1558,670 @@ fn value_to_facet_string(val: &Value) -> String {
         other => serde_json::to_string(other).unwrap_or_default(),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        build_plan, distinct_values, format_output, group_counts, project_fields, to_json_inline, write_stream,
+        Meta, OutputOptions, PlanOptions, Projection, CLIP_MARKER,
+    };
+    use crate::cli::{FacetCase, OutputMode};
+    use crate::engine::{Record, SearchResult};
+    use crate::manipulate::apply_patch;
+    use serde_json::json;
+
+    #[test]
+    fn exclude_nested_field_keeps_siblings() {
+        let record = json!({
+            "id": 1,
+            "meta": { "raw": "big blob", "source": "api" },
+            "embedding": [0.1, 0.2]
+        });
+        let projection = Projection {
+            exclude: Some(vec!["meta.raw".to_string(), "embedding".to_string()]),
+            ..Default::default()
+        };
+
+        let projected = project_fields(&record, &projection);
+
+        assert_eq!(projected, json!({ "id": 1, "meta": { "source": "api" } }));
+    }
+
+    #[test]
+    fn exclude_applies_to_every_array_element() {
+        let record = json!({ "items": [{ "sku": "a", "raw": 1 }, { "sku": "b", "raw": 2 }] });
+        let projection = Projection {
+            exclude: Some(vec!["items.raw".to_string()]),
+            ..Default::default()
+        };
+
+        let projected = project_fields(&record, &projection);
+
+        assert_eq!(projected, json!({ "items": [{ "sku": "a" }, { "sku": "b" }] }));
+    }
+
+    #[test]
+    fn redact_nested_secret_keeps_other_fields() {
+        let record = json!({
+            "user": { "name": "Ada", "token": "abc123" },
+            "id": 7
+        });
+        let projection = Projection {
+            redact: vec!["user.token".to_string()],
+            ..Default::default()
+        };
+
+        let projected = project_fields(&record, &projection);
+
+        assert_eq!(projected, json!({ "user": { "name": "Ada", "token": "***" }, "id": 7 }));
+    }
+
+    #[test]
+    fn redact_hash_is_stable_and_covers_arrays() {
+        let record = json!({ "emails": ["a@x.io", "b@x.io"], "keep": true });
+        let projection = Projection {
+            redact: vec!["emails".to_string()],
+            redact_hash: true,
+            ..Default::default()
+        };
+
+        let projected = project_fields(&record, &projection);
+        let emails = projected["emails"].as_array().unwrap();
+
+        assert_eq!(emails.len(), 2);
+        assert!(emails.iter().all(|e| e.as_str().unwrap().starts_with("sha256:")));
+        assert_ne!(emails[0], emails[1]);
+        assert_eq!(projected["keep"], json!(true));
+        assert_eq!(project_fields(&record, &projection), projected);
+    }
+
+    #[test]
+    fn select_recursive_path_collects_ids_at_every_depth() {
+        let record = json!({
+            "id": 1,
+            "name": "root",
+            "child": { "id": 2, "meta": { "id": 3 } },
+            "items": [{ "id": 4 }, { "tags": [{ "id": 5 }] }, { "sku": "x" }]
+        });
+        let projection = Projection {
+            select: Some(vec!["name".to_string(), "**.id".to_string()]),
+            ..Default::default()
+        };
+
+        let projected = project_fields(&record, &projection);
+
+        assert_eq!(projected, json!({ "name": "root", "**.id": [1, 2, 3, 4, 5] }));
+    }
+
+    #[test]
+    fn select_recursive_path_anchors_trailing_segments() {
+        let record = json!({
+            "a": { "owner": { "id": 1 }, "id": 9 },
+            "b": [{ "owner": { "id": 2 } }]
+        });
+        let projection = Projection {
+            select: Some(vec!["**.owner.id".to_string()]),
+            ..Default::default()
+        };
+
+        assert_eq!(project_fields(&record, &projection), json!({ "**.owner.id": [1, 2] }));
+    }
+
+    #[test]
+    fn select_regex_projects_matching_top_level_fields() {
+        let record = json!({
+            "host": "web-1",
+            "metric_cpu": 0.5,
+            "metric_mem": 512,
+            "note": "x",
+            "nested": { "metric_disk": 3 }
+        });
+        let projection = Projection {
+            select_regex: Some(regex::Regex::new("^metric_").unwrap()),
+            ..Default::default()
+        };
+        assert_eq!(
+            project_fields(&record, &projection),
+            json!({ "metric_cpu": 0.5, "metric_mem": 512 })
+        );
+
+        // Composes with literal --select.
+        let projection = Projection {
+            select: Some(vec!["host".to_string()]),
+            select_regex: Some(regex::Regex::new("^metric_").unwrap()),
+            ..Default::default()
+        };
+        assert_eq!(
+            project_fields(&record, &projection),
+            json!({ "host": "web-1", "metric_cpu": 0.5, "metric_mem": 512 })
+        );
+    }
+
+    #[test]
+    fn select_regex_deep_keeps_paths_to_nested_matches() {
+        let record = json!({
+            "metric_cpu": 0.5,
+            "note": "x",
+            "nested": { "metric_disk": 3, "label": "d" },
+            "samples": [{ "metric_io": 1, "t": 0 }, { "t": 1 }],
+            "empty": {}
+        });
+        let projection = Projection {
+            select_regex: Some(regex::Regex::new("^metric_").unwrap()),
+            select_regex_deep: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            project_fields(&record, &projection),
+            json!({
+                "metric_cpu": 0.5,
+                "nested": { "metric_disk": 3 },
+                "samples": [{ "metric_io": 1 }]
+            })
+        );
+    }
+
+    #[test]
+    fn inline_keeps_small_scalar_containers_on_one_line() {
+        let doc = json!({
+            "ports": [80, 443, 8080],
+            "hosts": ["a", "b", "c", "d", "e"],
+            "limits": { "cpu": 2, "mem": "1G" },
+            "empty": []
+        });
+
+        assert_eq!(
+            to_json_inline(&doc, 3),
+            "{\n  \"empty\": [],\n  \"hosts\": [\n    \"a\",\n    \"b\",\n    \"c\",\n    \"d\",\n    \"e\"\n  ],\n  \"limits\": {\"cpu\": 2, \"mem\": \"1G\"},\n  \"ports\": [80, 443, 8080]\n}"
+        );
+    }
+
+    #[test]
+    fn inline_zero_matches_plain_pretty_printing() {
+        let doc = json!({ "a": [1, [2, 3], {}], "b": { "c": null } });
+        assert_eq!(to_json_inline(&doc, 0), serde_json::to_string_pretty(&doc).unwrap());
+    }
+
+    fn result(file: &str, value: serde_json::Value) -> SearchResult {
+        SearchResult {
+            record: Record {
+                pointer: String::new(),
+                file: file.to_string(),
+                value,
+            },
+            score: 1.0,
+            locator: None,
+            snippet: None,
+            explanation: None,
+            doc: None,
+        }
+    }
+
+    #[test]
+    fn facet_by_file_breaks_down_counts_per_file() {
+        let results = vec![
+            result("a.json", json!({ "level": "error" })),
+            result("a.json", json!({ "level": "error" })),
+            result("b.json", json!({ "level": "warn" })),
+        ];
+        let options = PlanOptions {
+            facet_by_file: true,
+            ..Default::default()
+        };
+
+        let plan = build_plan(&results, "x", "logs/", &options);
+        let by_file = plan.facets_by_file.unwrap();
+
+        assert_eq!(plan.facets["level"], vec![("error".to_string(), 2), ("warn".to_string(), 1)]);
+        assert_eq!(by_file["a.json"]["level"], vec![("error".to_string(), 2)]);
+        assert_eq!(by_file["b.json"]["level"], vec![("warn".to_string(), 1)]);
+        assert!(plan.commands.iter().any(|c| c.ends_with("--field level b.json")));
+    }
+
+    #[test]
+    fn facets_by_file_omitted_by_default() {
+        let results = vec![result("a.json", json!({ "level": "error" }))];
+        let plan = build_plan(&results, "x", "a.json", &PlanOptions::default());
+        assert!(plan.facets_by_file.is_none());
+    }
+
+    #[test]
+    fn sort_keys_orders_envelope_and_record_keys() {
+        let results = vec![result("a.json", json!({ "zeta": 1, "alpha": { "y": 2, "b": 3 } }))];
+        let meta = || Meta {
+            total: 1,
+            limit: 20,
+            ..Default::default()
+        };
+
+        let default = format_output(&results, meta(), &OutputOptions::default());
+        assert!(default.starts_with(r#"{"meta":{"total":1,"returned":1,"limit":20"#));
+
+        let options = OutputOptions {
+            sort_keys: true,
+            ..Default::default()
+        };
+        let sorted = format_output(&results, meta(), &options);
+        assert_eq!(
+            sorted,
+            r#"{"meta":{"limit":20,"returned":1,"total":1,"truncated":false},"results":[{"alpha":{"b":3,"y":2},"zeta":1}]}"#
+        );
+    }
+
+    #[test]
+    fn max_bytes_fits_output_exactly_in_every_layout() {
+        let results: Vec<SearchResult> = (0..12)
+            .map(|i| result("a.json", json!({ "id": i, "tags": ["x", "y"], "nested": { "note": "n".repeat(i * 3) } })))
+            .collect();
+        let meta = || Meta {
+            total: 40,
+            limit: 12,
+            files_searched: Some(3),
+            hint: Some("a longer meta field the envelope has to make room for".to_string()),
+            ..Default::default()
+        };
+
+        for (mode, name) in [(OutputMode::Match, "match"), (OutputMode::Hit, "hit"), (OutputMode::Value, "value")] {
+            for (pretty, bare) in [(false, false), (true, false), (false, true), (true, true)] {
+                let options = |max_bytes| OutputOptions {
+                    mode: mode.clone(),
+                    pretty,
+                    bare,
+                    max_bytes,
+                    ..Default::default()
+                };
+                let label = format!("{} pretty={} bare={}", name, pretty, bare);
+
+                let full = format_output(&results, meta(), &options(None));
+                let exact = format_output(&results, meta(), &options(Some(full.len())));
+                assert_eq!(exact, full, "{}", label);
+
+                // The first result is kept even when it alone is over budget
+                // (along with the cursor to resume after it).
+                let first = format_output(&results, meta(), &options(Some(1)));
+                for budget in (first.len()..full.len()).step_by(7) {
+                    let output = format_output(&results, meta(), &options(Some(budget)));
+                    assert!(output.len() <= budget, "{} budget {}: {} bytes", label, budget, output.len());
+                    assert!(output.len() < full.len(), "{}", label);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn map_output_keys_records_by_pointer() {
+        let mut results = vec![
+            result("a.json", json!({ "id": 1, "name": "Ada" })),
+            result("a.json", json!({ "id": 2, "name": "Bob" })),
+        ];
+        results[0].record.pointer = "/users/0".to_string();
+        results[1].record.pointer = "/users/1".to_string();
+        let options = OutputOptions {
+            mode: OutputMode::Map,
+            projection: Projection {
+                select: Some(vec!["name".to_string()]),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let meta = Meta {
+            total: 2,
+            limit: 20,
+            ..Default::default()
+        };
+
+        let output: serde_json::Value = serde_json::from_str(&format_output(&results, meta, &options)).unwrap();
+        assert_eq!(
+            output["map"],
+            json!({ "/users/0": { "name": "Ada" }, "/users/1": { "name": "Bob" } })
+        );
+        assert_eq!(output["meta"]["returned"], json!(2));
+    }
+
+    #[test]
+    fn map_output_keeps_equal_pointers_from_different_files_apart() {
+        let mut results = vec![
+            result("a.json", json!({ "env": "prod" })),
+            result("b.json", json!({ "env": "dev" })),
+        ];
+        for r in &mut results {
+            r.record.pointer = "/config".to_string();
+        }
+        let options = OutputOptions {
+            mode: OutputMode::Map,
+            bare: true,
+            map_keys_with_file: true,
+            ..Default::default()
+        };
+
+        let output: serde_json::Value =
+            serde_json::from_str(&format_output(&results, Meta::default(), &options)).unwrap();
+        assert_eq!(
+            output,
+            json!({ "a.json#/config": { "env": "prod" }, "b.json#/config": { "env": "dev" } })
+        );
+    }
+
+    #[test]
+    fn value_fields_collect_only_named_field_across_records() {
+        let results = vec![
+            result("a.json", json!({ "name": "Ada", "email": "ada@example.com", "age": 36 })),
+            result("a.json", json!({ "name": "Bob", "email": "bob@example.com" })),
+            result("a.json", json!({ "name": "Cy" })),
+        ];
+        let options = OutputOptions {
+            mode: OutputMode::Value,
+            bare: true,
+            value_fields: vec!["email".to_string()],
+            ..Default::default()
+        };
+
+        let output = format_output(&results, Meta::default(), &options);
+        assert_eq!(output, r#"["ada@example.com","bob@example.com"]"#);
+    }
+
+    #[test]
+    fn value_fields_follow_dotted_paths_through_arrays() {
+        let results = vec![result(
+            "a.json",
+            json!({ "team": "core", "members": [{ "email": "a@x.io" }, { "email": "b@x.io" }] }),
+        )];
+        let options = OutputOptions {
+            mode: OutputMode::Value,
+            bare: true,
+            value_fields: vec!["team".to_string(), "members.email".to_string()],
+            ..Default::default()
+        };
+
+        let output = format_output(&results, Meta::default(), &options);
+        assert_eq!(output, r#"["core","a@x.io","b@x.io"]"#);
+    }
+
+    #[test]
+    fn stream_writes_one_line_per_result_and_meta_trailer() {
+        let results = vec![
+            result("a.json", json!({ "name": "Ada" })),
+            result("a.json", json!({ "name": "Bob" })),
+            result("b.json", json!({ "name": "Cy" })),
+        ];
+        let meta = Meta {
+            total: 3,
+            limit: 20,
+            ..Default::default()
+        };
+
+        let mut out = Vec::new();
+        write_stream(&results, meta, &OutputOptions::default(), &mut out).unwrap();
+        let lines: Vec<serde_json::Value> = String::from_utf8(out)
+            .unwrap()
+            .lines()
+            .map(|l| serde_json::from_str(l).unwrap())
+            .collect();
+
+        assert_eq!(lines.len(), 4);
+        assert_eq!(lines[1], json!({ "name": "Bob" }));
+        assert_eq!(lines[3]["meta"]["returned"], json!(3));
+        assert_eq!(lines[3]["meta"]["truncated"], json!(false));
+    }
+
+    #[test]
+    fn stream_stops_at_max_bytes_and_reports_truncation() {
+        let results = vec![
+            result("a.json", json!({ "name": "Ada" })),
+            result("a.json", json!({ "name": "Bob" })),
+        ];
+        let meta = Meta {
+            total: 2,
+            limit: 20,
+            ..Default::default()
+        };
+        let options = OutputOptions {
+            max_bytes: Some(20),
+            ..Default::default()
+        };
+
+        let mut out = Vec::new();
+        write_stream(&results, meta, &options, &mut out).unwrap();
+        let text = String::from_utf8(out).unwrap();
+        let lines: Vec<&str> = text.lines().collect();
+
+        // One record fits in 20 bytes; the second is dropped for the trailer.
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0], r#"{"name":"Ada"}"#);
+        let trailer: serde_json::Value = serde_json::from_str(lines[1]).unwrap();
+        assert_eq!(trailer["meta"]["total"], json!(2));
+        assert_eq!(trailer["meta"]["returned"], json!(1));
+        assert_eq!(trailer["meta"]["truncated"], json!(true));
+    }
+
+    #[test]
+    fn clip_record_bytes_shortens_oversized_record_and_keeps_others() {
+        let blob = "x".repeat(2_000);
+        let results = vec![
+            result("a.json", json!({ "id": 1, "note": "short" })),
+            result("a.json", json!({ "id": 2, "body": blob, "title": "big" })),
+        ];
+        let meta = Meta {
+            total: 2,
+            limit: 20,
+            ..Default::default()
+        };
+        let options = OutputOptions {
+            clip_record_bytes: Some(200),
+            ..Default::default()
+        };
+
+        let output: serde_json::Value =
+            serde_json::from_str(&format_output(&results, meta, &options)).unwrap();
+        let records = output["results"].as_array().unwrap();
+
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0], json!({ "id": 1, "note": "short" }));
+        assert_eq!(records[1]["title"], json!("big"));
+        let body = records[1]["body"].as_str().unwrap();
+        assert!(body.ends_with(CLIP_MARKER));
+        assert!(serde_json::to_string(&records[1]).unwrap().len() <= 200);
+        assert_eq!(output["meta"]["clipped"], json!(1));
+    }
+
+    #[test]
+    fn clip_record_bytes_flags_clipped_hits() {
+        let results = vec![result("a.json", json!({ "body": "é".repeat(500) }))];
+        let options = OutputOptions {
+            mode: OutputMode::Hit,
+            bare: true,
+            clip_record_bytes: Some(100),
+            ..Default::default()
+        };
+
+        let hits: serde_json::Value =
+            serde_json::from_str(&format_output(&results, Meta::default(), &options)).unwrap();
+        assert_eq!(hits[0]["clipped"], json!(true));
+        assert!(serde_json::to_string(&hits[0]["record"]).unwrap().len() <= 100);
+    }
+
+    #[test]
+    fn distinct_values_counts_and_sorts_across_files() {
+        let record = |file: &str, pointer: &str, value| Record {
+            pointer: pointer.to_string(),
+            file: file.to_string(),
+            value,
+        };
+        let records = vec![
+            record("a.json", "/0", json!({ "status": "open", "tags": ["x", "y"] })),
+            record("a.json", "/1", json!({ "status": "closed", "tags": ["x"] })),
+            record("b.json", "/0", json!({ "status": "open" })),
+            record("b.json", "/1", json!({ "status": 10 })),
+            record("b.json", "/2", json!({ "status": 9 })),
+            record("b.json", "/3", json!({ "other": 1 })),
+        ];
+
+        let values: Vec<(serde_json::Value, usize)> = distinct_values(&records, "status")
+            .into_iter()
+            .map(|d| (d.value, d.count))
+            .collect();
+        assert_eq!(
+            values,
+            vec![(json!(9), 1), (json!(10), 1), (json!("closed"), 1), (json!("open"), 2)]
+        );
+
+        let tags: Vec<(serde_json::Value, usize)> =
+            distinct_values(&records, "tags").into_iter().map(|d| (d.value, d.count)).collect();
+        assert_eq!(tags, vec![(json!("x"), 2), (json!("y"), 1)]);
+        assert!(distinct_values(&records, "nope").is_empty());
+    }
+
+    #[test]
+    fn facet_case_fold_collapses_mixed_case_values() {
+        let results = vec![
+            result("a.json", json!({ "country": "US" })),
+            result("a.json", json!({ "country": "us" })),
+            result("b.json", json!({ "country": "Us" })),
+            result("b.json", json!({ "country": "DE" })),
+        ];
+        let options = PlanOptions {
+            facet_by_file: true,
+            facet_case: FacetCase::Fold,
+            ..Default::default()
+        };
+
+        let plan = build_plan(&results, "x", "logs/", &options);
+        let by_file = plan.facets_by_file.unwrap();
+
+        assert_eq!(plan.facets["country"], vec![("US".to_string(), 3), ("DE".to_string(), 1)]);
+        assert_eq!(plan.fields[0].distinct, 2);
+        assert_eq!(by_file["b.json"]["country"], vec![("DE".to_string(), 1), ("US".to_string(), 1)]);
+    }
+
+    #[test]
+    fn facet_case_sensitive_keeps_spellings_apart() {
+        let results = vec![
+            result("a.json", json!({ "country": "US" })),
+            result("a.json", json!({ "country": "us" })),
+        ];
+        let plan = build_plan(&results, "x", "a.json", &PlanOptions::default());
+        assert_eq!(plan.facets["country"].len(), 2);
+    }
+
+    #[test]
+    fn patch_output_recreates_matches_in_empty_document() {
+        let mut nested = result("a.json", json!({ "name": "Ada" }));
+        nested.record.pointer = "/users/0".to_string();
+        let mut deep = result("a.json", json!({ "host": "db" }));
+        deep.record.pointer = "/config/database".to_string();
+
+        let options = OutputOptions {
+            mode: OutputMode::Patch,
+            ..Default::default()
+        };
+        let output = format_output(&[nested, deep], Meta::default(), &options);
+        let ops: serde_json::Value = serde_json::from_str(&output).unwrap();
+
+        assert_eq!(ops[1], json!({ "op": "add", "path": "/users/0", "value": { "name": "Ada" } }));
+
+        let mut doc = json!({});
+        apply_patch(&mut doc, ops.as_array().unwrap()).unwrap();
+        assert_eq!(doc.pointer("/users/0/name"), Some(&json!("Ada")));
+        assert_eq!(doc.pointer("/config/database"), Some(&json!({ "host": "db" })));
+    }
+
+    #[test]
+    fn csv_output_has_union_header_and_quotes_cells() {
+        let results = vec![
+            result("a.json", json!({ "name": "Ada, Countess", "tags": ["x", "y"] })),
+            result("a.json", json!({ "name": "Grace \"Amazing\" Hopper", "age": 85, "note": null })),
+        ];
+        let options = OutputOptions {
+            mode: OutputMode::Csv,
+            ..Default::default()
+        };
+        assert_eq!(
+            format_output(&results, Meta::default(), &options),
+            "name,tags,age,note\n\"Ada, Countess\",\"[\"\"x\"\",\"\"y\"\"]\",,\n\"Grace \"\"Amazing\"\" Hopper\",,85,"
+        );
+
+        let selected = OutputOptions {
+            mode: OutputMode::Csv,
+            projection: Projection {
+                select: Some(vec!["age".to_string(), "name".to_string()]),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        assert_eq!(
+            format_output(&results, Meta::default(), &selected),
+            "age,name\n,\"Ada, Countess\"\n85,\"Grace \"\"Amazing\"\" Hopper\""
+        );
+    }
+
+    #[test]
+    fn select_keeps_nested_paths_and_omits_missing_ones() {
+        let record = json!({
+            "author": { "name": "Ada", "born": 1815 },
+            "price": 10,
+            "items": [{ "sku": "a", "qty": 1 }, { "qty": 2 }, { "sku": "c" }],
+            "a.b": 1
+        });
+        let select = |fields: &[&str]| {
+            let projection = Projection {
+                select: Some(fields.iter().map(|f| f.to_string()).collect()),
+                ..Default::default()
+            };
+            project_fields(&record, &projection)
+        };
+
+        assert_eq!(
+            select(&["author.name", "price"]),
+            json!({ "author": { "name": "Ada" }, "price": 10 })
+        );
+        assert_eq!(
+            select(&["author.name", "author.born"]),
+            json!({ "author": { "name": "Ada", "born": 1815 } })
+        );
+        assert_eq!(select(&["author.email", "price"]), json!({ "price": 10 }));
+        assert_eq!(select(&["price.value"]), json!({}));
+        assert_eq!(
+            select(&["items.*.sku"]),
+            json!({ "items": [{ "sku": "a" }, { "sku": "c" }] })
+        );
+        assert_eq!(select(&["a.b"]), json!({ "a.b": 1 }));
+    }
+
+    #[test]
+    fn csv_columns_read_nested_select_paths() {
+        let results = vec![result("a.json", json!({ "author": { "name": "Ada" }, "price": 10 }))];
+        let options = OutputOptions {
+            mode: OutputMode::Csv,
+            projection: Projection {
+                select: Some(vec!["author.name".to_string(), "price".to_string()]),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        assert_eq!(format_output(&results, Meta::default(), &options), "author.name,price\nAda,10");
+    }
+
+    #[test]
+    fn group_counts_tallies_values_per_field() {
+        let results = vec![
+            result("a.json", json!({ "status": "active", "meta": { "tier": "gold" } })),
+            result("a.json", json!({ "status": "Active", "meta": { "tier": "free" } })),
+            result("a.json", json!({ "status": "closed", "tags": ["x", "y"] })),
+            result("a.json", json!({ "tags": ["x"] })),
+        ];
+        let fields = |names: &[&str]| names.iter().map(|n| n.to_string()).collect::<Vec<_>>();
+
+        assert_eq!(
+            group_counts(&results, &fields(&["status"]), &FacetCase::Sensitive),
+            json!({ "active": 1, "Active": 1, "closed": 1 })
+        );
+        assert_eq!(
+            group_counts(&results, &fields(&["status"]), &FacetCase::Fold),
+            json!({ "active": 2, "closed": 1 })
+        );
+        assert_eq!(
+            group_counts(&results, &fields(&["meta.tier", "tags"]), &FacetCase::Sensitive),
+            json!({ "meta.tier": { "free": 1, "gold": 1 }, "tags": { "x": 2, "y": 1 } })
+        );
+    }
+}
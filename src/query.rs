@@ -1,43 +1,180 @@
 use anyhow::{bail, Context, Result};
 use jaq_core::load::{Arena, File, Loader};
-use jaq_core::{Compiler, Ctx, RcIter};
+use jaq_core::{Compiler, Ctx, Filter, Native, RcIter};
 use jaq_json::Val;
 use serde_json::Value;
-use std::io::{self, Read};
+use std::io::{self, Read, Write};
 
+use crate::cli::QueryArgs;
 use crate::output;
 
 const ESCAPED_BANG_HINT: &str = "`\\!` detected. Use `!=` (no backslash) or `== ... | not`.";
 const UNARY_BANG_HINT: &str = "Unary `!` is unsupported. Use `not`.";
 
-pub fn run_query(filter_str: &str, input: &str, pretty: bool) -> Result<()> {
-    let value = load_input(input)?;
-    let results = eval(filter_str, value)?;
+/// Run the filter over each input, or once over all of them with
+/// `--slurp`. Returns whether the command succeeded: the `--any`/`--none`
+/// verdict, with `--exit-status` whether the last output is truthy, and
+/// otherwise true.
+pub fn run_query(args: &QueryArgs, pretty: bool) -> Result<bool> {
+    let vars = bindings(args)?;
+    let names: Vec<String> = vars.iter().map(|(name, _)| format!("${}", name)).collect();
+    let filter = compile_with_vars(&args.filter, names.iter().map(String::as_str))?;
+    let eval = |input: Value| run_with_vars(&filter, vars.iter().map(|(_, value)| value.clone()), input);
+
+    let values = args.input.iter().flat_map(|input| match load_input(input) {
+        Ok(values) => values.into_iter().map(Ok).collect(),
+        Err(e) => vec![Err(e)],
+    });
+    let mut inputs: Box<dyn Iterator<Item = Result<Value>>> = if args.slurp {
+        let all = values.collect::<Result<Vec<_>>>();
+        Box::new(std::iter::once(all.map(Value::Array)))
+    } else {
+        Box::new(values)
+    };
+
+    if args.any || args.none {
+        let matched = any_truthy(&mut inputs, eval)?;
+        return Ok(matched == args.any);
+    }
+
+    let mut results = Vec::new();
+    for input in inputs {
+        results.extend(eval(input?)?);
+    }
+
+    write_results(&mut io::stdout().lock(), &results, args.raw_output, pretty)?;
+    Ok(!args.exit_status || results.last().is_some_and(is_truthy))
+}
+
+/// Anything but `null` and `false`, as jq sees it.
+fn is_truthy(value: &Value) -> bool {
+    !matches!(value, Value::Null | Value::Bool(false))
+}
+
+/// Print `results`: one result as itself and several as an array, or with
+/// `raw` each on its own line, strings unquoted.
+fn write_results(out: &mut impl Write, results: &[Value], raw: bool, pretty: bool) -> io::Result<()> {
+    if raw {
+        for result in results {
+            match result {
+                Value::String(s) => writeln!(out, "{}", s)?,
+                other => writeln!(out, "{}", output::to_json(other, pretty))?,
+            }
+        }
+        return Ok(());
+    }
 
     match results.len() {
-        0 => {}
-        1 => println!("{}", output::to_json(&results[0], pretty)),
-        _ => println!("{}", output::to_json(&results, pretty)),
+        0 => Ok(()),
+        1 => writeln!(out, "{}", output::to_json(&results[0], pretty)),
+        _ => writeln!(out, "{}", output::to_json(&results, pretty)),
     }
+}
 
-    Ok(())
+/// The `--arg`/`--argjson` variables, in order; a name bound twice by the
+/// same flag keeps its last value, as in jq. A name bound by both flags is
+/// rejected, since their relative order on the command line is lost.
+fn bindings(args: &QueryArgs) -> Result<Vec<(String, Value)>> {
+    let var_name = |name: &str| name.strip_prefix('$').unwrap_or(name).to_string();
+    let string_names: Vec<String> = args.args.chunks(2).map(|pair| var_name(&pair[0])).collect();
+    let mut json_names = args.json_args.chunks(2).map(|pair| var_name(&pair[0]));
+    if let Some(name) = json_names.find(|name| string_names.contains(name)) {
+        bail!("${} is bound by both --arg and --argjson; bind it once", name);
+    }
+
+    let strings = args
+        .args
+        .chunks(2)
+        .map(|pair| -> Result<_> { Ok((pair[0].clone(), Value::String(pair[1].clone()))) });
+    let json = args.json_args.chunks(2).map(|pair| {
+        let value = serde_json::from_str(&pair[1])
+            .with_context(|| format!("Invalid JSON for --argjson {}", pair[0]))?;
+        Ok((pair[0].clone(), value))
+    });
+
+    let mut vars: Vec<(String, Value)> = Vec::new();
+    for binding in strings.chain(json) {
+        let (name, value) = binding?;
+        let name = var_name(&name);
+        if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+            bail!("Invalid variable name `{}`: use letters, digits and `_`", name);
+        }
+        vars.retain(|(existing, _)| *existing != name);
+        vars.push((name, value));
+    }
+    Ok(vars)
+}
+
+/// Whether `eval` yields a truthy value (anything but `null`/`false`) for
+/// any of the inputs. Stops at the first input that does.
+fn any_truthy(
+    inputs: impl Iterator<Item = Result<Value>>,
+    eval: impl Fn(Value) -> Result<Vec<Value>>,
+) -> Result<bool> {
+    for input in inputs {
+        let results = eval(input?)?;
+        if results.iter().any(is_truthy) {
+            return Ok(true);
+        }
+    }
+    Ok(false)
 }
 
-fn load_input(input: &str) -> Result<Value> {
+/// The values in one input: a single document, or one per line for
+/// `.ndjson`/`.jsonl` files, each a separate input to the filter.
+fn load_input(input: &str) -> Result<Vec<Value>> {
     if input == "-" {
         let mut buf = String::new();
         io::stdin()
             .read_to_string(&mut buf)
             .context("Failed to read stdin")?;
-        serde_json::from_str(&buf).context("Invalid JSON from stdin")
+        return Ok(vec![serde_json::from_str(&buf).context("Invalid JSON from stdin")?]);
+    }
+
+    let content = std::fs::read_to_string(input).with_context(|| format!("Failed to read {}", input))?;
+    if is_line_delimited(input) {
+        content
+            .lines()
+            .enumerate()
+            .filter(|(_, text)| !text.trim().is_empty())
+            .map(|(line, text)| {
+                serde_json::from_str(text).with_context(|| format!("Invalid JSON in {} line {}", input, line + 1))
+            })
+            .collect()
     } else {
-        let content =
-            std::fs::read_to_string(input).with_context(|| format!("Failed to read {}", input))?;
-        serde_json::from_str(&content).with_context(|| format!("Invalid JSON in {}", input))
+        Ok(vec![crate::manipulate::parse_document(&content, input)?])
     }
 }
 
-fn eval(filter_str: &str, input: Value) -> Result<Vec<Value>> {
+fn is_line_delimited(path: &str) -> bool {
+    std::path::Path::new(path)
+        .extension()
+        .and_then(|e| e.to_str())
+        .is_some_and(|e| e.eq_ignore_ascii_case("ndjson") || e.eq_ignore_ascii_case("jsonl"))
+}
+
+/// A compiled jq filter, reusable across inputs.
+pub type JqFilter = Filter<Native<Val>>;
+
+/// Replace a search record with the output of `--transform`: `None` when
+/// the filter yields nothing, the value itself for one output, and an
+/// array when it yields several.
+pub fn transform(filter: &JqFilter, record: Value) -> Result<Option<Value>> {
+    let mut outputs = run(filter, record)?;
+    Ok(match outputs.len() {
+        0 => None,
+        1 => outputs.pop(),
+        _ => Some(Value::Array(outputs)),
+    })
+}
+
+pub fn compile(filter_str: &str) -> Result<JqFilter> {
+    compile_with_vars(filter_str, [])
+}
+
+/// Compile a filter that may refer to the global variables `names`
+/// (each starting with `$`); run it with [`run_with_vars`].
+fn compile_with_vars<'a>(filter_str: &'a str, names: impl IntoIterator<Item = &'a str>) -> Result<JqFilter> {
     let loader = Loader::new(jaq_std::defs().chain(jaq_json::defs()));
     let arena = Arena::default();
 
@@ -67,13 +204,21 @@ fn eval(filter_str: &str, input: Value) -> Result<Vec<Value>> {
             }
         })?;
 
-    let filter = Compiler::default()
+    Compiler::default()
         .with_funs(jaq_std::funs().chain(jaq_json::funs()))
+        .with_global_vars(names)
         .compile(modules)
-        .map_err(|errs| anyhow::anyhow!("Compile error: {:?}", errs))?;
+        .map_err(|errs| anyhow::anyhow!("Compile error: {:?}", errs))
+}
+
+fn run(filter: &JqFilter, input: Value) -> Result<Vec<Value>> {
+    run_with_vars(filter, [], input)
+}
 
+/// Run `filter` with `vars` bound, in the order their names were compiled.
+fn run_with_vars(filter: &JqFilter, vars: impl IntoIterator<Item = Value>, input: Value) -> Result<Vec<Value>> {
     let inputs = RcIter::new(core::iter::empty());
-    let ctx = Ctx::new([], &inputs);
+    let ctx = Ctx::new(vars.into_iter().map(Val::from), &inputs);
     let out = filter.run((ctx, Val::from(input)));
 
     let mut results = Vec::new();
@@ -86,3 +231,125 @@ fn eval(filter_str: &str, input: Value) -> Result<Vec<Value>> {
 
     Ok(results)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{load_input, run_query, write_results};
+    use crate::cli::QueryArgs;
+    use clap::Parser;
+    use serde_json::json;
+    use std::fs;
+    use tempfile::tempdir;
+
+    fn query(argv: &[&str]) -> bool {
+        let args = QueryArgs::parse_from([&["query"], argv].concat());
+        run_query(&args, false).unwrap()
+    }
+
+    #[test]
+    fn any_and_none_report_whether_predicate_matched() {
+        let temp = tempdir().unwrap();
+        let file = temp.path().join("a.json");
+        fs::write(&file, r#"{"status":"open","count":0}"#).unwrap();
+        let file = file.to_str().unwrap();
+
+        assert!(query(&["-f", ".status == \"open\"", "--any", file]));
+        assert!(!query(&["-f", ".status == \"closed\"", "--any", file]));
+        assert!(!query(&["-f", ".status == \"open\"", "--none", file]));
+        assert!(query(&["-f", ".missing", "--none", file]));
+        // 0 is truthy in jq
+        assert!(query(&["-f", ".count", "--any", file]));
+    }
+
+    #[test]
+    fn ndjson_inputs_give_one_value_per_line() {
+        let temp = tempdir().unwrap();
+        let file = temp.path().join("events.ndjson");
+        fs::write(&file, "{\"level\":\"info\"}\n\n{\"level\":\"error\"}\n").unwrap();
+        let file = file.to_str().unwrap();
+
+        assert_eq!(load_input(file).unwrap(), [json!({ "level": "info" }), json!({ "level": "error" })]);
+        assert!(query(&["-f", ".level == \"error\"", "--any", file]));
+        assert!(!query(&["-f", ".level == \"error\"", "--none", file]));
+    }
+
+    #[test]
+    fn any_is_true_if_any_file_matches() {
+        let temp = tempdir().unwrap();
+        let a = temp.path().join("a.json");
+        let b = temp.path().join("b.json");
+        fs::write(&a, r#"{"level":"info"}"#).unwrap();
+        fs::write(&b, r#"{"level":"error"}"#).unwrap();
+        let (a, b) = (a.to_str().unwrap(), b.to_str().unwrap());
+
+        assert!(query(&["-f", ".level == \"error\"", "--any", a, b]));
+        assert!(!query(&["-f", ".level == \"error\"", "--none", a, b]));
+        assert!(!query(&["-f", ".level == \"error\"", "--any", a]));
+    }
+
+    #[test]
+    fn arg_and_argjson_bind_variables() {
+        let temp = tempdir().unwrap();
+        let file = temp.path().join("a.json");
+        fs::write(&file, r#"{"users":[{"role":"admin","age":40},{"role":"guest","age":20}]}"#).unwrap();
+        let file = file.to_str().unwrap();
+
+        let filter = "any(.users[]; .role == $role and .age > $min)";
+        assert!(query(&["-f", filter, "--arg", "role", "admin", "--argjson", "min", "30", "--any", file]));
+        assert!(!query(&["-f", filter, "--arg", "role", "guest", "--argjson", "min", "30", "--any", file]));
+        // The last binding of a name wins.
+        assert!(query(&["-f", ".users[0].role == $r", "--arg", "r", "x", "--arg", "r", "admin", "--any", file]));
+
+        let args = QueryArgs::parse_from(["query", "-f", ".", "--argjson", "n", "{oops", file]);
+        let err = run_query(&args, false).unwrap_err();
+        assert!(format!("{:#}", err).contains("--argjson n"), "{:#}", err);
+
+        let args = QueryArgs::parse_from(["query", "-f", ".", "--argjson", "x", "1", "--arg", "$x", "a", file]);
+        let err = run_query(&args, false).unwrap_err();
+        assert!(format!("{:#}", err).contains("$x is bound by both"), "{:#}", err);
+    }
+
+    #[test]
+    fn slurp_runs_the_filter_once_over_all_inputs() {
+        let temp = tempdir().unwrap();
+        let a = temp.path().join("a.json");
+        let b = temp.path().join("b.json");
+        fs::write(&a, r#"{"n":1}"#).unwrap();
+        fs::write(&b, r#"{"n":2}"#).unwrap();
+        let (a, b) = (a.to_str().unwrap(), b.to_str().unwrap());
+
+        assert!(query(&["-f", "length == 2 and .[1].n == 2", "--slurp", "--any", a, b]));
+        // Without --slurp each object is its own input.
+        assert!(!query(&["-f", "length == 2 and .[1].n == 2", "--any", a, b]));
+        assert!(query(&["-f", "length == 1", "--slurp", "--any", a]));
+    }
+
+    #[test]
+    fn raw_output_prints_strings_unquoted() {
+        let print = |results: &[serde_json::Value], raw: bool| {
+            let mut out = Vec::new();
+            write_results(&mut out, results, raw, false).unwrap();
+            String::from_utf8(out).unwrap()
+        };
+
+        assert_eq!(print(&[json!("Ada")], true), "Ada\n");
+        assert_eq!(print(&[json!("Ada")], false), "\"Ada\"\n");
+        assert_eq!(print(&[json!("Ada"), json!({ "n": 1 }), json!(2)], true), "Ada\n{\"n\":1}\n2\n");
+        assert_eq!(print(&[json!("Ada"), json!(2)], false), "[\"Ada\",2]\n");
+    }
+
+    #[test]
+    fn exit_status_follows_last_output() {
+        let temp = tempdir().unwrap();
+        let file = temp.path().join("a.json");
+        fs::write(&file, r#"{"items":[1,null],"ok":true}"#).unwrap();
+        let file = file.to_str().unwrap();
+
+        assert!(query(&["-f", ".ok", "-e", file]));
+        assert!(!query(&["-f", ".missing", "-e", file]));
+        assert!(!query(&["-f", ".items[]", "-e", file]));
+        assert!(!query(&["-f", "empty", "-e", file]));
+        // Without -e printing always succeeds.
+        assert!(query(&["-f", ".missing", file]));
+    }
+}
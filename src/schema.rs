@@ -0,0 +1,188 @@
+//! JSON Schema validation (`jsonai validate`, `search --schema`) and field
+//! discovery from a schema (`jsonai fields --schema`).
+//!
+//! Schemas declaring a `$schema` draft are compiled for that draft; all
+//! others are read as draft 2020-12.
+
+use anyhow::{anyhow, Result};
+use jsonschema::{Draft, JSONSchema};
+use serde::Serialize;
+use serde_json::Value;
+
+/// One way an instance breaks its schema.
+#[derive(Debug, Serialize, PartialEq)]
+pub struct Violation {
+    /// JSON Pointer to the offending value in the instance
+    pub pointer: String,
+    pub message: String,
+}
+
+/// Compile `schema`, defaulting to draft 2020-12.
+pub fn compile(schema: &Value) -> Result<JSONSchema> {
+    let mut options = JSONSchema::options();
+    if schema.get("$schema").is_none() {
+        options.with_draft(Draft::Draft202012);
+    }
+    options
+        .compile(schema)
+        .map_err(|e| anyhow!("Invalid JSON Schema at {}: {}", display_pointer(&e.schema_path.to_string()), e))
+}
+
+/// Read and compile the schema in `path` (JSON or YAML).
+pub fn load(path: &str) -> Result<JSONSchema> {
+    compile(&crate::manipulate::read_json_file(path)?)
+}
+
+/// Every violation of `schema` in `instance`; empty when it is valid.
+pub fn validate(schema: &JSONSchema, instance: &Value) -> Vec<Violation> {
+    match schema.validate(instance) {
+        Ok(()) => Vec::new(),
+        Err(errors) => errors
+            .map(|e| Violation {
+                pointer: e.instance_path.to_string(),
+                message: e.to_string(),
+            })
+            .collect(),
+    }
+}
+
+/// Dotted paths of the fields `schema` declares, as `fields` lists them for
+/// an instance: object `properties` at any depth, looking through `items`,
+/// `prefixItems`, the `allOf`/`anyOf`/`oneOf` branches and local `$ref`s.
+/// Sorted, without duplicates.
+pub fn field_paths(schema: &Value) -> Vec<String> {
+    let mut paths = Vec::new();
+    collect_field_paths(schema, schema, "", &mut Vec::new(), &mut paths);
+    paths.sort();
+    paths.dedup();
+    paths
+}
+
+fn collect_field_paths<'a>(
+    root: &'a Value,
+    node: &'a Value,
+    prefix: &str,
+    refs: &mut Vec<&'a str>,
+    paths: &mut Vec<String>,
+) {
+    let Value::Object(node) = node else {
+        return;
+    };
+
+    // A `$ref` cycle would recurse forever; each ref is followed once per branch.
+    if let Some(reference) = node.get("$ref").and_then(Value::as_str) {
+        if let Some(target) = reference.strip_prefix('#').and_then(|p| root.pointer(p)) {
+            if !refs.contains(&reference) {
+                refs.push(reference);
+                collect_field_paths(root, target, prefix, refs, paths);
+                refs.pop();
+            }
+        }
+    }
+
+    if let Some(Value::Object(properties)) = node.get("properties") {
+        for (key, property) in properties {
+            let path = if prefix.is_empty() {
+                key.clone()
+            } else {
+                format!("{}.{}", prefix, key)
+            };
+            paths.push(path.clone());
+            collect_field_paths(root, property, &path, refs, paths);
+        }
+    }
+
+    for keyword in ["items", "prefixItems", "allOf", "anyOf", "oneOf"] {
+        match node.get(keyword) {
+            Some(Value::Array(schemas)) => {
+                for schema in schemas {
+                    collect_field_paths(root, schema, prefix, refs, paths);
+                }
+            }
+            Some(schema) => collect_field_paths(root, schema, prefix, refs, paths),
+            None => {}
+        }
+    }
+}
+
+/// The root pointer is empty; show it as `/` in messages.
+fn display_pointer(pointer: &str) -> &str {
+    if pointer.is_empty() {
+        "/"
+    } else {
+        pointer
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{compile, field_paths, validate};
+    use serde_json::json;
+
+    #[test]
+    fn reports_each_violation_with_its_pointer() {
+        let schema = compile(&json!({
+            "type": "object",
+            "properties": {
+                "users": {
+                    "type": "array",
+                    "items": {
+                        "type": "object",
+                        "required": ["name"],
+                        "properties": { "age": { "type": "integer", "minimum": 0 } }
+                    }
+                }
+            }
+        }))
+        .unwrap();
+
+        let doc = json!({ "users": [{ "name": "Ada", "age": 36 }, { "age": -1 }] });
+        let mut pointers: Vec<String> = validate(&schema, &doc).into_iter().map(|v| v.pointer).collect();
+        pointers.sort();
+        assert_eq!(pointers, ["/users/1", "/users/1/age"]);
+
+        assert!(validate(&schema, &json!({ "users": [] })).is_empty());
+    }
+
+    #[test]
+    fn defaults_to_draft_2020_12() {
+        // `prefixItems` only exists from draft 2020-12 on.
+        let schema = compile(&json!({ "prefixItems": [{ "type": "string" }] })).unwrap();
+        assert_eq!(validate(&schema, &json!([1])).len(), 1);
+    }
+
+    #[test]
+    fn rejects_invalid_schema() {
+        let err = compile(&json!({ "type": "nonsense" })).unwrap_err();
+        assert!(err.to_string().contains("Invalid JSON Schema"), "{}", err);
+    }
+
+    #[test]
+    fn field_paths_follow_properties_items_and_refs() {
+        let schema = json!({
+            "type": "array",
+            "items": {
+                "type": "object",
+                "properties": {
+                    "name": { "type": "string" },
+                    "address": { "$ref": "#/$defs/address" },
+                    "tags": { "type": "array", "items": { "type": "string" } },
+                    "parent": { "$ref": "#/items" }
+                }
+            },
+            "$defs": {
+                "address": {
+                    "allOf": [{ "properties": { "city": {} } }, { "properties": { "zip": {} } }]
+                }
+            }
+        });
+
+        assert_eq!(
+            field_paths(&schema),
+            [
+                "address", "address.city", "address.zip", "name", "parent", "parent.address",
+                "parent.address.city", "parent.address.zip", "parent.name", "parent.parent", "parent.tags", "tags",
+            ]
+        );
+    }
+}
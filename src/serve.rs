@@ -0,0 +1,326 @@
+use anyhow::{bail, Context, Result};
+use clap::Parser;
+use serde_json::{json, Value};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::cli::SearchArgs;
+use crate::engine::Engine;
+
+/// Largest request body accepted, to keep a stray client from exhausting memory.
+const MAX_BODY_BYTES: usize = 1 << 20;
+
+/// An input loaded and indexed once, shared by every request.
+pub struct ServerState {
+    pub engine: Engine,
+    pub input: String,
+    pub files: crate::FileCounts,
+    pub pretty: bool,
+}
+
+/// Accept connections until `POST /shutdown` is received (or `stop` is set
+/// elsewhere), handling each on its own thread. In-flight requests are
+/// finished before returning.
+///
+/// Routes:
+/// - `POST /search`: body is a JSON object of `search` flags, e.g.
+///   `{"query":"error","field":["level"],"limit":5,"no_overflow":true}`.
+///   Responds with the same envelope the CLI prints.
+/// - `GET /health`: `{"status":"ok"}`
+/// - `POST /shutdown`: stop accepting connections and exit.
+pub fn serve(listener: TcpListener, state: Arc<ServerState>, stop: Arc<AtomicBool>) -> Result<()> {
+    listener
+        .set_nonblocking(true)
+        .context("Failed to configure listener")?;
+
+    let mut workers = Vec::new();
+    while !stop.load(Ordering::Relaxed) {
+        match listener.accept() {
+            Ok((stream, _)) => {
+                let state = Arc::clone(&state);
+                let stop = Arc::clone(&stop);
+                workers.push(std::thread::spawn(move || {
+                    if let Err(e) = handle_connection(stream, &state, &stop) {
+                        eprintln!("Warning: request failed: {:#}", e);
+                    }
+                }));
+                workers.retain(|w| !w.is_finished());
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => {
+                std::thread::sleep(Duration::from_millis(20));
+            }
+            Err(e) => return Err(e).context("Failed to accept connection"),
+        }
+    }
+
+    for worker in workers {
+        let _ = worker.join();
+    }
+    Ok(())
+}
+
+fn handle_connection(stream: TcpStream, state: &ServerState, stop: &AtomicBool) -> Result<()> {
+    stream.set_nonblocking(false)?;
+    stream.set_read_timeout(Some(Duration::from_secs(30)))?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    let mut parts = request_line.split_whitespace();
+    let method = parts.next().unwrap_or_default().to_string();
+    let path = parts.next().unwrap_or_default().to_string();
+
+    let mut content_length = 0;
+    loop {
+        let mut header = String::new();
+        if reader.read_line(&mut header)? == 0 || header.trim().is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.trim().eq_ignore_ascii_case("content-length") {
+                content_length = value.trim().parse().context("Invalid Content-Length")?;
+            }
+        }
+    }
+
+    let (status, body) = if content_length > MAX_BODY_BYTES {
+        (413, error_body("Request body too large"))
+    } else {
+        let mut body = vec![0; content_length];
+        reader.read_exact(&mut body)?;
+
+        match (method.as_str(), path.as_str()) {
+            ("POST", "/search") => match handle_search(&body, state) {
+                Ok(output) => (200, output),
+                Err(e) => (400, error_body(&format!("{:#}", e))),
+            },
+            ("GET", "/health") => (200, json!({ "status": "ok" }).to_string()),
+            ("POST", "/shutdown") => {
+                stop.store(true, Ordering::Relaxed);
+                (200, json!({ "status": "shutting down" }).to_string())
+            }
+            _ => (404, error_body("Not found")),
+        }
+    };
+
+    write_response(stream, status, &body)
+}
+
+/// Turn the JSON request into `search` arguments for the served input and
+/// run it through the same pipeline as the CLI.
+fn handle_search(body: &[u8], state: &ServerState) -> Result<String> {
+    let request: Value = serde_json::from_slice(body).context("Invalid JSON request body")?;
+    let mut argv = vec!["search".to_string()];
+    argv.extend(request_to_argv(&request)?);
+    argv.push(state.input.clone());
+
+    let args = SearchArgs::try_parse_from(&argv).map_err(|e| anyhow::anyhow!("{}", e.render()))?;
+    let (output, _) = crate::execute_search(&state.engine, &args, state.files, state.pretty)?;
+    Ok(output)
+}
+
+/// `search` options a request may set. The rest either shape the index
+/// built at startup (`lang`, `range`, `field_option`, ...) or pick the input
+/// (`since`, `files_from`, `follow`, ...), so they can't change per request.
+const REQUEST_KEYS: &[&str] = &[
+    "query",
+    "field",
+    "all",
+    "match",
+    "scoring",
+    "max_terms",
+    "fuzzy_distance",
+    "fuzzy_prefix",
+    "fuzzy_and",
+    "highlight",
+    "boolean",
+    "has",
+    "missing",
+    "eq",
+    "numbers",
+    "boost",
+    "ascend",
+    "top_level",
+    "dedup",
+    "no_dedup",
+    "order_by_location",
+    "sort",
+    "output",
+    "limit",
+    "offset",
+    "cursor",
+    "count_by_file",
+    "count_only",
+    "group_by",
+    "select",
+    "select_exclude",
+    "select_regex",
+    "select_regex_deep",
+    "transform",
+    "redact",
+    "redact_hash",
+    "only_files",
+    "stream",
+    "bare",
+    "value_field",
+    "id_pointer",
+    "sort_keys",
+    "clip_record_bytes",
+    "max_bytes",
+    "threshold",
+    "max_fetch",
+    "overflow_bytes",
+    "plan",
+    "no_overflow",
+    "on_overflow",
+    "facet_by_file",
+    "facet_case",
+    "schema",
+    "explain",
+];
+
+/// Convert `{"limit":5,"no_overflow":true,"field":["a","b"]}` into
+/// `--limit 5 --no-overflow --field a --field b`.
+fn request_to_argv(request: &Value) -> Result<Vec<String>> {
+    let map = match request {
+        Value::Object(map) => map,
+        _ => bail!("Request body must be a JSON object of search options"),
+    };
+
+    let mut argv = Vec::new();
+    for (key, value) in map {
+        if !REQUEST_KEYS.contains(&key.replace('-', "_").as_str()) {
+            bail!(
+                "Option {:?} isn't supported by `serve`; it is fixed when the server starts",
+                key
+            );
+        }
+        if key == "match" && value.as_str() == Some("ngram") {
+            bail!("Match mode \"ngram\" isn't supported by `serve`, which builds no n-gram index");
+        }
+        let flag = format!("--{}", key.replace('_', "-"));
+        match value {
+            Value::Bool(true) => argv.push(flag),
+            Value::Bool(false) | Value::Null => {}
+            Value::Array(items) => {
+                for item in items {
+                    argv.push(flag.clone());
+                    argv.push(scalar_arg(item)?);
+                }
+            }
+            other => {
+                argv.push(flag);
+                argv.push(scalar_arg(other)?);
+            }
+        }
+    }
+    Ok(argv)
+}
+
+fn scalar_arg(value: &Value) -> Result<String> {
+    match value {
+        Value::String(s) => Ok(s.clone()),
+        Value::Number(n) => Ok(n.to_string()),
+        Value::Bool(b) => Ok(b.to_string()),
+        other => bail!("Unsupported option value {}", other),
+    }
+}
+
+fn error_body(message: &str) -> String {
+    json!({ "error": message }).to_string()
+}
+
+fn write_response(mut stream: TcpStream, status: u16, body: &str) -> Result<()> {
+    let reason = match status {
+        200 => "OK",
+        400 => "Bad Request",
+        404 => "Not Found",
+        413 => "Payload Too Large",
+        _ => "Error",
+    };
+    write!(
+        stream,
+        "HTTP/1.1 {} {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        reason,
+        body.len(),
+        body
+    )?;
+    stream.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{serve, ServerState};
+    use crate::engine::{extract_records, Engine, IndexOptions};
+    use serde_json::{json, Value};
+    use std::io::{Read, Write};
+    use std::net::{TcpListener, TcpStream};
+    use std::sync::atomic::AtomicBool;
+    use std::sync::Arc;
+
+    fn request(addr: std::net::SocketAddr, method: &str, path: &str, body: &str) -> (String, String) {
+        let mut stream = TcpStream::connect(addr).unwrap();
+        write!(
+            stream,
+            "{} {} HTTP/1.1\r\nHost: localhost\r\nContent-Length: {}\r\n\r\n{}",
+            method,
+            path,
+            body.len(),
+            body
+        )
+        .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        let (head, body) = response.split_once("\r\n\r\n").unwrap();
+        (head.lines().next().unwrap().to_string(), body.to_string())
+    }
+
+    #[test]
+    fn serve_answers_search_with_envelope_and_shuts_down() {
+        let engine = Engine::with_options(&IndexOptions::default()).unwrap();
+        let records = extract_records(
+            &json!([{ "name": "Ada", "role": "admin" }, { "name": "Bob", "role": "dev" }]),
+            "users.json",
+        );
+        engine.index_records(&records).unwrap();
+        let state = Arc::new(ServerState {
+            engine,
+            input: "users.json".to_string(),
+            files: crate::FileCounts::single(),
+            pretty: false,
+        });
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let stop = Arc::new(AtomicBool::new(false));
+        let server = {
+            let stop = Arc::clone(&stop);
+            std::thread::spawn(move || serve(listener, state, stop))
+        };
+
+        let (status, body) = request(addr, "POST", "/search", r#"{"query":"admin","field":["role"]}"#);
+        assert_eq!(status, "HTTP/1.1 200 OK");
+        let envelope: Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(envelope["meta"]["total"], json!(1));
+        assert_eq!(envelope["results"][0]["name"], json!("Ada"));
+
+        let (status, _) = request(addr, "POST", "/search", r#"{"limit":"many"}"#);
+        assert_eq!(status, "HTTP/1.1 400 Bad Request");
+
+        for body in [r#"{"query":"admin","since":"1h"}"#, r#"{"query":"admin","match":"ngram"}"#] {
+            let (status, body) = request(addr, "POST", "/search", body);
+            assert_eq!(status, "HTTP/1.1 400 Bad Request");
+            let error: Value = serde_json::from_str(&body).unwrap();
+            assert!(error["error"].as_str().unwrap().contains("isn't supported by `serve`"));
+        }
+
+        let (status, _) = request(addr, "POST", "/shutdown", "");
+        assert_eq!(status, "HTTP/1.1 200 OK");
+        server.join().unwrap().unwrap();
+    }
+}
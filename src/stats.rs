@@ -0,0 +1,149 @@
+//! Shape summary of a JSON document (`jsonai stats`).
+//!
+//! The top-level objects are the root itself when it is an object, or the
+//! object elements of a root array; key statistics are taken over those.
+//! Depth and array lengths cover the whole tree.
+
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::BTreeMap;
+
+use crate::engine::extract_records;
+
+/// What `jsonai stats` prints.
+#[derive(Debug, Serialize)]
+pub struct Stats {
+    /// Records search would index (see [`extract_records`])
+    pub records: usize,
+    /// Top-level objects the key statistics are taken over
+    pub objects: usize,
+    /// Distinct keys across the top-level objects
+    pub keys: usize,
+    /// Per top-level key: in how many objects it appears, and as which types
+    pub fields: BTreeMap<String, FieldStats>,
+    /// Lengths of every array in the document
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub arrays: Option<ArrayStats>,
+    /// Levels of nesting: 0 for a scalar, 1 for a container of scalars
+    pub max_depth: usize,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct FieldStats {
+    pub count: usize,
+    /// Occurrences per JSON type name (`string`, `number`, `boolean`,
+    /// `null`, `array`, `object`)
+    pub types: BTreeMap<&'static str, usize>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ArrayStats {
+    pub count: usize,
+    pub min_length: usize,
+    pub max_length: usize,
+}
+
+/// Summarize `value`, read from `file` (used only to extract records).
+pub fn summarize(value: &Value, file: &str) -> Stats {
+    let top_level: Vec<&serde_json::Map<String, Value>> = match value {
+        Value::Object(map) => vec![map],
+        Value::Array(items) => items.iter().filter_map(Value::as_object).collect(),
+        _ => Vec::new(),
+    };
+
+    let mut fields: BTreeMap<String, FieldStats> = BTreeMap::new();
+    for object in &top_level {
+        for (key, field_value) in *object {
+            let stats = fields.entry(key.clone()).or_default();
+            stats.count += 1;
+            *stats.types.entry(type_name(field_value)).or_default() += 1;
+        }
+    }
+
+    let mut lengths = Vec::new();
+    collect_array_lengths(value, &mut lengths);
+
+    Stats {
+        records: extract_records(value, file).len(),
+        objects: top_level.len(),
+        keys: fields.len(),
+        fields,
+        arrays: (!lengths.is_empty()).then(|| ArrayStats {
+            count: lengths.len(),
+            min_length: lengths.iter().copied().min().unwrap_or_default(),
+            max_length: lengths.iter().copied().max().unwrap_or_default(),
+        }),
+        max_depth: depth(value),
+    }
+}
+
+pub(crate) fn type_name(value: &Value) -> &'static str {
+    match value {
+        Value::Null => "null",
+        Value::Bool(_) => "boolean",
+        Value::Number(_) => "number",
+        Value::String(_) => "string",
+        Value::Array(_) => "array",
+        Value::Object(_) => "object",
+    }
+}
+
+fn collect_array_lengths(value: &Value, lengths: &mut Vec<usize>) {
+    match value {
+        Value::Array(items) => {
+            lengths.push(items.len());
+            for item in items {
+                collect_array_lengths(item, lengths);
+            }
+        }
+        Value::Object(map) => {
+            for child in map.values() {
+                collect_array_lengths(child, lengths);
+            }
+        }
+        _ => {}
+    }
+}
+
+fn depth(value: &Value) -> usize {
+    match value {
+        Value::Array(items) => 1 + items.iter().map(depth).max().unwrap_or(0),
+        Value::Object(map) => 1 + map.values().map(depth).max().unwrap_or(0),
+        _ => 0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::summarize;
+    use serde_json::json;
+
+    #[test]
+    fn summarizes_keys_types_arrays_and_depth() {
+        let doc = json!([
+            { "name": "Ada", "tags": ["math", "code"], "age": 36 },
+            { "name": "Grace", "tags": [], "age": null, "address": { "city": "NYC" } },
+            "not an object"
+        ]);
+        let stats = summarize(&doc, "people.json");
+
+        assert_eq!(stats.objects, 2);
+        assert_eq!(stats.keys, 4);
+        assert_eq!(stats.fields["name"].count, 2);
+        assert_eq!(stats.fields["age"].types["number"], 1);
+        assert_eq!(stats.fields["age"].types["null"], 1);
+        assert_eq!(stats.fields["address"].count, 1);
+
+        let arrays = stats.arrays.unwrap();
+        assert_eq!((arrays.count, arrays.min_length, arrays.max_length), (3, 0, 3));
+        assert_eq!(stats.max_depth, 3);
+    }
+
+    #[test]
+    fn scalar_document_has_no_keys_or_arrays() {
+        let stats = summarize(&json!(42), "n.json");
+        assert_eq!((stats.objects, stats.keys, stats.max_depth), (0, 0, 0));
+        assert!(stats.arrays.is_none());
+        assert!(serde_json::to_value(&stats).unwrap().get("arrays").is_none());
+    }
+}
@@ -0,0 +1,84 @@
+//! Indented outline of a JSON document's shape (`jsonai tree`).
+//!
+//! One line per key or array element, naming its type: objects show their
+//! key count, arrays their length, scalars just their type. Values are never
+//! printed. Only the first element of an array is expanded; the others are
+//! summed up in one line, so long arrays stay short.
+
+use serde_json::Value;
+
+use crate::stats::type_name;
+
+/// Render `value` as a tree, descending at most `max_depth` levels below the
+/// root when given. Containers past the limit still show their size.
+pub fn render(value: &Value, max_depth: Option<usize>) -> String {
+    let mut lines = vec![format!("root ({})", describe(value))];
+    render_children(value, "", 1, max_depth, &mut lines);
+    lines.join("\n")
+}
+
+fn render_children(value: &Value, indent: &str, depth: usize, max_depth: Option<usize>, lines: &mut Vec<String>) {
+    if max_depth.is_some_and(|max| depth > max) {
+        return;
+    }
+    let children: Vec<(String, &Value)> = match value {
+        Value::Object(map) => map.iter().map(|(key, child)| (key.clone(), child)).collect(),
+        Value::Array(items) => items.first().map(|first| ("[0]".to_string(), first)).into_iter().collect(),
+        _ => return,
+    };
+    let more = match value {
+        Value::Array(items) if items.len() > 1 => Some(items.len() - 1),
+        _ => None,
+    };
+
+    for (i, (label, child)) in children.iter().enumerate() {
+        let last = more.is_none() && i + 1 == children.len();
+        let (branch, continuation) = if last { ("└─ ", "   ") } else { ("├─ ", "│  ") };
+        lines.push(format!("{}{}{} ({})", indent, branch, label, describe(child)));
+        render_children(child, &format!("{}{}", indent, continuation), depth + 1, max_depth, lines);
+    }
+    if let Some(more) = more {
+        lines.push(format!("{}└─ … {} more", indent, more));
+    }
+}
+
+fn describe(value: &Value) -> String {
+    match value {
+        Value::Object(map) => format!("object{{{}}}", map.len()),
+        Value::Array(items) => format!("array[{}]", items.len()),
+        scalar => type_name(scalar).to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::render;
+    use serde_json::json;
+
+    #[test]
+    fn renders_keys_types_and_sizes_without_values() {
+        let doc = json!({
+            "users": [{ "name": "Ada", "tags": ["math"] }, { "name": "Grace" }],
+            "total": 2
+        });
+        assert_eq!(
+            render(&doc, None),
+            "root (object{2})\n\
+             ├─ total (number)\n\
+             └─ users (array[2])\n   \
+                ├─ [0] (object{2})\n   \
+                │  ├─ name (string)\n   \
+                │  └─ tags (array[1])\n   \
+                │     └─ [0] (string)\n   \
+                └─ … 1 more"
+        );
+    }
+
+    #[test]
+    fn depth_limits_recursion() {
+        let doc = json!({ "a": { "b": { "c": null } }, "d": [] });
+        assert_eq!(render(&doc, Some(1)), "root (object{2})\n├─ a (object{1})\n└─ d (array[0])");
+        assert_eq!(render(&doc, Some(0)), "root (object{2})");
+        assert_eq!(render(&json!("x"), None), "root (string)");
+    }
+}